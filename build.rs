@@ -0,0 +1,23 @@
+fn main() {
+    #[cfg(feature = "capi")]
+    capi_header();
+}
+
+///Generates the header of the C API into OUT_DIR, the readme tells where to find it
+#[cfg(feature = "capi")]
+fn capi_header() {
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-changed=src/capi");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("cannot read cbindgen.toml");
+    // Submodules of src/capi are followed, public items of the rest of the crate are not part of the C API
+    cbindgen::Builder::new()
+        .with_src(crate_dir.join("src/capi/mod.rs"))
+        .with_config(config)
+        .generate()
+        .expect("cannot generate the C header")
+        .write_to_file(out_dir.join("ndsd_playback.h"));
+}
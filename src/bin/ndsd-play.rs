@@ -0,0 +1,449 @@
+//! Plays dsf and dff files on a dsd capable device.
+//!
+//! ndsd-play --list
+//! ndsd-play [--device hw:1,0] [--profile balanced] FILE_OR_DIR...
+//!
+//! Directories are searched for dsf and dff files, which play sorted by path. Tracks follow each other
+//! without a gap when their format is the same. Device accepts the same ids as create_player, e.g
+//! "file:/tmp/capture.dsf" or "null:4" for scripting. Without --device the first dsd capable device is used.
+//!
+//! Keys: space or p pause and resume, right or f seeks 10 s forward, left or b 10 s back, n skips to the
+//! next track, q or Ctrl+C quits. Without a terminal the keys are read from stdin as they are.
+//!
+//! Exit codes: 0 played or quit, 1 device failed, 2 bad arguments, 3 a track could not be played,
+//! 4 no tracks or no device found.
+
+use ndsd_read::{DSDFormat, DSDMeta};
+use ndsdplayback::players::{
+    LatencyProfile, PlayerConfig, PlayerError, PlayerEvent, SeekDirection, SyncPlayer, device_capabilities,
+    dsd_rate_label, enumerate_supported_devices,
+};
+use ndsdplayback::utils::tracks::collect_tracks;
+use std::ffi::CString;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+const EXIT_DEVICE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_TRACK: u8 = 3;
+const EXIT_NO_INPUT: u8 = 4;
+
+const SEEK_STEP: Duration = Duration::from_secs(10);
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: ndsd-play --list");
+    eprintln!("       ndsd-play [--device ID] [--profile low_latency|balanced|high_buffer] FILE_OR_DIR...");
+    ExitCode::from(EXIT_USAGE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Pause,
+    Forward,
+    Back,
+    Next,
+    Quit,
+}
+
+///Turns terminal input into keys, arrows arrive as ESC [ C and ESC [ D
+#[derive(Default)]
+struct KeyDecoder {
+    escape: Vec<u8>,
+}
+
+impl KeyDecoder {
+    fn push(&mut self, byte: u8) -> Option<Key> {
+        if !self.escape.is_empty() || byte == 0x1b {
+            self.escape.push(byte);
+            let key = match self.escape.as_slice() {
+                [0x1b] | [0x1b, b'['] => return None,
+                [0x1b, b'[', b'C'] => Some(Key::Forward),
+                [0x1b, b'[', b'D'] => Some(Key::Back),
+                _ => None,
+            };
+            self.escape.clear();
+            return key;
+        }
+        match byte {
+            b' ' | b'p' => Some(Key::Pause),
+            b'f' => Some(Key::Forward),
+            b'b' => Some(Key::Back),
+            b'n' => Some(Key::Next),
+            b'q' | 0x03 => Some(Key::Quit),
+            _ => None,
+        }
+    }
+}
+
+///Terminal without line buffering and echo while playing, restored on drop
+#[cfg(target_os = "linux")]
+struct RawTerminal(libc::termios);
+
+#[cfg(target_os = "linux")]
+impl RawTerminal {
+    fn enable() -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        let mut term: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) } != 0 {
+            return None;
+        }
+        let saved = term;
+        // Ctrl+C arrives as a key, so the terminal is restored before quitting
+        term.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        term.c_cc[libc::VMIN] = 1;
+        term.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) } != 0 {
+            return None;
+        }
+        Some(Self(saved))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+///Keys read from stdin on a thread of its own, the thread ends with stdin
+fn spawn_keys() -> Receiver<Key> {
+    let (sender, keys) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut decoder = KeyDecoder::default();
+        for byte in std::io::stdin().lock().bytes() {
+            let Ok(byte) = byte else { break };
+            if let Some(key) = decoder.push(byte)
+                && sender.send(key).is_err()
+            {
+                break;
+            }
+        }
+    });
+    keys
+}
+
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn print_track(path: &Path, format: &DSDFormat, meta: Option<&DSDMeta>, duration: Duration) {
+    println!("{}", path.display());
+    println!(
+        "  {} ({:.4} MHz), {} channels, {}",
+        dsd_rate_label(format.sampling_rate),
+        format.sampling_rate as f64 / 1e6,
+        format.num_channels,
+        format_time(duration)
+    );
+    let Some(meta) = meta else {
+        return;
+    };
+    let tags = [
+        ("Title", meta.title.clone()),
+        ("Artist", meta.artist.clone()),
+        ("Album", meta.album.clone()),
+        ("Year", meta.year.map(|year| year.to_string())),
+        ("Genre", meta.genre.clone()),
+    ];
+    for (name, value) in tags {
+        if let Some(value) = value {
+            println!("  {:<7}: {}", name, value);
+        }
+    }
+}
+
+fn list_devices() -> ExitCode {
+    let devices = enumerate_supported_devices();
+    if devices.is_empty() {
+        eprintln!("No dsd capable device found");
+        return ExitCode::from(EXIT_NO_INPUT);
+    }
+    for device in devices {
+        println!("{}", device.id.to_string_lossy());
+        println!("  stable id: {}", device.stable_id.to_string_lossy());
+        println!("  {} {}", device.card_description, device.device_description);
+        match device_capabilities(&device.id) {
+            Ok(capabilities) => {
+                for format in &capabilities.formats {
+                    let rates: Vec<String> = format.rates.iter().map(|rate| dsd_rate_label(*rate)).collect();
+                    println!("  {}: {}", format.format, rates.join(", "));
+                }
+                match (capabilities.channels.start(), capabilities.channels.end()) {
+                    (_, &u32::MAX) => println!("  any number of channels"),
+                    (min, max) => println!("  {}-{} channels", min, max),
+                }
+            }
+            Err(e) => println!("  capabilities unknown: {}", e),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+///What is playing and what follows it, indices into the track list
+struct Playlist {
+    tracks: Vec<PathBuf>,
+    current: usize,
+    enqueued: Option<usize>,
+    failed: bool,
+}
+
+impl Playlist {
+    ///Opens the first playable track from the index on, None if there is none
+    fn load_from(&mut self, player: &mut SyncPlayer, from: usize) -> Option<usize> {
+        for index in from..self.tracks.len() {
+            match player.load_new_track(&self.tracks[index].to_string_lossy()) {
+                Ok(()) => return Some(index),
+                Err(e) => self.skip(index, &e),
+            }
+        }
+        None
+    }
+
+    ///Enqueues the first playable track after the current one
+    fn enqueue_after_current(&mut self, player: &mut SyncPlayer) {
+        self.enqueued = None;
+        for index in self.current + 1..self.tracks.len() {
+            match player.enqueue_next(&self.tracks[index].to_string_lossy()) {
+                Ok(()) => {
+                    self.enqueued = Some(index);
+                    return;
+                }
+                Err(e) => self.skip(index, &e),
+            }
+        }
+    }
+
+    fn skip(&mut self, index: usize, e: &PlayerError) {
+        eprintln!("Skipping {}: {}", self.tracks[index].display(), e);
+        self.failed = true;
+    }
+}
+
+///Position line on the terminal, cleared before anything else is printed
+struct Status {
+    shown: bool,
+    enabled: bool,
+}
+
+impl Status {
+    fn show(&mut self, position: Duration, duration: Duration, paused: bool) {
+        if !self.enabled {
+            return;
+        }
+        let state = if paused { " paused" } else { "" };
+        eprint!("\r\x1b[K  {} / {}{}", format_time(position), format_time(duration), state);
+        let _ = std::io::stderr().flush();
+        self.shown = true;
+    }
+
+    fn clear(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[K");
+            self.shown = false;
+        }
+    }
+}
+
+fn exit_code(e: &PlayerError) -> u8 {
+    match e {
+        PlayerError::TrackOpen { .. } | PlayerError::Read { .. } => EXIT_TRACK,
+        _ => EXIT_DEVICE,
+    }
+}
+
+fn play(device: CString, config: PlayerConfig, tracks: Vec<PathBuf>) -> ExitCode {
+    let mut player = match SyncPlayer::create(device.clone(), config) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Failed to open {:?}: {}", device, e);
+            return ExitCode::from(EXIT_DEVICE);
+        }
+    };
+    let mut playlist = Playlist {
+        tracks,
+        current: 0,
+        enqueued: None,
+        failed: false,
+    };
+    let Some(first) = playlist.load_from(&mut player, 0) else {
+        eprintln!("None of the tracks could be opened");
+        return ExitCode::from(EXIT_TRACK);
+    };
+    playlist.current = first;
+    // Subscribed after the first load, so every TrackLoaded received is a switch to the enqueued track
+    let events = player.subscribe();
+    let mut duration = player.duration().time;
+    print_track(
+        &playlist.tracks[first],
+        &player.get_format_info(),
+        player.get_current_file_meta().as_ref(),
+        duration,
+    );
+    if let Err(e) = player.start() {
+        eprintln!("Failed to start playback on {:?}: {}", device, e);
+        return ExitCode::from(exit_code(&e));
+    }
+    playlist.enqueue_after_current(&mut player);
+
+    #[cfg(target_os = "linux")]
+    let _terminal = RawTerminal::enable();
+    let keys = spawn_keys();
+    let mut status = Status {
+        shown: false,
+        enabled: std::io::stderr().is_terminal(),
+    };
+    let mut paused = false;
+    let mut code = ExitCode::SUCCESS;
+    loop {
+        while let Ok(key) = keys.try_recv() {
+            let result = match key {
+                Key::Pause if paused => player.play().map(|_| paused = false),
+                Key::Pause => player.pause().map(|_| paused = true),
+                Key::Forward => player.seek_by(SEEK_STEP, SeekDirection::Forward).map(|_| ()),
+                Key::Back => player.seek_by(SEEK_STEP, SeekDirection::Backward).map(|_| ()),
+                // Playing into the end of the track moves on to the enqueued one without a gap
+                Key::Next if playlist.enqueued.is_some() => {
+                    player.seek(1.).and_then(|_| if paused { player.play() } else { Ok(()) }).map(|_| paused = false)
+                }
+                Key::Next | Key::Quit => {
+                    let _ = player.stop();
+                    status.clear();
+                    return finish(player, &playlist, code);
+                }
+            };
+            if let Err(e) = result {
+                status.clear();
+                eprintln!("{}", e);
+            }
+            status.show(player.position().time, duration, paused);
+        }
+        let event = match events.recv_timeout(Duration::from_millis(50)) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        match event {
+            PlayerEvent::PositionTick { .. } => status.show(player.position().time, duration, paused),
+            PlayerEvent::TrackLoaded { format, meta } => {
+                let Some(next) = playlist.enqueued else {
+                    continue;
+                };
+                playlist.current = next;
+                duration = player.duration().time;
+                status.clear();
+                print_track(&playlist.tracks[next], &format, meta.as_ref(), duration);
+                playlist.enqueue_after_current(&mut player);
+            }
+            PlayerEvent::TrackEnded if playlist.enqueued.is_none() => break,
+            PlayerEvent::Xrun(xrun) => {
+                status.clear();
+                eprintln!("Output {:?} at {}", xrun.kind, format_time(xrun.position.time));
+            }
+            PlayerEvent::DeviceLost(e) => {
+                status.clear();
+                eprintln!("Device lost, reconnecting: {}", e);
+            }
+            PlayerEvent::DeviceReconnected => {
+                status.clear();
+                eprintln!("Device reconnected");
+            }
+            PlayerEvent::DeviceError(e) => {
+                status.clear();
+                eprintln!("Playback failed: {}", e);
+                code = ExitCode::from(exit_code(&e));
+                break;
+            }
+            _ => {}
+        }
+    }
+    status.clear();
+    finish(player, &playlist, code)
+}
+
+fn finish(mut player: SyncPlayer, playlist: &Playlist, code: ExitCode) -> ExitCode {
+    if let Err(e) = player.close() {
+        eprintln!("Failed to close the player: {}", e);
+    }
+    if code == ExitCode::SUCCESS && playlist.failed {
+        return ExitCode::from(EXIT_TRACK);
+    }
+    code
+}
+
+fn main() -> ExitCode {
+    let mut device = None;
+    let mut config = PlayerConfig::default();
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--list" => return list_devices(),
+            "--device" => match args.next() {
+                Some(value) => device = Some(value),
+                None => return usage(),
+            },
+            "--profile" => {
+                let profile = match args.next().as_deref() {
+                    Some("low_latency") => LatencyProfile::LowLatency,
+                    Some("balanced") => LatencyProfile::Balanced,
+                    Some("high_buffer") => LatencyProfile::HighBuffer,
+                    _ => return usage(),
+                };
+                config = config.profile(profile);
+            }
+            "--help" | "-h" => return usage(),
+            _ if arg.starts_with("--") => return usage(),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return usage();
+    }
+
+    let mut tracks = Vec::new();
+    for path in &paths {
+        if let Err(e) = collect_tracks(path, &mut tracks) {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    }
+    if tracks.is_empty() {
+        eprintln!("No dsf or dff files found");
+        return ExitCode::from(EXIT_NO_INPUT);
+    }
+
+    let device = match device {
+        Some(device) => match CString::new(device) {
+            Ok(device) => device,
+            Err(_) => return usage(),
+        },
+        None => match enumerate_supported_devices().into_iter().next() {
+            Some(info) => info.id,
+            None => {
+                eprintln!("No dsd capable device found, pass one with --device");
+                return ExitCode::from(EXIT_NO_INPUT);
+            }
+        },
+    };
+    play(device, config, tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_letters_and_arrows() {
+        let mut decoder = KeyDecoder::default();
+        let keys: Vec<Key> = b"p\x1b[C\x1b[Dx\x1b[Aqn".iter().filter_map(|byte| decoder.push(*byte)).collect();
+        assert_eq!(keys, [Key::Pause, Key::Forward, Key::Back, Key::Quit, Key::Next]);
+    }
+}
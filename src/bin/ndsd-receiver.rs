@@ -0,0 +1,82 @@
+//! Receives a dsd stream from a player using the "tcp:<host>:<port>" device and plays it on a local device.
+//!
+//! ndsd-receiver [--listen 0.0.0.0:7171] [--device hw:1,0] [--prebuffer 200]
+//!
+//! Device accepts the same ids as create_player, e.g "file:/tmp/capture.dsf" or "null".
+//! Without --device the first dsd capable device is used.
+
+use ndsdplayback::players::net::{serve_connection, DEFAULT_PORT};
+use ndsdplayback::players::{create_sink, enumerate_supported_devices, PlayerConfig};
+use std::ffi::CString;
+use std::net::TcpListener;
+use std::process::ExitCode;
+use std::time::Duration;
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: ndsd-receiver [--listen ADDR] [--device ID] [--prebuffer MS]");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let mut listen = format!("0.0.0.0:{}", DEFAULT_PORT);
+    let mut device = None;
+    let mut prebuffer = Duration::from_millis(200);
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--listen", Some(value)) => listen = value,
+            ("--device", Some(value)) => device = Some(value),
+            ("--prebuffer", Some(value)) => match value.parse::<u64>() {
+                Ok(ms) => prebuffer = Duration::from_millis(ms),
+                Err(_) => return usage(),
+            },
+            _ => return usage(),
+        }
+    }
+
+    let device = match device {
+        Some(device) => match CString::new(device) {
+            Ok(device) => device,
+            Err(_) => return usage(),
+        },
+        None => match enumerate_supported_devices().into_iter().next() {
+            Some(info) => info.id,
+            None => {
+                eprintln!("No dsd capable device found, pass one with --device");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let listener = match TcpListener::bind(&listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", listen, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("Listening on {}, playing to {:?}", listen, device);
+
+    // One player at a time, the device is reopened for every connection
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        let Some(mut sink) = create_sink(&device, &PlayerConfig::default()) else {
+            eprintln!("Device {:?} can not be driven by the receiver", device);
+            return ExitCode::FAILURE;
+        };
+        eprintln!("Player connected from {}", peer);
+        match serve_connection(stream, sink.as_mut(), prebuffer) {
+            Ok(()) => eprintln!("Player {} disconnected", peer),
+            Err(e) => eprintln!("Stream from {} failed: {}", peer, e),
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,339 @@
+use crate::ui::format_time;
+use ndsd_read::{DSDFormat, DSDMeta};
+use ndsdplayback::players::{
+    DeviceInfo, PlayerConfig, PlayerError, PlayerEvent, SeekDirection, SyncPlayer, enumerate_supported_devices,
+};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+const SEEK_STEP: Duration = Duration::from_secs(10);
+
+///Player with the receiver of its events, subscribed after the last explicit load
+struct Output {
+    player: SyncPlayer,
+    events: Receiver<PlayerEvent>,
+}
+
+///State of the front-end, the player is driven from the ui thread and answers within milliseconds
+pub struct App {
+    output: Option<Output>,
+    config: PlayerConfig,
+    pub device: CString,
+    pub tracks: Vec<PathBuf>,
+    ///Tracks which could not be opened
+    pub failed: Vec<bool>,
+    pub queue: ListState,
+    pub current: Option<usize>,
+    enqueued: Option<usize>,
+    pub format: DSDFormat,
+    pub meta: Option<DSDMeta>,
+    pub position: Duration,
+    pub duration: Duration,
+    pub playing: bool,
+    pub paused: bool,
+    pub devices: Vec<DeviceInfo>,
+    pub device_list: ListState,
+    pub show_devices: bool,
+    ///Last error or notice, shown above the key help
+    pub message: Option<String>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(device: CString, config: PlayerConfig, tracks: Vec<PathBuf>) -> Self {
+        let mut app = Self {
+            output: None,
+            config,
+            device,
+            failed: vec![false; tracks.len()],
+            tracks,
+            queue: ListState::default().with_selected(Some(0)),
+            current: None,
+            enqueued: None,
+            format: DSDFormat::default(),
+            meta: None,
+            position: Duration::ZERO,
+            duration: Duration::ZERO,
+            playing: false,
+            paused: false,
+            devices: Vec::new(),
+            device_list: ListState::default(),
+            show_devices: false,
+            message: None,
+            quit: false,
+        };
+        app.open_output();
+        app
+    }
+
+    fn open_output(&mut self) {
+        self.output = match SyncPlayer::create(self.device.clone(), self.config.clone()) {
+            Ok(player) => Some(Output {
+                events: player.subscribe(),
+                player,
+            }),
+            Err(e) => {
+                self.message = Some(format!("Failed to open {}: {}", self.device.to_string_lossy(), e));
+                None
+            }
+        };
+        self.enqueued = None;
+    }
+
+    fn close_output(&mut self) {
+        if let Some(mut output) = self.output.take() {
+            // The device is released either way, a failed close has nothing left to stop
+            let _ = output.player.close();
+        }
+        self.playing = false;
+    }
+
+    pub fn close(&mut self) {
+        self.close_output();
+    }
+
+    fn fail(&mut self, e: PlayerError) {
+        self.message = Some(e.to_string());
+    }
+
+    ///Plays the track, or the first playable one after it, from the position
+    fn play(&mut self, from: usize, position: Duration) {
+        if self.output.is_none() {
+            self.message = Some("No output device, choose one with d".to_string());
+            return;
+        }
+        let stale = self.enqueued.is_some();
+        let Some(index) = self.load_from(from) else {
+            self.playing = false;
+            return;
+        };
+        self.enqueued = None;
+        if !self.enqueue_after(index) && stale {
+            // Enqueued track outlives load_new_track, with nothing to put in its place the player is reopened
+            self.close_output();
+            self.open_output();
+            if self.load_from(index).is_none() {
+                return;
+            }
+        }
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        match output.player.start_at(position) {
+            Ok(_) => {
+                self.playing = true;
+                self.paused = false;
+            }
+            Err(e) => {
+                self.playing = false;
+                self.fail(e);
+            }
+        }
+    }
+
+    ///Loads the first playable track from the index on and makes it current
+    fn load_from(&mut self, from: usize) -> Option<usize> {
+        let output = self.output.as_mut()?;
+        for index in from..self.tracks.len() {
+            match output.player.load_new_track(&self.tracks[index].to_string_lossy()) {
+                Ok(()) => {
+                    // Events of the tracks before are of no interest anymore
+                    output.events = output.player.subscribe();
+                    self.current = Some(index);
+                    self.queue.select(Some(index));
+                    self.format = output.player.get_format_info();
+                    self.meta = output.player.get_current_file_meta();
+                    self.duration = output.player.duration().time;
+                    self.position = Duration::ZERO;
+                    return Some(index);
+                }
+                Err(e) => {
+                    self.failed[index] = true;
+                    self.message = Some(e.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    ///Enqueues the first playable track after the index, false if there is none
+    fn enqueue_after(&mut self, index: usize) -> bool {
+        let Some(output) = self.output.as_mut() else {
+            return false;
+        };
+        for next in index + 1..self.tracks.len() {
+            match output.player.enqueue_next(&self.tracks[next].to_string_lossy()) {
+                Ok(()) => {
+                    self.enqueued = Some(next);
+                    return true;
+                }
+                Err(e) => {
+                    self.failed[next] = true;
+                    self.message = Some(e.to_string());
+                }
+            }
+        }
+        false
+    }
+
+    fn toggle_pause(&mut self) {
+        if !self.playing {
+            let selected = self.queue.selected().unwrap_or(0);
+            return self.play(selected, Duration::ZERO);
+        }
+        let Some(output) = self.output.as_ref() else {
+            return;
+        };
+        let result = if self.paused {
+            output.player.play()
+        } else {
+            output.player.pause()
+        };
+        match result {
+            Ok(()) => self.paused = !self.paused,
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn seek(&mut self, direction: SeekDirection) {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        if !self.playing {
+            return;
+        }
+        match output.player.seek_by(SEEK_STEP, direction) {
+            Ok(time) => self.position = time.time,
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn skip(&mut self, forward: bool) {
+        let Some(current) = self.current else {
+            return;
+        };
+        match forward {
+            true if current + 1 < self.tracks.len() => self.play(current + 1, Duration::ZERO),
+            false => self.play(current.saturating_sub(1), Duration::ZERO),
+            true => {}
+        }
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        let (state, len) = if self.show_devices {
+            (&mut self.device_list, self.devices.len())
+        } else {
+            (&mut self.queue, self.tracks.len())
+        };
+        if len == 0 {
+            return;
+        }
+        let selected = state.selected().unwrap_or(0);
+        state.select(Some(if down { (selected + 1).min(len - 1) } else { selected.saturating_sub(1) }));
+    }
+
+    fn open_devices(&mut self) {
+        self.devices = enumerate_supported_devices();
+        let selected = self.devices.iter().position(|d| d.id == self.device || d.stable_id == self.device);
+        self.device_list.select(selected.or(Some(0)));
+        self.show_devices = true;
+    }
+
+    ///Reopens the output on the chosen device, the track goes on where it was
+    fn switch_device(&mut self) {
+        self.show_devices = false;
+        let Some(device) = self.device_list.selected().and_then(|i| self.devices.get(i)) else {
+            return;
+        };
+        // Stable id finds the device again if it is re-plugged later
+        self.device = device.stable_id.clone();
+        let resume = self.playing.then_some((self.current, self.position, self.paused));
+        self.close_output();
+        self.open_output();
+        if let Some((Some(current), position, paused)) = resume {
+            self.play(current, position);
+            if paused {
+                self.toggle_pause();
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+        match key.code {
+            _ if ctrl_c => self.quit = true,
+            KeyCode::Esc if self.show_devices => self.show_devices = false,
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(false),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(true),
+            KeyCode::Enter if self.show_devices => self.switch_device(),
+            KeyCode::Enter => {
+                if let Some(selected) = self.queue.selected() {
+                    self.play(selected, Duration::ZERO);
+                }
+            }
+            KeyCode::Char('d') if self.show_devices => self.show_devices = false,
+            KeyCode::Char('d') => self.open_devices(),
+            KeyCode::Char(' ') => self.toggle_pause(),
+            KeyCode::Right | KeyCode::Char('f') => self.seek(SeekDirection::Forward),
+            KeyCode::Left | KeyCode::Char('b') => self.seek(SeekDirection::Backward),
+            KeyCode::Char('n') => self.skip(true),
+            KeyCode::Char('p') => self.skip(false),
+            _ => {}
+        }
+    }
+
+    ///Applies the player events and samples the position, called before every redraw
+    pub fn update(&mut self) {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        let events: Vec<PlayerEvent> = output.events.try_iter().collect();
+        for event in events {
+            self.handle_event(event);
+        }
+        if let Some(output) = self.output.as_ref()
+            && self.playing
+        {
+            self.position = output.player.position().time;
+        }
+    }
+
+    fn handle_event(&mut self, event: PlayerEvent) {
+        match event {
+            PlayerEvent::TrackLoaded { format, meta } => {
+                let Some(next) = self.enqueued.take() else {
+                    return;
+                };
+                self.current = Some(next);
+                self.queue.select(Some(next));
+                self.format = format;
+                self.meta = meta;
+                if let Some(output) = self.output.as_ref() {
+                    self.duration = output.player.duration().time;
+                }
+                self.enqueue_after(next);
+            }
+            PlayerEvent::TrackEnded if self.enqueued.is_none() => {
+                self.playing = false;
+                self.position = self.duration;
+            }
+            PlayerEvent::Paused => self.paused = true,
+            PlayerEvent::Resumed => self.paused = false,
+            PlayerEvent::Xrun(xrun) => {
+                self.message = Some(format!("Output {:?} at {}", xrun.kind, format_time(xrun.position.time)));
+            }
+            PlayerEvent::DeviceLost(e) => self.message = Some(format!("Device lost, reconnecting: {}", e)),
+            PlayerEvent::DeviceReconnected => self.message = Some("Device reconnected".to_string()),
+            PlayerEvent::DeviceError(e) => {
+                self.playing = false;
+                self.fail(e);
+            }
+            _ => {}
+        }
+    }
+}
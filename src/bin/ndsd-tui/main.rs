@@ -0,0 +1,115 @@
+//! Terminal front-end for playing dsf and dff files, usable over ssh. Built with the tui feature.
+//!
+//! ndsd-tui [--device hw:1,0] [--profile balanced] FILE_OR_DIR...
+//!
+//! Shows the tags and dsd rate of the current track, its progress and the queue. The output device can be
+//! changed while playing, the track goes on where it was. Without --device the first dsd capable device is used.
+//! Exit codes are the ones of ndsd-play: 0 quit, 2 bad arguments, 4 no tracks or no device found.
+
+mod app;
+mod ui;
+
+use app::App;
+use ndsdplayback::players::{LatencyProfile, PlayerConfig, enumerate_supported_devices};
+use ndsdplayback::utils::tracks::collect_tracks;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const EXIT_USAGE: u8 = 2;
+const EXIT_NO_INPUT: u8 = 4;
+
+// Redraw interval, the progress bar moves at this rate
+const FRAME: Duration = Duration::from_millis(100);
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: ndsd-tui [--device ID] [--profile low_latency|balanced|high_buffer] FILE_OR_DIR...");
+    ExitCode::from(EXIT_USAGE)
+}
+
+fn run(app: &mut App) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = (|| {
+        while !app.quit {
+            app.update();
+            terminal.draw(|frame| ui::draw(frame, app))?;
+            if event::poll(FRAME)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                app.handle_key(key);
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
+
+fn main() -> ExitCode {
+    let mut device = None;
+    let mut config = PlayerConfig::default();
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--device" => match args.next() {
+                Some(value) => device = Some(value),
+                None => return usage(),
+            },
+            "--profile" => {
+                let profile = match args.next().as_deref() {
+                    Some("low_latency") => LatencyProfile::LowLatency,
+                    Some("balanced") => LatencyProfile::Balanced,
+                    Some("high_buffer") => LatencyProfile::HighBuffer,
+                    _ => return usage(),
+                };
+                config = config.profile(profile);
+            }
+            "--help" | "-h" => return usage(),
+            _ if arg.starts_with("--") => return usage(),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return usage();
+    }
+
+    let mut tracks = Vec::new();
+    for path in &paths {
+        if let Err(e) = collect_tracks(path, &mut tracks) {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    }
+    if tracks.is_empty() {
+        eprintln!("No dsf or dff files found");
+        return ExitCode::from(EXIT_NO_INPUT);
+    }
+
+    let device = match device {
+        Some(device) => match CString::new(device) {
+            Ok(device) => device,
+            Err(_) => return usage(),
+        },
+        None => match enumerate_supported_devices().into_iter().next() {
+            Some(info) => info.stable_id,
+            None => {
+                eprintln!("No dsd capable device found, pass one with --device");
+                return ExitCode::from(EXIT_NO_INPUT);
+            }
+        },
+    };
+
+    let mut app = App::new(device, config, tracks);
+    let result = run(&mut app);
+    app.close();
+    if let Err(e) = result {
+        eprintln!("Terminal failed: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,184 @@
+use crate::app::App;
+use ndsdplayback::players::dsd_rate_label;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Clear, Gauge, List, ListItem, Paragraph};
+use std::time::Duration;
+
+const HELP: &str = "space play/pause  ←/→ seek 10s  n/p next/previous  ↑/↓ select  enter play  d devices  q quit";
+
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [track, progress, queue, footer] = Layout::vertical([
+        Constraint::Length(8),
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+    draw_track(frame, app, track);
+    draw_progress(frame, app, progress);
+    draw_queue(frame, app, queue);
+    draw_footer(frame, app, footer);
+    if app.show_devices {
+        draw_devices(frame, app);
+    }
+}
+
+fn draw_track(frame: &mut Frame, app: &App, area: Rect) {
+    let title = format!(" ndsd-tui on {} ", app.device.to_string_lossy());
+    let block = Block::bordered().title(title);
+    let Some(current) = app.current else {
+        frame.render_widget(Paragraph::new("Nothing played yet, press space or enter").block(block), area);
+        return;
+    };
+    let file_name = app.tracks[current].file_name().unwrap_or_default().to_string_lossy();
+    let meta = app.meta.as_ref();
+    let tag = |name: &'static str, value: Option<String>| {
+        Line::from(vec![Span::raw(format!("{:<8}", name)).dim(), Span::raw(value.unwrap_or_default())])
+    };
+    let format = &app.format;
+    let lines = vec![
+        Line::from(meta.and_then(|m| m.title.clone()).unwrap_or_else(|| file_name.to_string())).bold(),
+        tag("Artist", meta.and_then(|m| m.artist.clone())),
+        tag("Album", meta.and_then(|m| m.album.clone())),
+        tag("Year", meta.and_then(|m| m.year).map(|year| year.to_string())),
+        tag("Genre", meta.and_then(|m| m.genre.clone())),
+        Line::from(vec![
+            Span::raw(dsd_rate_label(format.sampling_rate)).fg(Color::Cyan).bold(),
+            Span::raw(format!(
+                "  {:.4} MHz  {} channels  {}",
+                format.sampling_rate as f64 / 1e6,
+                format.num_channels,
+                if format.is_lsb_first { "lsb first" } else { "msb first" }
+            )),
+        ]),
+    ];
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn draw_progress(frame: &mut Frame, app: &App, area: Rect) {
+    let state = match (app.playing, app.paused) {
+        (true, false) => "▶",
+        (true, true) => "⏸",
+        (false, _) => "■",
+    };
+    let ratio = match app.duration.is_zero() {
+        true => 0.,
+        false => (app.position.as_secs_f64() / app.duration.as_secs_f64()).clamp(0., 1.),
+    };
+    let label = format!("{} {} / {}", state, format_time(app.position), format_time(app.duration));
+    let gauge = Gauge::default()
+        .block(Block::bordered())
+        .gauge_style(Style::new().fg(Color::Cyan).bg(Color::Black))
+        .ratio(ratio)
+        .label(label);
+    frame.render_widget(gauge, area);
+}
+
+fn draw_queue(frame: &mut Frame, app: &mut App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let marker = if app.current == Some(index) { "▶ " } else { "  " };
+            let item = ListItem::new(format!("{}{}", marker, name));
+            match app.failed[index] {
+                true => item.fg(Color::Red),
+                false if app.current == Some(index) => item.bold(),
+                false => item,
+            }
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::bordered().title(format!(" Queue ({}) ", app.tracks.len())))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut app.queue);
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let message = Line::from(app.message.clone().unwrap_or_default()).fg(Color::Yellow);
+    frame.render_widget(Paragraph::new(vec![message, Line::from(HELP).dim()]), area);
+}
+
+fn draw_devices(frame: &mut Frame, app: &mut App) {
+    let [area] = Layout::horizontal([Constraint::Percentage(70)]).flex(Flex::Center).areas(frame.area());
+    let height = (app.devices.len() as u16 * 2 + 2).max(3);
+    let [area] = Layout::vertical([Constraint::Length(height)]).flex(Flex::Center).areas(area);
+    let block = Block::bordered().title(" Output device, enter selects, esc closes ");
+    frame.render_widget(Clear, area);
+    if app.devices.is_empty() {
+        frame.render_widget(Paragraph::new("No dsd capable device found").block(block), area);
+        return;
+    }
+    let items: Vec<ListItem> = app
+        .devices
+        .iter()
+        .map(|device| {
+            let mode = if device.dop { "DoP" } else { "native" };
+            ListItem::new(vec![
+                Line::from(format!("{} ({})", device.card_description, mode)),
+                Line::from(format!("  {}", device.id.to_string_lossy())).dim(),
+            ])
+        })
+        .collect();
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut app.device_list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndsd_read::DSDFormat;
+    use ndsdplayback::players::{AudioSink, PlayerConfig};
+    use ndsdplayback::players::file::FileSink;
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use std::path::PathBuf;
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    ///Writes a stereo dsf of silence, the library's own fixture is not visible to the binary
+    fn write_track(name: &str, sampling_rate: u32, bytes: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let format = DSDFormat { sampling_rate, num_channels: 2, total_samples: 0, is_lsb_first: true };
+        let mut sink = FileSink::new(&path);
+        sink.open(&format).unwrap();
+        sink.write(&[&vec![0x69; bytes], &vec![0x69; bytes]], bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn shows_the_playing_track() {
+        let path = write_track("ndsd_tui_track.dsf", 5644800, 70560);
+
+        let mut app = App::new(c"null".into(), PlayerConfig::default(), vec![path.clone()]);
+        app.handle_key(KeyEvent::from(KeyCode::Char(' ')));
+        app.update();
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| draw(frame, &mut app)).unwrap();
+        let text = screen(&terminal);
+        assert!(text.contains("DSD128"), "{}", text);
+        assert!(text.contains("▶ ndsd_tui_track.dsf"), "{}", text);
+        assert!(text.contains("0:00 / 0:00"), "{}", text);
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('d')));
+        terminal.draw(|frame| draw(frame, &mut app)).unwrap();
+        assert!(screen(&terminal).contains("Output device"));
+        app.close();
+        let _ = std::fs::remove_file(path);
+    }
+}
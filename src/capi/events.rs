@@ -0,0 +1,163 @@
+use super::{NdsdError, NdsdFormat};
+use crate::players::{PlayerError, PlayerEvent, XrunKind};
+use std::ffi::{CString, c_char, c_void};
+use std::ptr::null;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How often the event thread checks whether the callback was replaced
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdEventKind {
+    TrackLoaded,
+    Started,
+    Paused,
+    Resumed,
+    Stopped,
+    Seeked,
+    PositionTick,
+    TrackEnded,
+    FormatChanged,
+    Xrun,
+    DeviceLost,
+    DeviceReconnected,
+    DeviceError,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdXrunKind {
+    Underrun,
+    Suspend,
+}
+
+///PlayerEvent for C, fields which do not belong to the kind are zero
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NdsdEvent {
+    pub kind: NdsdEventKind,
+    ///Percent (0..1) for Seeked and PositionTick
+    pub position: f64,
+    ///Track format for TrackLoaded and FormatChanged
+    pub format: NdsdFormat,
+    pub xrun: NdsdXrunKind,
+    ///Code for DeviceLost and DeviceError, NDSD_ERROR_OK otherwise
+    pub error: NdsdError,
+    ///Text of the error, null without one. Valid only during the callback
+    pub message: *const c_char,
+}
+
+///Null when no callback is set
+pub type NdsdEventCallback = Option<extern "C" fn(event: *const NdsdEvent, user_data: *mut c_void)>;
+
+// The caller promises the user data can be used from the event thread
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+///Thread calling the C callback for every event until it is dropped
+pub(super) struct EventThread {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EventThread {
+    pub fn spawn(
+        events: Receiver<PlayerEvent>,
+        callback: extern "C" fn(*const NdsdEvent, *mut c_void),
+        user_data: *mut c_void,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let user_data = UserData(user_data);
+        let thread = std::thread::spawn(move || {
+            let user_data = user_data;
+            while !stopped.load(Relaxed) {
+                match events.recv_timeout(STOP_POLL) {
+                    Ok(event) => {
+                        let (event, message) = convert(&event);
+                        let event = NdsdEvent {
+                            message: message.as_ref().map_or(null(), |message| message.as_ptr()),
+                            ..event
+                        };
+                        callback(&event, user_data.0);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for EventThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+///C event and the text its message points to
+fn convert(event: &PlayerEvent) -> (NdsdEvent, Option<CString>) {
+    let mut out = NdsdEvent {
+        kind: NdsdEventKind::Started,
+        position: 0.,
+        format: NdsdFormat::default(),
+        xrun: NdsdXrunKind::Underrun,
+        error: NdsdError::Ok,
+        message: null(),
+    };
+    let mut message = None;
+    let error = |e: &PlayerError| (NdsdError::from(e), CString::new(e.to_string().replace('\0', " ")).ok());
+    out.kind = match event {
+        PlayerEvent::TrackLoaded { format, .. } => {
+            out.format = (*format).into();
+            NdsdEventKind::TrackLoaded
+        }
+        PlayerEvent::Started => NdsdEventKind::Started,
+        PlayerEvent::Paused => NdsdEventKind::Paused,
+        PlayerEvent::Resumed => NdsdEventKind::Resumed,
+        PlayerEvent::Stopped => NdsdEventKind::Stopped,
+        PlayerEvent::Seeked { position } => {
+            out.position = *position;
+            NdsdEventKind::Seeked
+        }
+        PlayerEvent::PositionTick { position } => {
+            out.position = *position;
+            NdsdEventKind::PositionTick
+        }
+        PlayerEvent::TrackEnded => NdsdEventKind::TrackEnded,
+        PlayerEvent::FormatChanged(format) => {
+            out.format = (*format).into();
+            NdsdEventKind::FormatChanged
+        }
+        PlayerEvent::Xrun(xrun) => {
+            out.xrun = match xrun.kind {
+                XrunKind::Underrun => NdsdXrunKind::Underrun,
+                XrunKind::Suspend => NdsdXrunKind::Suspend,
+            };
+            NdsdEventKind::Xrun
+        }
+        PlayerEvent::DeviceLost(e) => {
+            (out.error, message) = error(e);
+            NdsdEventKind::DeviceLost
+        }
+        PlayerEvent::DeviceError(e) => {
+            (out.error, message) = error(e);
+            NdsdEventKind::DeviceError
+        }
+        PlayerEvent::DeviceReconnected => NdsdEventKind::DeviceReconnected,
+    };
+    (out, message)
+}
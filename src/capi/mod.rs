@@ -0,0 +1,606 @@
+//! C API over enumerate_supported_devices, create_player and the player operations, built with the capi feature.
+//!
+//! Every call blocks until the player answered, like SyncPlayer. Functions returning NdsdError put the text of
+//! a failure into ndsd_last_error_message of the calling thread. Pointers must be valid for the duration of the
+//! call, strings are nul terminated utf-8, handles are released only with their free function.
+//! The header is generated from this module by build.rs, see the C API section of the readme
+#![allow(clippy::missing_safety_doc)]
+
+mod events;
+
+use crate::players::{
+    ClosePolicy, DeviceInfo, LatencyProfile, PlaybackTime, PlayerConfig, PlayerError, ReconnectPolicy, SeekDirection,
+    SyncPlayer, enumerate_supported_devices,
+};
+use events::EventThread;
+pub use events::{NdsdEvent, NdsdEventCallback, NdsdEventKind, NdsdXrunKind};
+use ndsd_read::DSDFormat;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr::null;
+use std::time::Duration;
+
+///Config value which keeps the library default
+pub const NDSD_DEFAULT: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdError {
+    Ok = 0,
+    ///Null handle or pointer, string which is not utf-8 or value out of range
+    InvalidArgument,
+    DeviceOpen,
+    UnsupportedFormat,
+    UnsupportedRate,
+    UnsupportedChannels,
+    DeviceSetup,
+    Xrun,
+    DeviceLost,
+    Device,
+    TrackOpen,
+    Read,
+    Io,
+    InvalidConfig,
+    NoTrack,
+    Terminated,
+    ///Bug in the library, the player should be freed
+    Panic,
+}
+
+impl From<&PlayerError> for NdsdError {
+    fn from(e: &PlayerError) -> Self {
+        match e {
+            PlayerError::DeviceOpen { .. } => NdsdError::DeviceOpen,
+            PlayerError::UnsupportedFormat { .. } => NdsdError::UnsupportedFormat,
+            PlayerError::UnsupportedRate { .. } => NdsdError::UnsupportedRate,
+            PlayerError::UnsupportedChannels { .. } => NdsdError::UnsupportedChannels,
+            PlayerError::DeviceSetup { .. } => NdsdError::DeviceSetup,
+            PlayerError::Xrun { .. } => NdsdError::Xrun,
+            PlayerError::DeviceLost { .. } => NdsdError::DeviceLost,
+            PlayerError::Device { .. } => NdsdError::Device,
+            PlayerError::TrackOpen { .. } => NdsdError::TrackOpen,
+            PlayerError::Read { .. } => NdsdError::Read,
+            PlayerError::Io { .. } => NdsdError::Io,
+            PlayerError::InvalidConfig { .. } => NdsdError::InvalidConfig,
+            PlayerError::NoTrack => NdsdError::NoTrack,
+            PlayerError::Terminated => NdsdError::Terminated,
+        }
+    }
+}
+
+///Failure of a C call, invalid arguments never reach the player
+enum Failure {
+    Player(PlayerError),
+    InvalidArgument(&'static str),
+}
+
+impl From<PlayerError> for Failure {
+    fn from(e: PlayerError) -> Self {
+        Failure::Player(e)
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+///Runs the call, turns its failure or panic into the code and the last error message
+fn guard(call: impl FnOnce() -> Result<(), Failure>) -> NdsdError {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(())) => NdsdError::Ok,
+        Ok(Err(Failure::Player(e))) => {
+            set_last_error(e.to_string());
+            NdsdError::from(&e)
+        }
+        Ok(Err(Failure::InvalidArgument(message))) => {
+            set_last_error(message.to_string());
+            NdsdError::InvalidArgument
+        }
+        Err(_) => {
+            set_last_error("panic in ndsd-playback".to_string());
+            NdsdError::Panic
+        }
+    }
+}
+
+unsafe fn string_arg<'a>(value: *const c_char, name: &'static str) -> Result<&'a str, Failure> {
+    if value.is_null() {
+        return Err(Failure::InvalidArgument(name));
+    }
+    unsafe { CStr::from_ptr(value) }
+        .to_str()
+        .map_err(|_| Failure::InvalidArgument(name))
+}
+
+unsafe fn player_arg<'a>(player: *mut NdsdPlayer) -> Result<&'a mut NdsdPlayer, Failure> {
+    unsafe { player.as_mut() }.ok_or(Failure::InvalidArgument("player handle is null"))
+}
+
+unsafe fn write_out<T>(out: *mut T, value: T) {
+    if let Some(out) = unsafe { out.as_mut() } {
+        *out = value;
+    }
+}
+
+///Text of the last failure on the calling thread, null if there was none.
+/// Valid until the next failing call on the same thread
+#[unsafe(no_mangle)]
+pub extern "C" fn ndsd_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(null(), |message| message.as_ptr()))
+}
+
+///Devices found by ndsd_devices_enumerate, strings live as long as the list
+pub struct NdsdDeviceList {
+    devices: Vec<DeviceInfo>,
+    card_descriptions: Vec<CString>,
+    device_descriptions: Vec<CString>,
+}
+
+impl NdsdDeviceList {
+    fn get(&self, index: usize) -> Option<&DeviceInfo> {
+        self.devices.get(index)
+    }
+}
+
+///Dsd capable output devices, release the list with ndsd_devices_free
+#[unsafe(no_mangle)]
+pub extern "C" fn ndsd_devices_enumerate() -> *mut NdsdDeviceList {
+    let devices = enumerate_supported_devices();
+    let text = |value: &str| CString::new(value.replace('\0', " ")).unwrap_or_default();
+    Box::into_raw(Box::new(NdsdDeviceList {
+        card_descriptions: devices.iter().map(|d| text(&d.card_description)).collect(),
+        device_descriptions: devices.iter().map(|d| text(&d.device_description)).collect(),
+        devices,
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_devices_count(list: *const NdsdDeviceList) -> usize {
+    unsafe { list.as_ref() }.map_or(0, |list| list.devices.len())
+}
+
+///Id to pass to ndsd_player_create, null if the index is out of range
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_device_id(list: *const NdsdDeviceList, index: usize) -> *const c_char {
+    let list = unsafe { list.as_ref() };
+    list.and_then(|list| list.get(index)).map_or(null(), |device| device.id.as_ptr())
+}
+
+///Id which survives reboots and re-plugging, meant to be saved in settings
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_device_stable_id(list: *const NdsdDeviceList, index: usize) -> *const c_char {
+    let list = unsafe { list.as_ref() };
+    list.and_then(|list| list.get(index)).map_or(null(), |device| device.stable_id.as_ptr())
+}
+
+///Card name, usually the first line of the device description
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_device_card_description(list: *const NdsdDeviceList, index: usize) -> *const c_char {
+    let list = unsafe { list.as_ref() };
+    list.and_then(|list| list.card_descriptions.get(index)).map_or(null(), |text| text.as_ptr())
+}
+
+///Second line of the device description, empty if there is none
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_device_description(list: *const NdsdDeviceList, index: usize) -> *const c_char {
+    let list = unsafe { list.as_ref() };
+    list.and_then(|list| list.device_descriptions.get(index)).map_or(null(), |text| text.as_ptr())
+}
+
+///True if the device has no native dsd format and plays over DoP
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_device_is_dop(list: *const NdsdDeviceList, index: usize) -> bool {
+    let list = unsafe { list.as_ref() };
+    list.and_then(|list| list.get(index)).is_some_and(|device| device.dop)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_devices_free(list: *mut NdsdDeviceList) {
+    if !list.is_null() {
+        drop(unsafe { Box::from_raw(list) });
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdLatencyProfile {
+    LowLatency,
+    Balanced,
+    HighBuffer,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdClosePolicy {
+    Drop,
+    Drain,
+}
+
+///PlayerConfig for C, times are in microseconds and NDSD_DEFAULT keeps the value of the library
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NdsdConfig {
+    pub profile: NdsdLatencyProfile,
+    pub buffer_time_us: u64,
+    pub period_time_us: u64,
+    pub start_threshold_us: u64,
+    pub read_ahead_us: u64,
+    pub preroll_us: u64,
+    pub mute_time_us: u64,
+    pub reconnect_interval_us: u64,
+    ///Zero disables the reconnect, NDSD_DEFAULT retries until stopped
+    pub reconnect_max_attempts: u64,
+    pub close_policy: NdsdClosePolicy,
+}
+
+///Config of the Balanced profile with every value left to the library
+#[unsafe(no_mangle)]
+pub extern "C" fn ndsd_config_default() -> NdsdConfig {
+    NdsdConfig {
+        profile: NdsdLatencyProfile::Balanced,
+        buffer_time_us: NDSD_DEFAULT,
+        period_time_us: NDSD_DEFAULT,
+        start_threshold_us: NDSD_DEFAULT,
+        read_ahead_us: NDSD_DEFAULT,
+        preroll_us: NDSD_DEFAULT,
+        mute_time_us: NDSD_DEFAULT,
+        reconnect_interval_us: NDSD_DEFAULT,
+        reconnect_max_attempts: NDSD_DEFAULT,
+        close_policy: NdsdClosePolicy::Drop,
+    }
+}
+
+impl From<&NdsdConfig> for PlayerConfig {
+    fn from(c: &NdsdConfig) -> Self {
+        let profile = match c.profile {
+            NdsdLatencyProfile::LowLatency => LatencyProfile::LowLatency,
+            NdsdLatencyProfile::Balanced => LatencyProfile::Balanced,
+            NdsdLatencyProfile::HighBuffer => LatencyProfile::HighBuffer,
+        };
+        let close_policy = match c.close_policy {
+            NdsdClosePolicy::Drop => ClosePolicy::Drop,
+            NdsdClosePolicy::Drain => ClosePolicy::Drain,
+        };
+        let time = |us: u64| (us != NDSD_DEFAULT).then(|| Duration::from_micros(us));
+        let mut config = PlayerConfig::new().profile(profile).close_policy(close_policy);
+        let mut reconnect = ReconnectPolicy::default();
+        if let Some(interval) = time(c.reconnect_interval_us) {
+            reconnect.interval = interval;
+        }
+        if c.reconnect_max_attempts != NDSD_DEFAULT {
+            reconnect.max_attempts = Some(c.reconnect_max_attempts.min(u32::MAX as u64) as u32);
+        }
+        config = config.reconnect(reconnect);
+        type Setter = fn(PlayerConfig, Duration) -> PlayerConfig;
+        let setters: [(u64, Setter); 6] = [
+            (c.buffer_time_us, PlayerConfig::buffer_time),
+            (c.period_time_us, PlayerConfig::period_time),
+            (c.start_threshold_us, PlayerConfig::start_threshold),
+            (c.read_ahead_us, PlayerConfig::read_ahead),
+            (c.preroll_us, PlayerConfig::preroll),
+            (c.mute_time_us, PlayerConfig::mute_time),
+        ];
+        for (value, set) in setters {
+            if let Some(value) = time(value) {
+                config = set(config, value);
+            }
+        }
+        config
+    }
+}
+
+///Point in the track, one frame is a single bit per channel
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NdsdTime {
+    pub time_us: u64,
+    pub frames: u64,
+}
+
+impl From<PlaybackTime> for NdsdTime {
+    fn from(time: PlaybackTime) -> Self {
+        Self {
+            time_us: time.time.as_micros() as u64,
+            frames: time.frames,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NdsdFormat {
+    pub sampling_rate: u32,
+    pub num_channels: u32,
+    pub total_samples: u64,
+    pub is_lsb_first: bool,
+}
+
+impl From<DSDFormat> for NdsdFormat {
+    fn from(format: DSDFormat) -> Self {
+        Self {
+            sampling_rate: format.sampling_rate,
+            num_channels: format.num_channels,
+            total_samples: format.total_samples,
+            is_lsb_first: format.is_lsb_first,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdsdMetaField {
+    Artist,
+    Album,
+    Title,
+    Comment,
+    Genre,
+}
+
+///Player created by ndsd_player_create, released with ndsd_player_free
+pub struct NdsdPlayer {
+    player: SyncPlayer,
+    events: Option<EventThread>,
+}
+
+///Creates the player for a device id or stable id, config may be null for the defaults
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_create(
+    device_id: *const c_char,
+    config: *const NdsdConfig,
+    out: *mut *mut NdsdPlayer,
+) -> NdsdError {
+    guard(|| {
+        if out.is_null() {
+            return Err(Failure::InvalidArgument("out pointer is null"));
+        }
+        let device_id = unsafe { string_arg(device_id, "device id is null or not utf-8")? };
+        let config = unsafe { config.as_ref() }.map(PlayerConfig::from).unwrap_or_default();
+        let device_id = CString::new(device_id).map_err(|_| Failure::InvalidArgument("device id has a nul"))?;
+        let player = SyncPlayer::create(device_id, config)?;
+        unsafe { *out = Box::into_raw(Box::new(NdsdPlayer { player, events: None })) };
+        Ok(())
+    })
+}
+
+///Closes the player as its close policy says and releases it, null is ignored.
+/// Must not be called from the event callback
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_free(player: *mut NdsdPlayer) {
+    if player.is_null() {
+        return;
+    }
+    let mut player = unsafe { Box::from_raw(player) };
+    // Close failures are of no use to a caller releasing the player
+    let _ = catch_unwind(AssertUnwindSafe(|| player.player.close()));
+    drop(player.events.take());
+}
+
+///Stops the playback and releases the device, later commands fail with NDSD_ERROR_TERMINATED
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_close(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.close()?))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_load(player: *mut NdsdPlayer, path: *const c_char) -> NdsdError {
+    guard(|| {
+        let path = unsafe { string_arg(path, "path is null or not utf-8")? };
+        Ok(unsafe { player_arg(player)? }.player.load_new_track(path)?)
+    })
+}
+
+///Track which plays right after the current one ends, gapless when the format is the same
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_enqueue_next(player: *mut NdsdPlayer, path: *const c_char) -> NdsdError {
+    guard(|| {
+        let path = unsafe { string_arg(path, "path is null or not utf-8")? };
+        Ok(unsafe { player_arg(player)? }.player.enqueue_next(path)?)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_start(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.start()?))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_pause(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.pause()?))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_play(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.play()?))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_stop(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.stop()?))
+}
+
+///Seeks to the percent (0..1) of the track
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_seek(player: *mut NdsdPlayer, percent: f64) -> NdsdError {
+    guard(|| Ok(unsafe { player_arg(player)? }.player.seek(percent)?))
+}
+
+///Seeks to the time from the start of the track, landed may be null
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_seek_to(player: *mut NdsdPlayer, time_us: u64, landed: *mut NdsdTime) -> NdsdError {
+    guard(|| {
+        let time = unsafe { player_arg(player)? }.player.seek_to(Duration::from_micros(time_us))?;
+        unsafe { write_out(landed, time.into()) };
+        Ok(())
+    })
+}
+
+///Seeks relative to what is heard right now, negative offsets go back
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_seek_by(player: *mut NdsdPlayer, offset_us: i64, landed: *mut NdsdTime) -> NdsdError {
+    guard(|| {
+        let direction = if offset_us < 0 { SeekDirection::Backward } else { SeekDirection::Forward };
+        let offset = Duration::from_micros(offset_us.unsigned_abs());
+        let time = unsafe { player_arg(player)? }.player.seek_by(offset, direction)?;
+        unsafe { write_out(landed, time.into()) };
+        Ok(())
+    })
+}
+
+///Seeks like ndsd_player_seek_to and starts the playback from there
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_start_at(player: *mut NdsdPlayer, time_us: u64, landed: *mut NdsdTime) -> NdsdError {
+    guard(|| {
+        let time = unsafe { player_arg(player)? }.player.start_at(Duration::from_micros(time_us))?;
+        unsafe { write_out(landed, time.into()) };
+        Ok(())
+    })
+}
+
+///Position of what is heard right now
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_position(player: *mut NdsdPlayer, position: *mut NdsdTime) -> NdsdError {
+    guard(|| {
+        let time = unsafe { player_arg(player)? }.player.position();
+        unsafe { write_out(position, time.into()) };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_duration(player: *mut NdsdPlayer, duration: *mut NdsdTime) -> NdsdError {
+    guard(|| {
+        let time = unsafe { player_arg(player)? }.player.duration();
+        unsafe { write_out(duration, time.into()) };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_is_playing(player: *mut NdsdPlayer) -> bool {
+    unsafe { player.as_ref() }.is_some_and(|player| player.player.is_playing())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_format(player: *mut NdsdPlayer, format: *mut NdsdFormat) -> NdsdError {
+    guard(|| {
+        let current = unsafe { player_arg(player)? }.player.get_format_info();
+        unsafe { write_out(format, current.into()) };
+        Ok(())
+    })
+}
+
+///Copies the metadata text of the current track into buffer like snprintf does.
+/// Returns the length of the whole text without the nul, 0 if the track has none
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_meta(
+    player: *mut NdsdPlayer,
+    field: NdsdMetaField,
+    buffer: *mut c_char,
+    size: usize,
+) -> usize {
+    let Some(player) = (unsafe { player.as_ref() }) else {
+        return 0;
+    };
+    let Some(meta) = player.player.get_current_file_meta() else {
+        return 0;
+    };
+    let text = match field {
+        NdsdMetaField::Artist => meta.artist,
+        NdsdMetaField::Album => meta.album,
+        NdsdMetaField::Title => meta.title,
+        NdsdMetaField::Comment => meta.comment,
+        NdsdMetaField::Genre => meta.genre,
+    };
+    let text = text.unwrap_or_default().replace('\0', " ");
+    if !buffer.is_null() && size > 0 {
+        let copied = text.len().min(size - 1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(text.as_ptr() as *const c_char, buffer, copied);
+            *buffer.add(copied) = 0;
+        }
+    }
+    text.len()
+}
+
+///Error which stopped the playback in background, NDSD_ERROR_OK if there was none.
+/// Its text is in ndsd_last_error_message
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_take_error(player: *mut NdsdPlayer) -> NdsdError {
+    guard(|| match unsafe { player_arg(player)? }.player.take_error() {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    })
+}
+
+///Callback gets every player event on a thread of the library, user_data is passed back unchanged.
+/// Replaces the callback set before, null removes it
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ndsd_player_set_event_callback(
+    player: *mut NdsdPlayer,
+    callback: NdsdEventCallback,
+    user_data: *mut c_void,
+) -> NdsdError {
+    guard(|| {
+        let player = unsafe { player_arg(player)? };
+        // Old callback is not called anymore once this returns
+        drop(player.events.take());
+        if let Some(callback) = callback {
+            player.events = Some(EventThread::spawn(player.player.subscribe(), callback, user_data));
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    extern "C" fn count_ended(event: *const NdsdEvent, user_data: *mut c_void) {
+        let counter = unsafe { &*(user_data as *const AtomicUsize) };
+        if unsafe { (*event).kind } == NdsdEventKind::TrackEnded {
+            counter.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn plays_through_the_c_api() {
+        let track = crate::test_util::write_test_track("ndsd_capi.dsf", 2822400, 0.1);
+        let path = CString::new(track.as_str()).unwrap();
+
+        let mut config = ndsd_config_default();
+        config.preroll_us = 0;
+        let mut player = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ndsd_player_create(c"null:4".as_ptr(), &config, &mut player), NdsdError::Ok);
+            assert_eq!(ndsd_player_start(player), NdsdError::NoTrack);
+            assert!(!ndsd_last_error_message().is_null());
+            let ended = AtomicUsize::new(0);
+            let user_data = &ended as *const AtomicUsize as *mut c_void;
+            assert_eq!(ndsd_player_set_event_callback(player, Some(count_ended), user_data), NdsdError::Ok);
+            assert_eq!(ndsd_player_load(player, path.as_ptr()), NdsdError::Ok);
+            let mut duration = NdsdTime::default();
+            assert_eq!(ndsd_player_duration(player, &mut duration), NdsdError::Ok);
+            assert_eq!(duration.time_us, 100_000);
+            assert_eq!(ndsd_player_start(player), NdsdError::Ok);
+            for _ in 0..300 {
+                if ended.load(Relaxed) > 0 {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(ended.load(Relaxed), 1);
+            assert_eq!(ndsd_player_close(player), NdsdError::Ok);
+            assert_eq!(ndsd_player_play(player), NdsdError::Terminated);
+            ndsd_player_free(player);
+            assert_eq!(ndsd_player_start(std::ptr::null_mut()), NdsdError::InvalidArgument);
+        }
+        let _ = std::fs::remove_file(track);
+    }
+}
@@ -1,30 +1,783 @@
 pub mod semaphore;
 pub mod players;
 pub mod utils;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+pub mod python;
 
-
+// Fixtures shared by the tests of every module, the tokio gated ones included
 #[cfg(test)]
+pub(crate) mod test_util{
+    use ndsd_read::DSDFormat;
+    use crate::players::AudioSink;
+    use crate::players::file::FileSink;
+
+    ///Writes stereo dsf with a counting pattern, so the tests do not depend on local music collection
+    pub(crate) fn write_test_track(name: &str, sampling_rate: u32, seconds: f64) -> String {
+        let path = std::env::temp_dir().join(name);
+        let format = DSDFormat {
+            sampling_rate,
+            num_channels: 2,
+            total_samples: 0,
+            is_lsb_first: true,
+        };
+        let bytes = (sampling_rate as f64 / 8.0 * seconds).round() as usize;
+        let data: Vec<Vec<u8>> = (0..2)
+            .map(|ch| (0..bytes).map(|i| (i + ch * 128) as u8).collect())
+            .collect();
+        let mut sink = FileSink::new(&path);
+        sink.open(&format).unwrap();
+        let slices: Vec<&[u8]> = data.iter().map(|c| c.as_slice()).collect();
+        sink.write(&slices, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+}
+
+// Event tests use the tokio receivers, SyncPlayer covers the build without them
+#[cfg(all(test, feature = "tokio"))]
 mod tests{
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
     use tokio::time::sleep;
-    use crate::players::{create_player, enumerate_supported_devices};
+    use ndsd_read::DSDFormat;
+    use crate::players::{create_player, create_sink, device_capabilities, enumerate_supported_devices, AudioSink, ClosePolicy, DSDPlayer, LatencyProfile, OutputParams, PlaybackEngine, PlayerConfig, PlayerError, PlayerEvent, ReconnectPolicy, SeekDirection, Wakeup, XrunKind};
+    use crate::players::file::FileSink;
+    use crate::players::null::NullSink;
+    use crate::test_util::write_test_track;
+
+    #[tokio::test]
+    async fn null_sink_playback_flow(){
+        let track = write_test_track("ndsd_null_flow.dsf", 2822400, 2.0);
+        let mut player = create_player(c"null:4".into(), PlayerConfig::default()).unwrap();
+
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(player.is_playing().await);
+        assert_eq!(player.get_format_info().await.sampling_rate, 2822400);
+
+        player.pause().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let paused_at = player.get_pos().await;
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(player.get_pos().await, paused_at);
+        player.play().await.unwrap();
+
+        // 2 seconds at 4x speed, must end well before the timeout
+        let mut waited = 0;
+        while player.is_playing().await {
+            assert!(waited < 3000, "track did not finish");
+            sleep(Duration::from_millis(20)).await;
+            waited += 20;
+        }
+        assert!(waited >= 200, "virtual clock runs too fast");
+        assert_eq!(player.get_pos().await, 1f64);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn events_follow_transport(){
+        let track = write_test_track("ndsd_events.dsf", 2822400, 1.0);
+        let mut player = create_player(c"null:4".into(), PlayerConfig::default()).unwrap();
+        let mut events = player.subscribe();
+
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        player.pause().await.unwrap();
+        player.play().await.unwrap();
+        player.seek(0.5).await.unwrap();
+
+        let mut received = Vec::new();
+        let ended = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                let done = event == PlayerEvent::TrackEnded;
+                received.push(event);
+                if done {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(ended.is_ok(), "no TrackEnded in {:?}", received);
+
+        assert!(matches!(received[0], PlayerEvent::TrackLoaded { format, .. } if format.sampling_rate == 2822400));
+        assert!(matches!(received[1], PlayerEvent::FormatChanged(_)));
+        assert_eq!(received[2..5], [PlayerEvent::Started, PlayerEvent::Paused, PlayerEvent::Resumed]);
+        assert!(matches!(received[5], PlayerEvent::Seeked { position } if (position - 0.5).abs() < 0.01));
+        assert!(received[6..received.len() - 1]
+            .iter()
+            .all(|e| matches!(e, PlayerEvent::PositionTick { .. })));
+
+        player.stop().await.unwrap();
+        assert_eq!(events.recv().await.unwrap(), PlayerEvent::Stopped);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn gapless_transition_keeps_stream_continuous(){
+        // Lengths are not multiples of the DSF block, padding must not end up between the tracks
+        let first = write_test_track("ndsd_gapless_1.dsf", 2822400, 0.3);
+        let second = write_test_track("ndsd_gapless_2.dsf", 2822400, 0.2);
+        let capture = std::env::temp_dir().join("ndsd_gapless_capture.dsf");
+        let device = std::ffi::CString::new(format!("file:{}", capture.display())).unwrap();
+        let mut player = create_player(device, PlayerConfig::default()).unwrap();
+        let mut events = player.subscribe();
+
+        player.load_new_track(&first).await.unwrap();
+        player.enqueue_next(&second).await.unwrap();
+        player.start().await.unwrap();
+        let mut received = Vec::new();
+        while received.iter().filter(|e| **e == PlayerEvent::TrackEnded).count() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(3), events.recv()).await.unwrap().unwrap();
+            received.push(event);
+        }
+        let transitions: Vec<&PlayerEvent> = received
+            .iter()
+            .filter(|e| !matches!(e, PlayerEvent::PositionTick { .. }))
+            .collect();
+        assert_eq!(transitions.len(), 6, "{:?}", transitions);
+        assert!(matches!(transitions[3], PlayerEvent::TrackEnded));
+        assert!(matches!(transitions[4], PlayerEvent::TrackLoaded { .. }));
+        drop(player);
+        sleep(Duration::from_millis(100)).await;
+
+        let first_bytes = (352800.0f64 * 0.3).round() as usize;
+        let second_bytes = (352800.0f64 * 0.2).round() as usize;
+        let mut format = DSDFormat::default();
+        let mut reader = ndsd_read::open_dsd_auto(capture.to_str().unwrap(), &mut format).unwrap();
+        let total = first_bytes + second_bytes;
+        let mut out = vec![vec![0u8; total]; 2];
+        let mut slices: Vec<&mut [u8]> = out.iter_mut().map(|v| v.as_mut_slice()).collect();
+        assert_eq!(reader.read(&mut slices, total).unwrap(), total);
+        for (ch, data) in out.iter().enumerate() {
+            let expected: Vec<u8> = (0..first_bytes)
+                .chain(0..second_bytes)
+                .map(|i| (i + ch * 128) as u8)
+                .collect();
+            assert!(data == &expected, "channel {} is not continuous", ch);
+        }
+        assert!(!std::env::temp_dir().join("ndsd_gapless_capture-1.dsf").exists(), "output was reopened");
+        for path in [first, second, capture.to_str().unwrap().to_string()] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_ahead_fills_up_to_depth(){
+        let track = write_test_track("ndsd_read_ahead.dsf", 2822400, 3.0);
+        let sink = create_sink(&c"null".into(), &PlayerConfig::default()).unwrap();
+        let mut player = PlaybackEngine::with_read_ahead(sink, Duration::from_secs(1));
+
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        player.pause().await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+        let level = player.buffer_level().await;
+        assert!((level.depth.as_secs_f64() - 1.0).abs() < 0.01, "depth {:?}", level.depth);
+        assert!(level.filled >= Duration::from_millis(900), "filled only {:?}", level.filled);
+        assert!(level.filled <= level.depth + Duration::from_millis(20));
+
+        // Position follows the output, not the read-ahead
+        let position = player.get_pos().await;
+        assert!(position < 0.2, "position {} runs ahead of the output", position);
+
+        player.seek(0.5).await.unwrap();
+        assert!((player.get_pos().await - 0.5).abs() < 0.01);
+        player.stop().await.unwrap();
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn errors_reach_caller(){
+        let mut player = create_player(c"null".into(), PlayerConfig::default()).unwrap();
+        assert_eq!(player.start().await, Err(PlayerError::NoTrack));
+        let missing = player.load_new_track("/nonexistent/track.dsf").await;
+        assert!(matches!(missing, Err(PlayerError::TrackOpen { .. })));
+
+        #[cfg(target_os = "linux")]
+        {
+            let track = write_test_track("ndsd_errors.dsf", 2822400, 0.1);
+            let mut player = create_player(c"hw:99,0".into(), PlayerConfig::default()).unwrap();
+            let mut events = player.subscribe();
+            // Device is opened once the track needs it, failure must not kill the player thread
+            let res = player.load_new_track(&track).await;
+            assert!(matches!(res, Err(PlayerError::DeviceOpen { errno, .. }) if errno > 0), "{:?}", res);
+            let errors = std::iter::from_fn(|| events.try_recv().ok()).filter(|e| matches!(e, PlayerEvent::DeviceError(_)));
+            assert_eq!(errors.count(), 1);
+            assert!(player.start().await.is_err());
+            assert!(!player.is_playing().await);
+            let _ = std::fs::remove_file(track);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_reopen_is_reported_once(){
+        let first = write_test_track("ndsd_reopen_1.dsf", 2822400, 0.2);
+        let second = write_test_track("ndsd_reopen_2.dsf", 5644800, 0.2);
+        let (sink, _) = TestSink::wrap(NullSink::new(4.0), Hooks { lose_at_open: 2, failing_opens: 1, ..Default::default() });
+        let mut player = PlaybackEngine::new(sink);
+        let mut events = player.subscribe();
+        player.load_new_track(&first).await.unwrap();
+        player.enqueue_next(&second).await.unwrap();
+        player.start().await.unwrap();
+
+        // Second track needs the output opened for its rate
+        let error = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                if let PlayerEvent::DeviceError(e) = events.recv().await.unwrap() {
+                    return e;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(matches!(error, PlayerError::DeviceOpen { .. }));
+        sleep(Duration::from_millis(50)).await;
+        assert!(!std::iter::from_fn(|| events.try_recv().ok()).any(|e| matches!(e, PlayerEvent::DeviceError(_))));
+        assert_eq!(player.take_error().await, Some(error));
+        for path in [first, second] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
 
     #[tokio::test]
+    async fn position_counts_only_what_was_heard(){
+        let track = write_test_track("ndsd_position.dsf", 2822400, 2.0);
+        let mut player = create_player(c"null".into(), PlayerConfig::default()).unwrap();
+
+        player.load_new_track(&track).await.unwrap();
+        let duration = player.duration().await;
+        assert_eq!(duration.frames, 2 * 2822400);
+        assert_eq!(duration.time, Duration::from_secs(2));
+
+        let started = std::time::Instant::now();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        for _ in 0..5 {
+            let position = player.position().await;
+            // Sink keeps up to the 200ms buffer queued, that part must not be counted
+            assert!(position.time <= started.elapsed() + Duration::from_millis(5), "{:?} is ahead of the clock", position.time);
+            assert!(position.time >= Duration::from_millis(400), "{:?} is behind", position.time);
+            assert_eq!(position.time, Duration::from_nanos(position.frames * 1_000_000_000 / 2822400));
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        player.pause().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let paused_at = player.position().await;
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(player.position().await, paused_at);
+        player.stop().await.unwrap();
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn seeking_by_time_lands_on_blocks(){
+        let track = write_test_track("ndsd_seek_time.dsf", 2822400, 3.0);
+        let mut player = create_player(c"null:4".into(), PlayerConfig::default()).unwrap();
+        assert_eq!(player.seek_to(Duration::from_secs(1)).await, Err(PlayerError::NoTrack));
+
+        player.load_new_track(&track).await.unwrap();
+        // DSF blocks are 4096 bytes per channel
+        let block = Duration::from_nanos(4096 * 8 * 1_000_000_000 / 2822400);
+        let landed = player.seek_to(Duration::from_millis(1500)).await.unwrap();
+        assert_eq!(landed.frames % (4096 * 8), 0);
+        assert!(landed.time <= Duration::from_millis(1500) && landed.time + block > Duration::from_millis(1500));
+        assert_eq!(player.position().await, landed);
+
+        let forward = player.seek_by(Duration::from_millis(500), SeekDirection::Forward).await.unwrap();
+        assert!(forward.time <= Duration::from_secs(2) && forward.time + block > Duration::from_secs(2));
+        let back = player.seek_by(Duration::from_secs(10), SeekDirection::Backward).await.unwrap();
+        assert_eq!(back.frames, 0);
+
+        let started = player.start_at(Duration::from_millis(2500)).await.unwrap();
+        assert!(started.time + block > Duration::from_millis(2500));
+        assert!(player.is_playing().await);
+        sleep(Duration::from_millis(50)).await;
+        assert!(player.position().await >= started);
+        player.stop().await.unwrap();
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn commands_do_not_wait_for_the_output(){
+        let track = write_test_track("ndsd_commands.dsf", 2822400, 3.0);
+        // At a tenth of the speed a 100ms period of this profile blocks the null sink write for a second,
+        // commands answered well below that did not wait for it
+        let config = PlayerConfig::new().profile(LatencyProfile::HighBuffer);
+        let mut player = create_player(c"null:0.1".into(), config).unwrap();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+        for _ in 0..3 {
+            let sent = std::time::Instant::now();
+            player.pause().await.unwrap();
+            assert!(sent.elapsed() < Duration::from_millis(500), "pause took {:?}", sent.elapsed());
+            sleep(Duration::from_millis(40)).await;
+            let sent = std::time::Instant::now();
+            player.play().await.unwrap();
+            assert!(sent.elapsed() < Duration::from_millis(500), "play took {:?}", sent.elapsed());
+            sleep(Duration::from_millis(40)).await;
+        }
+        assert!(player.is_playing().await);
+        player.stop().await.unwrap();
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn stop_ends_the_pause(){
+        let track = write_test_track("ndsd_pause_stop.dsf", 2822400, 0.5);
+        let (sink, probe) = TestSink::wrap(NullSink::new(4.0), Hooks { strict_pause: true, ..Default::default() });
+        let mut player = PlaybackEngine::new(sink);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(30)).await;
+        player.pause().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        player.stop().await.unwrap();
+        player.pause().await.unwrap();
+        player.start().await.unwrap();
+
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                match events.recv().await.unwrap() {
+                    PlayerEvent::PositionTick { .. } => {}
+                    PlayerEvent::TrackEnded => break,
+                    event => received.push(event),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let paused = received.iter().filter(|e| **e == PlayerEvent::Paused).count();
+        assert_eq!(paused, 1, "{:?}", received);
+        assert!(!received.iter().any(|e| matches!(e, PlayerEvent::DeviceError(_))), "{:?}", received);
+        assert!(!probe.lock().unwrap().paused);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn play_and_pause_repeat_safely(){
+        let track = write_test_track("ndsd_play_twice.dsf", 2822400, 1.0);
+        let (sink, probe) = TestSink::wrap(NullSink::new(2.0), Hooks { strict_pause: true, ..Default::default() });
+        let mut player = PlaybackEngine::new(sink);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        player.play().await.unwrap();
+        player.pause().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        player.pause().await.unwrap();
+        player.play().await.unwrap();
+        player.play().await.unwrap();
+        player.pause().await.unwrap();
+        player.play().await.unwrap();
+
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                match events.recv().await.unwrap() {
+                    PlayerEvent::PositionTick { .. } => {}
+                    PlayerEvent::TrackEnded => break,
+                    event => received.push(event),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!received.iter().any(|e| matches!(e, PlayerEvent::DeviceError(_))), "{:?}", received);
+        assert_eq!(player.take_error().await, None);
+        assert!(!probe.lock().unwrap().paused);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn config_is_validated_and_granted(){
+        let short_buffer = PlayerConfig::new().buffer_time(Duration::from_millis(30)).period_time(Duration::from_millis(20));
+        assert!(matches!(create_player(c"null".into(), short_buffer).err(), Some(PlayerError::InvalidConfig { .. })));
+        let late_start = PlayerConfig::new().start_threshold(Duration::from_secs(5));
+        assert!(matches!(late_start.validate(), Err(PlayerError::InvalidConfig { .. })));
+        assert!(PlayerConfig::new().profile(LatencyProfile::HighBuffer).validate().is_ok());
+
+        let track = write_test_track("ndsd_config.dsf", 2822400, 0.5);
+        let config = PlayerConfig::new().profile(LatencyProfile::LowLatency).start_threshold(Duration::from_millis(20));
+        let mut player = create_player(c"null:4".into(), config).unwrap();
+        assert_eq!(player.output_params().await, None);
+        player.load_new_track(&track).await.unwrap();
+        // Null sink frames are dsd bytes per channel
+        let granted = player.output_params().await.unwrap();
+        assert_eq!(granted.rate, 2822400 / 8);
+        assert_eq!(granted.period_frames, 3528);
+        assert_eq!(granted.buffer_frames, 4 * 3528);
+        assert_eq!(granted.start_threshold_frames, 2 * 3528);
+        assert_eq!(granted.buffer_time, Duration::from_millis(40));
+        let _ = std::fs::remove_file(track);
+    }
+
+    ///What the test output does on top of the sink it wraps
+    #[derive(Default)]
+    struct Hooks {
+        ///Underrun on the third and suspend on the sixth of every six writes
+        xruns: bool,
+        ///Write on which the output disappears, 0 keeps it
+        lose_at_write: usize,
+        ///Open on which the output disappears, 0 keeps it
+        lose_at_open: usize,
+        ///Opens failing once the output disappeared
+        failing_opens: usize,
+        ///Gets the idle pattern a DAC gets
+        silence: bool,
+        ///Fails to pause or resume twice in a row, like a running alsa pcm does
+        strict_pause: bool,
+    }
+
+    ///What the test output went through, shared with the test
+    #[derive(Default)]
+    struct Probe {
+        opens: usize,
+        writes: usize,
+        // Bytes per channel written, counted from zero again once the output disappeared
+        written: usize,
+        // Bytes per channel the output played until it disappeared
+        heard_at_loss: usize,
+        lost: bool,
+        paused: bool,
+        ///Drains, flushes and the release of the output in the order they happened
+        calls: Vec<&'static str>,
+    }
+
+    ///Output passing everything to a null or file sink, the hooks add what a real device would do
+    struct TestSink {
+        inner: Box<dyn AudioSink>,
+        hooks: Hooks,
+        probe: Arc<Mutex<Probe>>,
+    }
+
+    impl TestSink {
+        fn wrap(inner: impl AudioSink + 'static, hooks: Hooks) -> (Box<dyn AudioSink>, Arc<Mutex<Probe>>) {
+            let probe = Arc::new(Mutex::new(Probe::default()));
+            let sink = Self { inner: Box::new(inner), hooks, probe: probe.clone() };
+            (Box::new(sink), probe)
+        }
+    }
+
+    impl AudioSink for TestSink {
+        fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+            let mut probe = self.probe.lock().unwrap();
+            probe.opens += 1;
+            probe.lost |= probe.opens == self.hooks.lose_at_open;
+            if probe.lost && self.hooks.failing_opens > 0 {
+                self.hooks.failing_opens -= 1;
+                return Err(PlayerError::DeviceOpen { device: "unplugged".to_string(), errno: 2, message: "No such file or directory".to_string() });
+            }
+            self.inner.open(format)
+        }
+        fn period_bytes(&self) -> usize {
+            self.inner.period_bytes()
+        }
+        fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+            let mut probe = self.probe.lock().unwrap();
+            probe.writes += 1;
+            if !probe.lost && probe.writes == self.hooks.lose_at_write {
+                probe.lost = true;
+                probe.heard_at_loss = probe.written - self.inner.delay();
+                probe.written = 0;
+                return Err(PlayerError::DeviceLost { device: "unplugged".to_string(), errno: 19, message: "No such device".to_string() });
+            }
+            probe.written += bytes_per_channel;
+            drop(probe);
+            self.inner.write(data, bytes_per_channel)
+        }
+        fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+            let mut probe = self.probe.lock().unwrap();
+            if self.hooks.strict_pause && probe.paused == paused {
+                return Err(PlayerError::Device { errno: 77, message: "File descriptor in bad state".to_string() });
+            }
+            probe.paused = paused;
+            self.inner.pause(paused)
+        }
+        fn drain(&mut self) -> Result<(), PlayerError> {
+            self.probe.lock().unwrap().calls.push("drain");
+            self.inner.drain()
+        }
+        fn flush(&mut self) -> Result<(), PlayerError> {
+            self.probe.lock().unwrap().calls.push("flush");
+            self.inner.flush()
+        }
+        fn delay(&self) -> usize {
+            self.inner.delay()
+        }
+        fn output_params(&self) -> Option<OutputParams> {
+            self.inner.output_params()
+        }
+        fn wait_writable(&mut self, wakeup: &Wakeup, timeout: Duration) -> Result<bool, PlayerError> {
+            self.inner.wait_writable(wakeup, timeout)
+        }
+        fn take_xrun(&mut self) -> Option<XrunKind> {
+            let writes = self.probe.lock().unwrap().writes;
+            match writes % 6 {
+                3 if self.hooks.xruns => Some(XrunKind::Underrun),
+                0 if self.hooks.xruns => Some(XrunKind::Suspend),
+                _ => self.inner.take_xrun(),
+            }
+        }
+        fn needs_silence(&self) -> bool {
+            self.hooks.silence || self.inner.needs_silence()
+        }
+        fn finish(&mut self) -> Result<(), PlayerError> {
+            self.inner.finish()
+        }
+    }
+
+    impl Drop for TestSink {
+        fn drop(&mut self) {
+            self.probe.lock().unwrap().calls.push("released");
+        }
+    }
+
+    #[tokio::test]
+    async fn recovered_xruns_are_counted(){
+        let track = write_test_track("ndsd_xruns.dsf", 2822400, 0.5);
+        let (sink, _) = TestSink::wrap(NullSink::new(8.0), Hooks { xruns: true, ..Default::default() });
+        let mut player = PlaybackEngine::new(sink);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+
+        let mut reported = Vec::new();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                match events.recv().await.unwrap() {
+                    PlayerEvent::Xrun(xrun) => reported.push(xrun),
+                    PlayerEvent::TrackEnded => break,
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        // 25ms periods, the half second takes about 20 writes
+        // Silence written after the track end can still hit an xrun, stop answers once its event was sent
+        player.stop().await.unwrap();
+        let stats = player.xrun_stats().await;
+        reported.extend(std::iter::from_fn(|| events.try_recv().ok()).filter_map(|event| match event {
+            PlayerEvent::Xrun(xrun) => Some(xrun),
+            _ => None,
+        }));
+        assert!(stats.suspends >= 3 && stats.underruns - stats.suspends <= 1, "{:?}", stats);
+        assert_eq!(stats.total(), reported.len() as u64);
+        assert_eq!(stats.recent, reported);
+        assert_eq!(stats.recent[0].kind, XrunKind::Underrun);
+        assert!(stats.recent.iter().zip(stats.recent.iter().skip(1)).all(|(a, b)| a.at <= b.at && a.position <= b.position));
+        let _ = std::fs::remove_file(track);
+    }
+
+    fn unplugged_player(hooks: Hooks, reconnect: ReconnectPolicy) -> (PlaybackEngine, Arc<Mutex<Probe>>) {
+        let (sink, probe) = TestSink::wrap(NullSink::new(4.0), hooks);
+        let player = PlaybackEngine::with_config(sink, &PlayerConfig::new().reconnect(reconnect));
+        (player, probe)
+    }
+
+    #[tokio::test]
+    async fn lost_device_is_reconnected(){
+        let track = write_test_track("ndsd_reconnect.dsf", 2822400, 1.0);
+        let reconnect = ReconnectPolicy { interval: Duration::from_millis(30), max_attempts: None };
+        let (mut player, probe) = unplugged_player(Hooks { lose_at_write: 8, failing_opens: 3, ..Default::default() }, reconnect);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+
+        let mut transitions = Vec::new();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                match events.recv().await.unwrap() {
+                    PlayerEvent::PositionTick { .. } => {}
+                    PlayerEvent::TrackEnded => break,
+                    event => transitions.push(event),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(matches!(transitions[..], [.., PlayerEvent::DeviceLost(PlayerError::DeviceLost { errno: 19, .. }), PlayerEvent::DeviceReconnected]), "{:?}", transitions);
+        assert!(!player.is_device_lost().await);
+
+        let probe = probe.lock().unwrap();
+        assert_eq!(probe.opens, 5);
+        // Playback goes on from the DSF block holding what was heard, nothing is skipped
+        let rest = 352800 - probe.heard_at_loss;
+        assert!(probe.written >= rest, "{} of {} written", probe.written, rest);
+        assert!(probe.written <= rest + 4096 + 2 * 8820, "{} of {} written", probe.written, rest);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn reconnect_gives_up_after_max_attempts(){
+        let track = write_test_track("ndsd_reconnect_fail.dsf", 2822400, 1.0);
+        let reconnect = ReconnectPolicy { interval: Duration::from_millis(20), max_attempts: Some(2) };
+        let (mut player, probe) = unplugged_player(Hooks { lose_at_write: 3, failing_opens: 10, ..Default::default() }, reconnect);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+
+        let error = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let PlayerEvent::DeviceError(e) = events.recv().await.unwrap() {
+                    return e;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(matches!(error, PlayerError::DeviceLost { .. }));
+        assert!(!player.is_playing().await);
+        assert!(!player.is_device_lost().await);
+        assert_eq!(player.take_error().await, Some(error));
+        assert_eq!(probe.lock().unwrap().opens, 3);
+
+        let invalid = PlayerConfig::new().reconnect(ReconnectPolicy { interval: Duration::ZERO, max_attempts: None });
+        assert!(invalid.validate().is_err());
+        assert!(PlayerConfig::new().reconnect(ReconnectPolicy::DISABLED).validate().is_ok());
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn silence_surrounds_the_track(){
+        let track = write_test_track("ndsd_preroll.dsf", 2822400, 0.2);
+        let capture = std::env::temp_dir().join("ndsd_preroll_capture.dsf");
+        let config = PlayerConfig::new().preroll(Duration::from_millis(50)).mute_time(Duration::from_millis(10));
+        // Capture file stands in for a DAC
+        let (sink, _) = TestSink::wrap(FileSink::new(&capture), Hooks { silence: true, ..Default::default() });
+        let mut player = PlaybackEngine::with_config(sink, &config);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while events.recv().await.unwrap() != PlayerEvent::TrackEnded {}
+        })
+        .await
+        .unwrap();
+        drop(player);
+
+        let (preroll, music, mute) = (17640, 70560, 3528);
+        let mut format = DSDFormat::default();
+        let mut reader = ndsd_read::open_dsd_auto(capture.to_str().unwrap(), &mut format).unwrap();
+        let total = preroll + music + mute;
+        assert_eq!(format.total_samples, total as u64 * 8);
+        let mut out = vec![vec![0u8; total]; 2];
+        let mut slices: Vec<&mut [u8]> = out.iter_mut().map(|v| v.as_mut_slice()).collect();
+        assert_eq!(reader.read(&mut slices, total).unwrap(), total);
+        // Track is lsb first, its idle pattern is the reversed 0x69
+        assert!(out[0][..preroll].iter().all(|&b| b == 0x96));
+        assert!(out[0][preroll..preroll + music].iter().enumerate().all(|(i, &b)| b == i as u8));
+        assert!(out[1][preroll + music..total].iter().all(|&b| b == 0x96));
+        let _ = std::fs::remove_file(track);
+        let _ = std::fs::remove_file(capture);
+    }
+
+    #[tokio::test]
+    async fn repeated_start_keeps_the_stream(){
+        let track = write_test_track("ndsd_restart.dsf", 2822400, 0.5);
+        let config = PlayerConfig::new().preroll(Duration::from_millis(50)).mute_time(Duration::from_millis(10));
+        let (sink, probe) = TestSink::wrap(NullSink::new(2.0), Hooks { silence: true, ..Default::default() });
+        let mut player = PlaybackEngine::with_config(sink, &config);
+        let mut events = player.subscribe();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        player.start().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while events.recv().await.unwrap() != PlayerEvent::TrackEnded {}
+        })
+        .await
+        .unwrap();
+        // Preroll, music and mute, the second start did not put silence in between
+        assert_eq!(probe.lock().unwrap().written, 17640 + 176400 + 3528);
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[tokio::test]
+    async fn close_stops_and_releases_the_output(){
+        let track = write_test_track("ndsd_close.dsf", 2822400, 5.0);
+        for policy in [ClosePolicy::Drop, ClosePolicy::Drain] {
+            let (sink, probe) = TestSink::wrap(NullSink::new(1.0), Hooks::default());
+            let config = PlayerConfig::new().close_policy(policy);
+            let mut player = PlaybackEngine::with_config(sink, &config);
+            let mut events = player.subscribe();
+            player.load_new_track(&track).await.unwrap();
+            player.start().await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            player.close().await.unwrap();
+
+            let expected: &[&str] = match policy {
+                ClosePolicy::Drop => &["flush", "released"],
+                ClosePolicy::Drain => &["drain", "released"],
+            };
+            assert_eq!(probe.lock().unwrap().calls, expected);
+            assert!(!player.is_playing().await);
+            assert_eq!(player.start().await, Err(PlayerError::Terminated));
+            assert!(std::iter::from_fn(|| events.try_recv().ok()).any(|e| e == PlayerEvent::Stopped));
+            player.close().await.unwrap();
+        }
+
+        // Dropping a playing engine releases the output before drop returns
+        let (sink, probe) = TestSink::wrap(NullSink::new(1.0), Hooks::default());
+        let mut player = PlaybackEngine::new(sink);
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        drop(player);
+        assert_eq!(probe.lock().unwrap().calls.last(), Some(&"released"));
+        let _ = std::fs::remove_file(track);
+    }
+
+    #[test]
+    fn capabilities_tell_what_can_play(){
+        let null = device_capabilities(&c"null".into()).unwrap();
+        let mut format = DSDFormat {
+            sampling_rate: 22579200,
+            num_channels: 2,
+            ..Default::default()
+        };
+        assert!(null.can_play(&format));
+        assert_eq!(null.rates().len(), 10);
+
+        let missing = device_capabilities(&c"hw:99,0".into()).unwrap_err();
+        assert!(matches!(missing, PlayerError::DeviceOpen { .. }));
+
+        for device in enumerate_supported_devices() {
+            let capabilities = device_capabilities(&device.id).unwrap();
+            assert!(!capabilities.formats.is_empty());
+            assert!(capabilities.channels.start() <= capabilities.channels.end());
+        }
+        format.sampling_rate = 1;
+        assert!(!null.can_play(&format));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a DSD DAC and local music files"]
     async fn it_works(){
 
         let devices = enumerate_supported_devices();
 
         devices.iter().for_each(|device| {
-            eprintln!("{:?}{:?}", device.0, device.1);
+            eprintln!("{:?} {:?} {}", device.id, device.stable_id, device.card_description);
         });
-        let mut player = create_player(devices[1].0.clone()).unwrap();
+        let mut player = create_player(devices[1].stable_id.clone(), PlayerConfig::default()).unwrap();
 
         player
             .load_new_track(
-                "/mnt/hdd/Music/Enigma 2018 DSD/1996 -  III-Le Roi Est Mort, Vive Le Roi!/05 - Why! ....dsf".into()
+                "/mnt/hdd/Music/Enigma 2018 DSD/1996 -  III-Le Roi Est Mort, Vive Le Roi!/05 - Why! ....dsf"
             )
-            .await;
-        player.start().await;
+            .await
+            .unwrap();
+        player.start().await.unwrap();
 
 
         sleep(Duration::from_millis(1500)).await;
@@ -35,36 +788,33 @@ mod tests{
 
         player.seek(0.9f64).await.unwrap();
         sleep(Duration::from_millis(1500)).await;
-        player.pause().await;
+        player.pause().await.unwrap();
         sleep(Duration::from_millis(1500)).await;
-        player.play().await;
+        player.play().await.unwrap();
         sleep(Duration::from_millis(1500)).await;
 
         player.load_new_track(
-            "/home/larry/Desktop/sacd/RUMOURS/Stereo/07 - THE CHAIN.dff".into(),
+            "/home/larry/Desktop/sacd/RUMOURS/Stereo/07 - THE CHAIN.dff",
         )
-            .await;
+            .await.unwrap();
         sleep(Duration::from_millis(1500)).await;
 
-        player.start().await;
+        player.start().await.unwrap();
 
         sleep(Duration::from_millis(1000)).await;
 
         if let Some(meta) = player.get_current_file_meta().await{
             meta.pretty_print()
         }
+        let mut events = player.subscribe();
         player.seek(0.98f64).await.unwrap();
         loop {
-            let pos =  player.get_pos().await;
-
-            println!("Progress {}", pos);
-            if pos == 1f64{
-                player.stop().await;
-
-                break;
+            match events.recv().await.unwrap() {
+                PlayerEvent::PositionTick { position } => println!("Progress {}", position),
+                PlayerEvent::TrackEnded => break,
+                PlayerEvent::DeviceError(e) => panic!("{}", e),
+                _ => {}
             }
-            sleep(Duration::from_millis(500)).await;
-
         }
     }
 }
@@ -1,443 +1,108 @@
-#[cfg(target_os = "linux")]
-use ndsd_read;
-#[cfg(target_os = "linux")]
-use ndsd_read::{DSDFormat, DSDReader};
-#[cfg(target_os = "linux")]
-use crate::players::DSDPlayer;
-#[cfg(target_os = "linux")]
+#![cfg(target_os = "linux")]
+
+use crate::players::{
+    AudioSink, DeviceCapabilities, DeviceInfo, DsdWordFormat, FormatCapability, OutputParams, PlayerConfig, PlayerError,
+    Wakeup, XrunKind,
+};
+use crate::players::capabilities::DSD_RATES;
 use crate::utils::bit_reverse_table::BIT_REVERSE_TABLE;
-#[cfg(target_os = "linux")]
+use crate::utils::silence::DSD_SILENCE;
 use alsa_sys::{SND_PCM_NONBLOCK, SND_PCM_STREAM_PLAYBACK};
-#[cfg(target_os = "linux")]
-use atomic_float::AtomicF64;
-#[cfg(target_os = "linux")]
+use ndsd_read::DSDFormat;
 use std::ffi::{CStr, CString, c_char, c_void};
-#[cfg(target_os = "linux")]
-use std::io::{Error, ErrorKind};
-#[cfg(target_os = "linux")]
-use std::path::PathBuf;
-
-#[cfg(target_os = "linux")]
 use std::ptr;
-#[cfg(target_os = "linux")]
-use std::sync::Arc;
-#[cfg(target_os = "linux")]
-use std::sync::atomic::AtomicBool;
-#[cfg(target_os = "linux")]
-use std::sync::atomic::Ordering::Relaxed;
-use ndsd_read::DSDMeta;
-#[cfg(target_os = "linux")]
-use tokio::sync::Mutex;
-#[cfg(target_os = "linux")]
-use tokio::sync::mpsc::Sender;
-#[cfg(target_os = "linux")]
-use tokio::sync::{mpsc, mpsc::Receiver};
-
-#[cfg(target_os = "linux")]
-pub enum ControlRequest {
-    LoadTrack(PathBuf),
-    Start,
-    Stop,
-    Seek(f64),
-    Pause,
-    Play,
-    Terminate,
-}
-#[cfg(target_os = "linux")]
-
-struct PlayerState {
-    reader: Option<Box<dyn DSDReader>>,
-    format: DSDFormat,
-    setup: Option<AlsaSetup>,
-    playing: bool,
-    paused: bool,
-    first_paused: bool,
-    released_pause: bool,
-    device_name: CString,
-    alsa_buffer: Option<Vec<u8>>,
-}
-#[cfg(target_os = "linux")]
-#[allow(unused)]
-pub struct AlsaPlayer {
-    device_name: CString,
-    player_thread: std::thread::JoinHandle<()>,
-    message_channel: Sender<ControlRequest>,
-    current_pos: Arc<AtomicF64>,
-    is_playing: Arc<AtomicBool>,
-    cur_format: Arc<Mutex<DSDFormat>>,
-    cur_meta: Arc<Mutex<Option<DSDMeta>>>,
-}
-#[cfg(target_os = "linux")]
-#[async_trait::async_trait]
-impl DSDPlayer for AlsaPlayer {
-    async fn start(&mut self) {
-        self.message_channel
-            .send(ControlRequest::Start)
-            .await
-            .unwrap();
-    }
-
-    async fn pause(&self) {
-        let _ = self.message_channel.send(ControlRequest::Pause).await;
-    }
-
-    async fn play(&self) {
-        let _ = self.message_channel.send(ControlRequest::Play).await;
-    }
-
-    async fn get_pos(&self) -> f64 {
-        self.current_pos.load(Relaxed)
-    }
+use std::time::Duration;
 
-    async fn stop(&self) {
-        let _ = self.message_channel.send(ControlRequest::Stop).await;
-    }
+extern crate alsa_sys as alsa;
 
-    async fn is_playing(&self) -> bool {
-        self.is_playing.load(Relaxed)
-    }
+///Pseudo device id prefix forcing DoP output, e.g "dop:hw:1,0"
+pub const DOP_DEVICE_PREFIX: &str = "dop:";
 
-    async fn load_new_track(&mut self, filename: &str) {
-        let _ = self
-            .message_channel
-            .send(ControlRequest::LoadTrack(PathBuf::from(filename)))
-            .await;
-    }
+const DOP_MARKER_A: u8 = 0x05;
+const DOP_MARKER_B: u8 = 0xFA;
 
-    async fn seek(&mut self, percent: f64) -> Result<(), Error> {
-        let res = self
-            .message_channel
-            .send(ControlRequest::Seek(percent))
-            .await;
-        if let Err(_) = res {
-            return Err(Error::new(ErrorKind::Other, "Alsa player seek error"));
-        }
-        Ok(())
-    }
+const ENXIO: i32 = 6;
+const EBADF: i32 = 9;
+const ENODEV: i32 = 19;
+const EINVAL: i32 = 22;
+const EPIPE: i32 = 32;
+const ESTRPIPE: i32 = 86;
 
-    async fn get_format_info(&self) -> DSDFormat {
-        self.cur_format.lock().await.clone()
-    }
+///Positive errno and snd_strerror text of a negative alsa return code
+fn alsa_error(code: i32) -> (i32, String) {
+    let message = unsafe { CStr::from_ptr(alsa::snd_strerror(code)) };
+    (-code, message.to_string_lossy().into_owned())
+}
 
-    async fn get_current_file_meta(&self) -> Option<DSDMeta> {
-        self.cur_meta.lock().await.clone()
-    }
+fn setup_error(code: i32) -> PlayerError {
+    let (errno, message) = alsa_error(code);
+    PlayerError::DeviceSetup { errno, message }
 }
 
-#[cfg(target_os = "linux")]
-impl AlsaPlayer {
-    pub fn new(device_name: &str) -> Self {
-        let device = std::ffi::CString::new(device_name).unwrap();
-        let mpsc = mpsc::channel::<ControlRequest>(16);
-        let cur_pos = Arc::new(AtomicF64::new(0.));
-        let is_playing = Arc::new(AtomicBool::new(false));
-        let cur_format = Arc::new(Mutex::new(DSDFormat::default()));
-        let cur_meta = Arc::new(Mutex::new(None));
-        Self {
-            device_name: device.clone(),
-            player_thread: Self::player_main(
-                device,
-                mpsc.1,
-                cur_pos.clone(),
-                is_playing.clone(),
-                cur_format.clone(),
-                cur_meta.clone(),
-            ),
-            message_channel: mpsc.0,
-            current_pos: cur_pos,
-            is_playing,
-            cur_format,
-            cur_meta,
-        }
-    }
-
-    fn player_main(
-        device_name: CString,
-        mut channel: Receiver<ControlRequest>,
-        pos: Arc<AtomicF64>,
-        is_playing: Arc<AtomicBool>,
-        cur_format: Arc<Mutex<DSDFormat>>,
-        cur_meta: Arc<Mutex<Option<DSDMeta>>>,
-    ) -> std::thread::JoinHandle<()> {
-        std::thread::spawn(move || {
-            let mut state: PlayerState = PlayerState {
-                reader: None,
-                format: Default::default(),
-                setup: None,
-                playing: false,
-                paused: false,
-                first_paused: false,
-                released_pause: false,
-                device_name,
-                alsa_buffer: None,
-            };
-            loop {
-                if !state.playing {
-                    is_playing.store(false, Relaxed);
-                    *cur_format.blocking_lock() = DSDFormat::default();
-                    if let Some(cmd) = channel.blocking_recv() {
-                        if !Self::process_command(cmd, &mut state, cur_format.clone(), cur_meta.clone()) {
-                            break;
-                        }
-                    }
-                } else {
-                    if let Ok(cmd) = channel.try_recv() {
-                        if !Self::process_command(cmd, &mut state, cur_format.clone(), cur_meta.clone()) {
-                            break;
-                        }
-                    }
-                    Self::playback_poll(&mut state);
-                    pos.store(
-                        state.reader.as_mut().unwrap().get_position_percent(),
-                        Relaxed,
-                    );
-                    is_playing.store(true, Relaxed);
-                }
-            }
-        })
-    }
+///Device was unplugged or its driver unbound
+fn device_gone(code: i32) -> bool {
+    matches!(-code, ENODEV | EBADF | ENXIO)
+}
 
-    fn process_command(
-        command: ControlRequest,
-        state: &mut PlayerState,
-        cur_format: Arc<Mutex<DSDFormat>>,
-        cur_meta: Arc<Mutex<Option<DSDMeta>>>,
-    ) -> bool {
-        let mut setup_reload_required = false;
-        match command {
-            ControlRequest::LoadTrack(path) => {
-                let mut format = DSDFormat::default();
-                if let Ok(reader) = ndsd_read::open_dsd_auto(path.to_str().unwrap(), &mut format)
-                {
-                    state.reader = Some(reader);
-                    setup_reload_required = format.is_different(&state.format);
-                    state.format = format.clone();
-                    *cur_format.blocking_lock() = format;
-                    *cur_meta.blocking_lock() = state.reader.as_ref().unwrap().get_metadata().map(|meta| meta.clone());
-                }
-            }
-            ControlRequest::Start => {
-                if let Some(reader) = state.reader.as_mut() {
-                    if state.setup.is_none() {
-                        setup_reload_required = true;
-                    }
-                    if reader.eof() {
-                        let _ = reader.reset();
-                    }
-                    state.playing = true;
-                }
-            }
-            ControlRequest::Stop => {
-                state.playing = false;
-            }
-            ControlRequest::Seek(f64) => {
-                if let Some(reader) = state.reader.as_mut() {
-                    let _ = reader.seek_percent(f64);
-                }
-            }
-            ControlRequest::Pause => {
-                state.paused = true;
-                state.first_paused = true;
-            }
-            ControlRequest::Play => {
-                state.paused = false;
-                state.released_pause = true;
-            }
-            ControlRequest::Terminate => {
-                return false;
-            }
-        }
-        if setup_reload_required {
-            if state.setup.is_none() {
-                let res = AlsaSetup::new(state.device_name.clone());
-                if res.is_none() {
-                    eprintln!("Alsa failed ");
-                    return false;
-                }
-                state.setup = Some(res.unwrap());
-            } else {
-                state.setup.as_mut().unwrap().reprepare_alsa_sync();
-            }
-            let alsa_buffer_size = 8192 * (state.format.sampling_rate / 2822400) as usize;
-            let buffers = Buffers::new(alsa_buffer_size, state.format.num_channels as usize);
-            state.setup.as_mut().unwrap().buffers = buffers;
-            state
-                .setup
-                .as_mut()
-                .unwrap()
-                .update_hw_params(&state.format, alsa_buffer_size);
-            state.alsa_buffer = Some(vec![0u8; alsa_buffer_size]);
-        }
-        return true;
-    }
-
-    fn playback_poll(state: &mut PlayerState) -> bool {
-        if state.playing {
-            let alsa_buffer = state.alsa_buffer.as_mut().unwrap();
-            let setup = state.setup.as_mut().unwrap();
-            let reader = state.reader.as_mut().unwrap();
-            let format = &state.format;
-
-            if state.paused {
-                if state.first_paused {
-                    unsafe {
-                        alsa::snd_pcm_pause(setup.playback_handle, 1);
-                    }
-                    state.first_paused = false;
-                }
-                return false;
-            } else if state.released_pause {
-                unsafe {
-                    alsa::snd_pcm_pause(setup.playback_handle, 0);
-                }
-                state.released_pause = false;
-            }
+fn xrun_error(code: i32) -> PlayerError {
+    let (errno, message) = alsa_error(code);
+    PlayerError::Xrun { errno, message }
+}
 
-            let alsa_buffer_size = alsa_buffer.len();
-            let num_channels = format.num_channels;
+fn device_error(code: i32) -> PlayerError {
+    let (errno, message) = alsa_error(code);
+    PlayerError::Device { errno, message }
+}
 
-            let mut work_slices = setup.buffers.get_slice_for_reader();
-            let bytes =
-                match reader.read(&mut work_slices, alsa_buffer_size / num_channels as usize) {
-                    Ok(b) => b,
-                    Err(e) => {
-                        eprintln!("read error {:?}", e);
-                        return false;
-                    }
-                };
-            if bytes == 0 {
-                return false;
-            }
-            let write_frames = setup.buffers.populate_alsa_buffer(
-                alsa_buffer.as_mut_slice(),
-                bytes,
-                format.is_lsb_first,
-                setup.bytes_per_word,
-                setup.word_is_le
-            );
-            let alsa_ptr = alsa_buffer.as_ptr() as *const std::ffi::c_void;
+///DoP frames reach the DAC untouched only on hw devices, plug and dmix layers would resample or mix them
+fn takes_dop(device: &CStr) -> bool {
+    device.to_bytes().starts_with(b"hw:")
+}
 
-            let written = unsafe {
-                alsa::snd_pcm_writei(
-                    setup.playback_handle,
-                    alsa_ptr,
-                    write_frames as alsa::snd_pcm_uframes_t,
-                )
-            };
-            if written == -77 {
-                eprintln!("cannot write audio frame EBADF");
-                state.playing = false;
-                return false;
-            }
-            if written == -32 {
-                unsafe { alsa::snd_pcm_prepare(setup.playback_handle); }
-                return true;
-            }
-            if written == -86 {
-                eprintln!("cannot write audio frame ESTRPIPE");
-                state.playing = false;
-                return false;
-            }
-            if reader.eof() {
-                state.playing = false;
-            }
-            return true;
-        } else {
-            return false;
-        }
-    }
+fn micros(time: Duration) -> u32 {
+    time.as_micros().min(u32::MAX as u128) as u32
+}
 
-    pub fn support_dsd(device_name: *const c_char) -> bool {
-        let mut handle: *mut alsa::snd_pcm_t = std::ptr::null_mut();
-        let mut params: *mut alsa::snd_pcm_hw_params_t = std::ptr::null_mut();
-        let err = unsafe {
-            alsa::snd_pcm_open(
-                &mut handle,
-                device_name,
-                SND_PCM_STREAM_PLAYBACK,
-                SND_PCM_NONBLOCK,
-            )
-        };
-        if err < 0 {
-            return false;
-        }
-        unsafe {
-            alsa::snd_pcm_hw_params_malloc(&mut params);
-        }
-        unsafe {
-            alsa::snd_pcm_hw_params_any(handle, params);
-        }
-        let supported = AlsaSetup::detect_dsd_format(handle, params).is_some();
-        unsafe {
-            alsa::snd_pcm_hw_params_free(params);
-        }
-        unsafe {
-            alsa::snd_pcm_close(handle);
-        }
-        supported
+fn pcm_format(format: DsdWordFormat) -> alsa::snd_pcm_format_t {
+    match format {
+        DsdWordFormat::U8 => alsa::SND_PCM_FORMAT_DSD_U8,
+        DsdWordFormat::U16Le => alsa::SND_PCM_FORMAT_DSD_U16_LE,
+        DsdWordFormat::U16Be => alsa::SND_PCM_FORMAT_DSD_U16_BE,
+        DsdWordFormat::U32Le => alsa::SND_PCM_FORMAT_DSD_U32_LE,
+        DsdWordFormat::U32Be => alsa::SND_PCM_FORMAT_DSD_U32_BE,
+        DsdWordFormat::DopS32Le => alsa::SND_PCM_FORMAT_S32_LE,
+        DsdWordFormat::DopS24Packed => alsa::SND_PCM_FORMAT_S24_3LE,
     }
+}
 
-    pub fn enumerate_supported_devices() -> Vec<(CString, CString)> {
-        unsafe {
-            let pcm_const = CString::new("pcm").unwrap();
-            let name_const = CString::new("NAME").unwrap();
-
-            let mut devices_raw: *mut *mut c_void = std::ptr::null_mut();
-            let err = alsa::snd_device_name_hint(-1, pcm_const.as_ptr(), &mut devices_raw);
-            if err != 0 {
-                eprintln!(
-                    "Error getting device hints: {}\n",
-                    CString::from(CStr::from_ptr(alsa::snd_strerror(err)))
-                        .to_str()
-                        .unwrap()
-                );
-                return vec![];
-            }
-            let mut res = Vec::new();
-            let mut n = devices_raw;
-            let mut iter = *n;
-            while !iter.is_null() {
-                let name = alsa::snd_device_name_get_hint(iter, name_const.as_ptr());
-                if !name.is_null() {
-                    if Self::support_dsd(name) {
-                        res.push(( CStr::from_ptr(name).to_owned(),  CStr::from_ptr(name).to_owned()));
-                    }
-                }
-                n = n.offset(1);
-                iter = *n;
-            }
-            res
-        }
-    }
+///How dsd is delivered to the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AlsaOutputMode {
+    ///Native dsd formats when device has them, DoP otherwise on hw devices
+    #[default]
+    Auto,
+    ///SND_PCM_FORMAT_DSD_* only
+    Native,
+    ///DSD over PCM in S32_LE or S24_3LE frames
+    Dop,
 }
-#[cfg(target_os = "linux")]
-extern crate alsa_sys as alsa;
 
-#[cfg(target_os = "linux")]
-#[allow(unused)]
 struct Buffers {
-    work: Vec<Vec<u8>>,
-    alsa_buffer_size: usize,
+    alsa_buffer: Vec<u8>,
     num_channels: usize,
 }
-#[cfg(target_os = "linux")]
+
 impl Buffers {
     pub fn new(alsa_buffer_size: usize, num_channels: usize) -> Self {
         Self {
-            work: (0..num_channels)
-                .map(|_| vec![0u8; alsa_buffer_size / num_channels])
-                .collect(),
-            alsa_buffer_size,
+            alsa_buffer: vec![0u8; alsa_buffer_size],
             num_channels,
         }
     }
 
-    pub fn get_slice_for_reader(&mut self) -> Vec<&mut [u8]> {
-        self.work.iter_mut().map(|v| v.as_mut_slice()).collect()
-    }
     pub fn populate_alsa_buffer(
-        &self,
-        alsa_buffer: &mut [u8],
+        &mut self,
+        data: &[&[u8]],
         bytes: usize,
         lsb_first: bool,
         bytes_per_word: usize,
@@ -446,175 +111,539 @@ impl Buffers {
         let mut out = 0usize;
         let mut j = 0usize;
         while j + bytes_per_word - 1 < bytes {
-            for ch in 0..self.num_channels {
+            for channel in data.iter().take(self.num_channels) {
                 let mut word = [0u8; 4];
-                for k in 0..bytes_per_word {
-                    let byte = self.work[ch][j + k];
-                    word[k] = if lsb_first { BIT_REVERSE_TABLE[byte as usize] } else { byte };
+                for (k, w) in word.iter_mut().enumerate().take(bytes_per_word) {
+                    let byte = channel[j + k];
+                    *w = if lsb_first { BIT_REVERSE_TABLE[byte as usize] } else { byte };
                 }
                 // Byte-swap the word for LE formats
                 if word_is_le {
                     word[..bytes_per_word].reverse();
                 }
-                for k in 0..bytes_per_word {
-                    alsa_buffer[out] = word[k];
-                    out += 1;
-                }
+                self.alsa_buffer[out..out + bytes_per_word].copy_from_slice(&word[..bytes_per_word]);
+                out += bytes_per_word;
             }
             j += bytes_per_word;
         }
         (bytes / bytes_per_word) as i64
     }
-    #[allow(unused)]
-    pub fn alsa_buffer_size(&self) -> usize {
-        self.alsa_buffer_size
+
+    ///Packs 16 dsd bits per channel into every pcm frame, the marker byte alternates between frames.
+    /// sample_bytes is 4 for S32_LE and 3 for S24_3LE
+    pub fn populate_dop_buffer(
+        &mut self,
+        data: &[&[u8]],
+        bytes: usize,
+        lsb_first: bool,
+        sample_bytes: usize,
+        marker: &mut u8,
+    ) -> i64 {
+        let mut out = 0usize;
+        let mut j = 0usize;
+        while j + 1 < bytes {
+            for channel in data.iter().take(self.num_channels) {
+                let (mut first, mut second) = (channel[j], channel[j + 1]);
+                if lsb_first {
+                    first = BIT_REVERSE_TABLE[first as usize];
+                    second = BIT_REVERSE_TABLE[second as usize];
+                }
+                if sample_bytes == 4 {
+                    self.alsa_buffer[out] = 0;
+                    out += 1;
+                }
+                self.alsa_buffer[out] = second;
+                self.alsa_buffer[out + 1] = first;
+                self.alsa_buffer[out + 2] = *marker;
+                out += 3;
+            }
+            *marker = if *marker == DOP_MARKER_A { DOP_MARKER_B } else { DOP_MARKER_A };
+            j += 2;
+        }
+        (bytes / 2) as i64
     }
 }
-#[cfg(target_os = "linux")]
 
-pub struct AlsaSetup {
+///Native dsd output over alsa hw devices
+pub struct AlsaSink {
     playback_handle: *mut alsa::snd_pcm_t,
     hw_params: *mut alsa::snd_pcm_hw_params_t,
     buffers: Buffers,
     current_device: CString,
-    dsd_format: alsa::snd_pcm_format_t,
+    mode: AlsaOutputMode,
+    pcm_format: alsa::snd_pcm_format_t,
+    // Dsd bytes per channel carried by one alsa frame, 2 for DoP
     bytes_per_word: usize,
     word_is_le: bool,
+    dop: bool,
+    dop_sample_bytes: usize,
+    dop_marker: u8,
+    lsb_first: bool,
+    period_bytes: usize,
+    config: PlayerConfig,
+    output_params: Option<OutputParams>,
+    // Pcm descriptors followed by the wakeup eventfd
+    poll_fds: Vec<libc::pollfd>,
+    xrun: Option<XrunKind>,
+    // Device name by card id, resolved on the first open. Reopens use it, the card index may change on re-plugging
+    stable_device: Option<CString>,
 }
-#[cfg(target_os = "linux")]
 
-unsafe impl Send for AlsaSetup {}
-#[cfg(target_os = "linux")]
+unsafe impl Send for AlsaSink {}
+
+unsafe impl Sync for AlsaSink {}
+
+impl AudioSink for AlsaSink {
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+        if self.playback_handle.is_null() {
+            self.open_device()?;
+        } else {
+            self.reprepare_alsa_sync()?;
+        }
+        let params = self.update_hw_params(format)?;
+        let period_frames = params.period_frames as usize;
+        // DoP frame carries 2 dsd bytes in 3 or 4 pcm bytes
+        let frame_bytes = if self.dop { self.dop_sample_bytes } else { self.bytes_per_word };
+        self.buffers = Buffers::new(
+            period_frames * frame_bytes * format.num_channels as usize,
+            format.num_channels as usize,
+        );
+        self.lsb_first = format.is_lsb_first;
+        self.period_bytes = period_frames * self.bytes_per_word;
+        self.output_params = Some(params);
+        Ok(())
+    }
+
+    fn period_bytes(&self) -> usize {
+        self.period_bytes
+    }
+
+    fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+        if self.playback_handle.is_null() {
+            return Err(self.failure(-ENODEV));
+        }
+        let written = self.write_planar(data, bytes_per_channel, self.lsb_first);
+        if written >= 0 {
+            return Ok(());
+        }
+        self.recover(written as i32)?;
+        // Pcm starts again from silence instead of jumping into the music, the data is packed again after it
+        let silence = vec![DSD_SILENCE; self.period_bytes];
+        let channels: Vec<&[u8]> = (0..self.buffers.num_channels).map(|_| silence.as_slice()).collect();
+        for (data, bytes, lsb_first) in [(&channels[..], self.period_bytes, false), (data, bytes_per_channel, self.lsb_first)] {
+            let written = self.write_planar(data, bytes, lsb_first) as i32;
+            if written < 0 && device_gone(written) {
+                return Err(self.failure(written));
+            }
+            if written < 0 {
+                return Err(xrun_error(written));
+            }
+        }
+        Ok(())
+    }
+
+    fn take_xrun(&mut self) -> Option<XrunKind> {
+        self.xrun.take()
+    }
 
-unsafe impl Sync for AlsaSetup {}
-#[cfg(target_os = "linux")]
+    fn needs_silence(&self) -> bool {
+        true
+    }
 
-impl AlsaSetup {
-    pub fn new(device: CString) -> Option<Self> {
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+        let err = unsafe { alsa::snd_pcm_pause(self.playback_handle, paused as i32) };
+        if err < 0 {
+            // Not every device can pause, stop it instead and start over with the next write.
+            // Running pcm was not paused at all and is left alone
+            let err = unsafe {
+                if paused {
+                    alsa::snd_pcm_drop(self.playback_handle)
+                } else if alsa::snd_pcm_state(self.playback_handle) != alsa::SND_PCM_STATE_RUNNING {
+                    alsa::snd_pcm_prepare(self.playback_handle)
+                } else {
+                    0
+                }
+            };
+            if err < 0 {
+                return Err(self.failure(err));
+            }
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<(), PlayerError> {
+        unsafe {
+            let err = alsa::snd_pcm_drain(self.playback_handle);
+            // Drain leaves pcm in SETUP state, next track must be able to write right away
+            let prepared = alsa::snd_pcm_prepare(self.playback_handle);
+            if err < 0 {
+                return Err(self.failure(err));
+            }
+            if prepared < 0 {
+                return Err(self.failure(prepared));
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PlayerError> {
         unsafe {
-            let buffers = Buffers::new(1, 2);
-            let err: i32;
-            let mut playback_handle: *mut alsa::snd_pcm_t = ptr::null_mut();
-            let hw_params: *mut alsa::snd_pcm_hw_params_t = ptr::null_mut();
+            alsa::snd_pcm_drop(self.playback_handle);
+            let err = alsa::snd_pcm_prepare(self.playback_handle);
+            if err < 0 {
+                return Err(self.failure(err));
+            }
+        }
+        Ok(())
+    }
+
+    fn delay(&self) -> usize {
+        let mut frames: alsa::snd_pcm_sframes_t = 0;
+        if unsafe { alsa::snd_pcm_delay(self.playback_handle, &mut frames) } < 0 {
+            return 0;
+        }
+        frames.max(0) as usize * self.bytes_per_word
+    }
+
+    fn output_params(&self) -> Option<OutputParams> {
+        self.output_params
+    }
+
+    fn wait_writable(&mut self, wakeup: &Wakeup, timeout: Duration) -> Result<bool, PlayerError> {
+        unsafe {
+            let count = alsa::snd_pcm_poll_descriptors_count(self.playback_handle);
+            if count <= 0 || wakeup.fd() < 0 {
+                return Ok(true);
+            }
+            let empty = libc::pollfd { fd: -1, events: 0, revents: 0 };
+            self.poll_fds.resize(count as usize + 1, empty);
+            let filled = alsa::snd_pcm_poll_descriptors(self.playback_handle, self.poll_fds.as_mut_ptr(), count as _);
+            if filled < 0 {
+                return Err(self.failure(filled));
+            }
+            let pcm_fds = filled as usize;
+            self.poll_fds.truncate(pcm_fds);
+            self.poll_fds.push(libc::pollfd { fd: wakeup.fd(), events: libc::POLLIN, revents: 0 });
+            let ready = libc::poll(self.poll_fds.as_mut_ptr(), self.poll_fds.len() as _, timeout.as_millis() as _);
+            if ready < 0 {
+                // Interrupted by a signal, the caller simply waits again
+                return Ok(false);
+            }
+            if ready == 0 || self.poll_fds[pcm_fds].revents != 0 {
+                return Ok(false);
+            }
+            let mut revents: libc::c_ushort = 0;
+            let err = alsa::snd_pcm_poll_descriptors_revents(
+                self.playback_handle,
+                self.poll_fds.as_mut_ptr(),
+                pcm_fds as _,
+                &mut revents,
+            );
+            if err < 0 {
+                return Err(self.failure(err));
+            }
+            // On error or disconnect the write runs into it and reports or recovers it
+            Ok(revents & (libc::POLLOUT | libc::POLLERR | libc::POLLHUP) as libc::c_ushort != 0)
+        }
+    }
+}
+
+impl AlsaSink {
+    pub fn new(device: CString) -> Self {
+        Self::with_mode(device, AlsaOutputMode::Auto)
+    }
+
+    pub fn with_mode(device: CString, mode: AlsaOutputMode) -> Self {
+        Self::with_config(device, mode, PlayerConfig::default())
+    }
+
+    ///Buffer, period and start threshold are requested from the device as the config asks
+    pub fn with_config(device: CString, mode: AlsaOutputMode, config: PlayerConfig) -> Self {
+        Self {
+            playback_handle: ptr::null_mut(),
+            hw_params: ptr::null_mut(),
+            buffers: Buffers::new(1, 2),
+            current_device: device,
+            mode,
+            pcm_format: alsa::SND_PCM_FORMAT_DSD_U32_LE,
+            bytes_per_word: 4,
+            word_is_le: true,
+            dop: false,
+            dop_sample_bytes: 4,
+            dop_marker: DOP_MARKER_A,
+            lsb_first: false,
+            period_bytes: 0,
+            config,
+            output_params: None,
+            poll_fds: Vec::new(),
+            xrun: None,
+            stable_device: None,
+        }
+    }
+
+    ///Parses device id, "dop:" prefix forces DoP output
+    pub fn from_device_id(device_id: CString, config: &PlayerConfig) -> Self {
+        let id = device_id.to_string_lossy();
+        match id.strip_prefix(DOP_DEVICE_PREFIX) {
+            Some(name) => Self::with_config(CString::new(name).unwrap(), AlsaOutputMode::Dop, config.clone()),
+            None => Self::with_config(device_id, AlsaOutputMode::Auto, config.clone()),
+        }
+    }
 
-            err = alsa::snd_pcm_open(
-                &mut playback_handle,
+    fn open_device(&mut self) -> Result<(), PlayerError> {
+        let device = self.stable_device.as_ref().unwrap_or(&self.current_device);
+        let err = unsafe {
+            alsa::snd_pcm_open(
+                &mut self.playback_handle,
                 device.as_ptr(),
                 alsa::SND_PCM_STREAM_PLAYBACK,
                 0,
-            );
-            if err < 0 {
-                eprintln!("cannot open audio device: {}", err);
-                return None;
-            }
-            let mut res = Self {
-                playback_handle,
-                hw_params,
-                buffers,
-                current_device: device,
-                dsd_format: alsa::SND_PCM_FORMAT_DSD_U32_LE,
-                bytes_per_word: 4,
-                word_is_le: true,
-            };
-            res.setup_params();
-            Some(res)
+            )
+        };
+        if err < 0 {
+            self.playback_handle = ptr::null_mut();
+            let (errno, message) = alsa_error(err);
+            return Err(PlayerError::DeviceOpen {
+                device: device.to_string_lossy().into_owned(),
+                errno,
+                message,
+            });
+        }
+        if self.stable_device.is_none() {
+            self.stable_device = stable_device_name(&self.current_device.to_string_lossy()).and_then(|name| CString::new(name).ok());
+        }
+        self.setup_params()
+    }
+
+    ///Error of a failed pcm call, a device which is gone is closed so the next open starts from scratch
+    fn failure(&mut self, code: i32) -> PlayerError {
+        if !device_gone(code) {
+            return device_error(code);
+        }
+        if !self.playback_handle.is_null() {
+            // Drain would wait for a device which no longer plays
+            unsafe { alsa::snd_pcm_close(self.playback_handle) };
+            self.playback_handle = ptr::null_mut();
+        }
+        self.output_params = None;
+        let (errno, message) = alsa_error(code);
+        PlayerError::DeviceLost {
+            device: self.current_device.to_string_lossy().into_owned(),
+            errno,
+            message,
+        }
+    }
+
+    ///Packs planar dsd into the device format and writes it, returns snd_pcm_writei result
+    fn write_planar(&mut self, data: &[&[u8]], bytes_per_channel: usize, lsb_first: bool) -> alsa::snd_pcm_sframes_t {
+        let write_frames = if self.dop {
+            self.buffers.populate_dop_buffer(
+                data,
+                bytes_per_channel,
+                lsb_first,
+                self.dop_sample_bytes,
+                &mut self.dop_marker,
+            )
+        } else {
+            self.buffers.populate_alsa_buffer(
+                data,
+                bytes_per_channel,
+                lsb_first,
+                self.bytes_per_word,
+                self.word_is_le,
+            )
+        };
+        let alsa_ptr = self.buffers.alsa_buffer.as_ptr() as *const c_void;
+        unsafe { alsa::snd_pcm_writei(self.playback_handle, alsa_ptr, write_frames as alsa::snd_pcm_uframes_t) }
+    }
+
+    ///Brings the pcm back after an underrun or suspend, waits for the resume and prepares it if the device can not resume
+    fn recover(&mut self, code: i32) -> Result<(), PlayerError> {
+        let kind = match code {
+            c if c == -EPIPE => XrunKind::Underrun,
+            c if c == -ESTRPIPE => XrunKind::Suspend,
+            _ => return Err(self.failure(code)),
+        };
+        let err = unsafe { alsa::snd_pcm_recover(self.playback_handle, code, 1) };
+        if err < 0 {
+            return Err(xrun_error(err));
         }
+        self.xrun = Some(kind);
+        Ok(())
     }
-    fn reprepare_alsa_sync(&mut self) {
+
+    fn reprepare_alsa_sync(&mut self) -> Result<(), PlayerError> {
         unsafe {
-            let err: i32;
             alsa::snd_pcm_drain(self.playback_handle);
             alsa::snd_pcm_close(self.playback_handle);
-            err = alsa::snd_pcm_open(
-                &mut self.playback_handle,
-                self.current_device.as_ptr(),
-                alsa::SND_PCM_STREAM_PLAYBACK,
-                0,
-            );
-            if err < 0 {
-                panic!("cannot open audio device: {}", err);
-            }
-            self.setup_params();
         }
+        self.playback_handle = ptr::null_mut();
+        self.open_device()
     }
 
-    fn setup_params(&mut self) {
+    fn setup_params(&mut self) -> Result<(), PlayerError> {
         unsafe {
             if !self.hw_params.is_null() {
                 alsa::snd_pcm_hw_params_free(self.hw_params);
+                self.hw_params = ptr::null_mut();
             }
-            if alsa::snd_pcm_hw_params_malloc(&mut self.hw_params) < 0 {
-                panic!("cannot allocate hardware parameter structure");
+            let err = alsa::snd_pcm_hw_params_malloc(&mut self.hw_params);
+            if err < 0 {
+                return Err(setup_error(err));
             }
-            if alsa::snd_pcm_hw_params_any(self.playback_handle.clone(), self.hw_params.clone()) < 0
-            {
-                panic!("cannot initialize hardware parameter structure");
+            let err = alsa::snd_pcm_hw_params_any(self.playback_handle, self.hw_params);
+            if err < 0 {
+                return Err(setup_error(err));
             }
-            if alsa::snd_pcm_hw_params_set_access(
-                self.playback_handle.clone(),
-                self.hw_params.clone(),
+            let err = alsa::snd_pcm_hw_params_set_access(
+                self.playback_handle,
+                self.hw_params,
                 alsa::SND_PCM_ACCESS_RW_INTERLEAVED,
-            ) < 0
-            {
-                panic!("cannot set access type");
+            );
+            if err < 0 {
+                return Err(setup_error(err));
             }
         }
+        Ok(())
     }
 
-    fn update_hw_params(&mut self, format: &DSDFormat, alsa_buffer_size: usize) {
+    fn update_hw_params(&mut self, format: &DSDFormat) -> Result<OutputParams, PlayerError> {
         unsafe {
-            // Detect the best supported DSD format for this device
-            let dsd_fmt = Self::detect_dsd_format(self.playback_handle, self.hw_params)
-                .expect("no supported DSD format found");
-            self.dsd_format = dsd_fmt;
-            self.bytes_per_word = match dsd_fmt {
+            // Detect the best supported DSD format for this device, fall back to DoP on hw devices.
+            // Forced DoP mode trusts the caller with the device
+            let native = if self.mode != AlsaOutputMode::Dop {
+                Self::detect_dsd_format(self.playback_handle, self.hw_params)
+            } else {
+                None
+            };
+            let dop_allowed = match self.mode {
+                AlsaOutputMode::Auto => takes_dop(&self.current_device),
+                AlsaOutputMode::Native => false,
+                AlsaOutputMode::Dop => true,
+            };
+            let dop = if native.is_none() && dop_allowed {
+                Self::detect_dop_format(
+                    self.playback_handle,
+                    self.hw_params,
+                    Some(format.sampling_rate / 16),
+                )
+            } else {
+                None
+            };
+            let Some(pcm_fmt) = native.or(dop) else {
+                let (errno, message) = alsa_error(-EINVAL);
+                return Err(PlayerError::UnsupportedFormat { errno, message });
+            };
+            self.pcm_format = pcm_fmt;
+            self.dop = dop.is_some();
+            self.dop_marker = DOP_MARKER_A;
+            self.bytes_per_word = match pcm_fmt {
                 alsa::SND_PCM_FORMAT_DSD_U8 => 1,
                 alsa::SND_PCM_FORMAT_DSD_U16_BE | alsa::SND_PCM_FORMAT_DSD_U16_LE => 2,
                 alsa::SND_PCM_FORMAT_DSD_U32_LE | alsa::SND_PCM_FORMAT_DSD_U32_BE => 4,
-                _ => panic!("unsupported DSD format"),
+                alsa::SND_PCM_FORMAT_S32_LE | alsa::SND_PCM_FORMAT_S24_3LE => 2,
+                _ => unreachable!("format is one of the detected candidates"),
             };
+            self.dop_sample_bytes = if pcm_fmt == alsa::SND_PCM_FORMAT_S24_3LE { 3 } else { 4 };
             self.word_is_le = matches!(
-                dsd_fmt,
+                pcm_fmt,
                 alsa::SND_PCM_FORMAT_DSD_U32_LE | alsa::SND_PCM_FORMAT_DSD_U16_LE
             );
-            // Rate is DSD bit-rate divided by bits-per-word (8 for U8, 16 for U16, 32 for U32)
+            // Rate is DSD bit-rate divided by bits-per-word (8 for U8, 16 for U16 and DoP, 32 for U32)
             let rate = format.sampling_rate / 8 / self.bytes_per_word as u32;
-            if alsa::snd_pcm_hw_params_set_rate(self.playback_handle, self.hw_params, rate, 0) < 0 {
-                panic!("cannot set sample rate");
+            let err = alsa::snd_pcm_hw_params_set_rate(self.playback_handle, self.hw_params, rate, 0);
+            if err < 0 {
+                let (errno, message) = alsa_error(err);
+                return Err(PlayerError::UnsupportedRate { rate, errno, message });
             }
-            if alsa::snd_pcm_hw_params_set_channels(
+            let err = alsa::snd_pcm_hw_params_set_channels(
                 self.playback_handle,
                 self.hw_params,
                 format.num_channels,
-            ) < 0
-            {
-                panic!("cannot set channel count");
+            );
+            if err < 0 {
+                let (errno, message) = alsa_error(err);
+                return Err(PlayerError::UnsupportedChannels {
+                    channels: format.num_channels,
+                    errno,
+                    message,
+                });
             }
-            if alsa::snd_pcm_hw_params_set_format(self.playback_handle, self.hw_params, dsd_fmt) < 0
-            {
-                panic!("cannot set sample format");
+            let err = alsa::snd_pcm_hw_params_set_format(self.playback_handle, self.hw_params, pcm_fmt);
+            if err < 0 {
+                let (errno, message) = alsa_error(err);
+                return Err(PlayerError::UnsupportedFormat { errno, message });
             }
 
-            let mut frames: alsa::snd_pcm_uframes_t =
-                (alsa_buffer_size / format.num_channels as usize / self.bytes_per_word)
-                    as alsa::snd_pcm_uframes_t;
+            // Times are asked for instead of sizes, so every rate and word format gets the same latency
+            let mut buffer_time = micros(self.config.get_buffer_time());
+            let mut period_time = micros(self.config.get_period_time());
             let mut dir: i32 = 0;
-            alsa::snd_pcm_hw_params_set_period_size_near(
+            let err = alsa::snd_pcm_hw_params_set_buffer_time_near(
+                self.playback_handle,
+                self.hw_params,
+                &mut buffer_time,
+                &mut dir,
+            );
+            if err < 0 {
+                return Err(setup_error(err));
+            }
+            let err = alsa::snd_pcm_hw_params_set_period_time_near(
                 self.playback_handle,
                 self.hw_params,
-                &mut frames,
+                &mut period_time,
                 &mut dir,
             );
+            if err < 0 {
+                return Err(setup_error(err));
+            }
             let err = alsa::snd_pcm_hw_params(self.playback_handle, self.hw_params);
             if err < 0 {
-                panic!("cannot set parameters {}", err);
+                return Err(setup_error(err));
+            }
+            let (mut buffer_frames, mut period_frames) = (0, 0);
+            alsa::snd_pcm_hw_params_get_buffer_size(self.hw_params, &mut buffer_frames);
+            alsa::snd_pcm_hw_params_get_period_size(self.hw_params, &mut period_frames, &mut dir);
+            if period_frames == 0 {
+                let (errno, message) = alsa_error(-EINVAL);
+                return Err(PlayerError::DeviceSetup { errno, message });
             }
-            if alsa::snd_pcm_prepare(self.playback_handle) < 0 {
-                panic!("cannot prepare audio interface for use");
+            let start_threshold = (self.config.get_start_threshold().as_secs_f64() * rate as f64) as u64;
+            let start_threshold = start_threshold.clamp(1, buffer_frames as _);
+            self.update_sw_params(start_threshold, period_frames as _)?;
+            let err = alsa::snd_pcm_prepare(self.playback_handle);
+            if err < 0 {
+                return Err(setup_error(err));
             }
+            Ok(OutputParams::new(rate, buffer_frames as _, period_frames as _, start_threshold))
         }
     }
 
+    fn update_sw_params(&mut self, start_threshold: u64, avail_min: u64) -> Result<(), PlayerError> {
+        unsafe {
+            let mut sw_params: *mut alsa::snd_pcm_sw_params_t = ptr::null_mut();
+            let err = alsa::snd_pcm_sw_params_malloc(&mut sw_params);
+            if err < 0 {
+                return Err(setup_error(err));
+            }
+            let mut err = alsa::snd_pcm_sw_params_current(self.playback_handle, sw_params);
+            if err >= 0 {
+                err = alsa::snd_pcm_sw_params_set_start_threshold(self.playback_handle, sw_params, start_threshold as _);
+            }
+            if err >= 0 {
+                err = alsa::snd_pcm_sw_params_set_avail_min(self.playback_handle, sw_params, avail_min as _);
+            }
+            if err >= 0 {
+                err = alsa::snd_pcm_sw_params(self.playback_handle, sw_params);
+            }
+            alsa::snd_pcm_sw_params_free(sw_params);
+            if err < 0 {
+                return Err(setup_error(err));
+            }
+        }
+        Ok(())
+    }
+
     fn detect_dsd_format(
         handle: *mut alsa::snd_pcm_t,
         params: *mut alsa::snd_pcm_hw_params_t,
@@ -637,14 +666,359 @@ impl AlsaSetup {
         }
         None
     }
+
+    ///Pcm format usable for DoP, rate is the pcm rate (dsd rate / 16) if it has to be checked as well
+    fn detect_dop_format(
+        handle: *mut alsa::snd_pcm_t,
+        params: *mut alsa::snd_pcm_hw_params_t,
+        rate: Option<u32>,
+    ) -> Option<alsa::snd_pcm_format_t> {
+        if let Some(rate) = rate
+            && unsafe { alsa::snd_pcm_hw_params_test_rate(handle, params, rate, 0) } != 0
+        {
+            return None;
+        }
+        let candidates = [alsa::SND_PCM_FORMAT_S32_LE, alsa::SND_PCM_FORMAT_S24_3LE];
+
+        candidates.into_iter().find(|&fmt| unsafe {
+            alsa::snd_pcm_hw_params_test_format(handle, params, fmt) == 0
+        })
+    }
+
+    /// # Safety
+    /// device_name must point to a valid nul terminated string
+    pub unsafe fn support_dsd(device_name: *const c_char) -> bool {
+        unsafe {
+            Self::probe_hw_params(device_name, |handle, params| {
+                Self::detect_dsd_format(handle, params).is_some()
+            })
+        }
+    }
+
+    ///Checks if device can take DoP at least at DSD64 rate.
+    /// Only direct hw devices are considered, plug layers would resample the DoP frames
+    /// # Safety
+    /// device_name must point to a valid nul terminated string
+    pub unsafe fn support_dop(device_name: *const c_char) -> bool {
+        if !takes_dop(unsafe { CStr::from_ptr(device_name) }) {
+            return false;
+        }
+        unsafe {
+            Self::probe_hw_params(device_name, |handle, params| {
+                Self::detect_dop_format(handle, params, Some(2822400 / 16)).is_some()
+            })
+        }
+    }
+
+    ///Probes every dsd word format and rate the device accepts, "dop:" prefix is ignored.
+    /// DoP formats are reported for hw devices only, same as in enumerate_supported_devices
+    pub fn capabilities(device: &CStr) -> Result<DeviceCapabilities, PlayerError> {
+        let id = device.to_string_lossy();
+        let name = CString::new(id.strip_prefix(DOP_DEVICE_PREFIX).unwrap_or(&id)).unwrap();
+        let mut handle: *mut alsa::snd_pcm_t = ptr::null_mut();
+        let err = unsafe {
+            alsa::snd_pcm_open(&mut handle, name.as_ptr(), SND_PCM_STREAM_PLAYBACK, SND_PCM_NONBLOCK)
+        };
+        if err < 0 {
+            let (errno, message) = alsa_error(err);
+            return Err(PlayerError::DeviceOpen {
+                device: name.to_string_lossy().into_owned(),
+                errno,
+                message,
+            });
+        }
+        let mut params: *mut alsa::snd_pcm_hw_params_t = ptr::null_mut();
+        let mut scratch: *mut alsa::snd_pcm_hw_params_t = ptr::null_mut();
+        let res = unsafe {
+            let err = alsa::snd_pcm_hw_params_malloc(&mut params);
+            let err = if err < 0 { err } else { alsa::snd_pcm_hw_params_malloc(&mut scratch) };
+            let err = if err < 0 { err } else { alsa::snd_pcm_hw_params_any(handle, params) };
+            if err < 0 {
+                Err(setup_error(err))
+            } else {
+                let candidates = if takes_dop(&name) {
+                    [&DsdWordFormat::NATIVE[..], &DsdWordFormat::DOP[..]].concat()
+                } else {
+                    DsdWordFormat::NATIVE.to_vec()
+                };
+                let formats = candidates
+                    .into_iter()
+                    .filter_map(|format| {
+                        alsa::snd_pcm_hw_params_copy(scratch, params);
+                        if alsa::snd_pcm_hw_params_set_format(handle, scratch, pcm_format(format)) < 0 {
+                            return None;
+                        }
+                        let rates: Vec<u32> = DSD_RATES
+                            .into_iter()
+                            .filter(|&rate| {
+                                alsa::snd_pcm_hw_params_test_rate(handle, scratch, format.frame_rate(rate), 0) == 0
+                            })
+                            .collect();
+                        (!rates.is_empty()).then_some(FormatCapability { format, rates })
+                    })
+                    .collect();
+                let (mut channels_min, mut channels_max) = (0, 0);
+                alsa::snd_pcm_hw_params_get_channels_min(params, &mut channels_min);
+                alsa::snd_pcm_hw_params_get_channels_max(params, &mut channels_max);
+                let (mut buffer_min, mut buffer_max) = (0, 0);
+                alsa::snd_pcm_hw_params_get_buffer_size_min(params, &mut buffer_min);
+                alsa::snd_pcm_hw_params_get_buffer_size_max(params, &mut buffer_max);
+                let (mut period_min, mut period_max, mut dir) = (0, 0, 0);
+                alsa::snd_pcm_hw_params_get_period_size_min(params, &mut period_min, &mut dir);
+                alsa::snd_pcm_hw_params_get_period_size_max(params, &mut period_max, &mut dir);
+                Ok(DeviceCapabilities {
+                    formats,
+                    channels: channels_min..=channels_max,
+                    buffer_frames: buffer_min as _..=buffer_max as _,
+                    period_frames: period_min as _..=period_max as _,
+                })
+            }
+        };
+        unsafe {
+            if !scratch.is_null() {
+                alsa::snd_pcm_hw_params_free(scratch);
+            }
+            if !params.is_null() {
+                alsa::snd_pcm_hw_params_free(params);
+            }
+            alsa::snd_pcm_close(handle);
+        }
+        res
+    }
+
+    unsafe fn probe_hw_params(
+        device_name: *const c_char,
+        probe: impl FnOnce(*mut alsa::snd_pcm_t, *mut alsa::snd_pcm_hw_params_t) -> bool,
+    ) -> bool {
+        let mut handle: *mut alsa::snd_pcm_t = std::ptr::null_mut();
+        let mut params: *mut alsa::snd_pcm_hw_params_t = std::ptr::null_mut();
+        let err = unsafe {
+            alsa::snd_pcm_open(
+                &mut handle,
+                device_name,
+                SND_PCM_STREAM_PLAYBACK,
+                SND_PCM_NONBLOCK,
+            )
+        };
+        if err < 0 {
+            return false;
+        }
+        unsafe {
+            alsa::snd_pcm_hw_params_malloc(&mut params);
+        }
+        unsafe {
+            alsa::snd_pcm_hw_params_any(handle, params);
+        }
+        let supported = probe(handle, params);
+        unsafe {
+            alsa::snd_pcm_hw_params_free(params);
+        }
+        unsafe {
+            alsa::snd_pcm_close(handle);
+        }
+        supported
+    }
+
+    ///Devices with native dsd formats, followed by hw devices able to take DoP.
+    /// Returns nothing if alsa can not list the devices
+    pub fn enumerate_supported_devices() -> Vec<DeviceInfo> {
+        let mut hints: *mut *mut c_void = ptr::null_mut();
+        if unsafe { alsa::snd_device_name_hint(-1, c"pcm".as_ptr(), &mut hints) } != 0 {
+            return vec![];
+        }
+        let mut native = Vec::new();
+        let mut dop = Vec::new();
+        let mut n = hints;
+        unsafe {
+            while !(*n).is_null() {
+                let hint = *n;
+                if let Some(name) = take_hint(hint, c"NAME") {
+                    let name = CString::new(name).unwrap();
+                    if Self::support_dsd(name.as_ptr()) {
+                        native.push(Self::device_info(hint, name, false));
+                    } else if Self::support_dop(name.as_ptr()) {
+                        dop.push(Self::device_info(hint, name, true));
+                    }
+                }
+                n = n.offset(1);
+            }
+            alsa::snd_device_name_free_hint(hints);
+        }
+        native.extend(dop);
+        native
+    }
+
+    unsafe fn device_info(hint: *const c_void, id: CString, dop: bool) -> DeviceInfo {
+        let description = unsafe { take_hint(hint, c"DESC") }.unwrap_or_default();
+        let mut lines = description.lines();
+        let card_description = lines.next().unwrap_or_default().to_string();
+        let device_description = lines.next().unwrap_or_default().to_string();
+        let ioid = unsafe { take_hint(hint, c"IOID") };
+        let name = id.to_string_lossy().into_owned();
+        let (card, device) = parse_card_device(&name);
+        let card_index = card.and_then(card_index);
+        let (card_id, driver) = card_index.map(card_info).unwrap_or_default();
+        let stable_id = match card_id {
+            Some(card_id) => CString::new(stable_name(&name, &card_id, device)).unwrap(),
+            // Virtual devices like "default" are not bound to a card number
+            None => id.clone(),
+        };
+        DeviceInfo {
+            id,
+            stable_id,
+            card_description,
+            device_description,
+            ioid,
+            card_index,
+            driver,
+            dop,
+        }
+    }
+}
+
+///Copies the hint value and frees the string alsa allocated for it
+unsafe fn take_hint(hint: *const c_void, id: &CStr) -> Option<String> {
+    let value = unsafe { alsa::snd_device_name_get_hint(hint, id.as_ptr()) };
+    if value.is_null() {
+        return None;
+    }
+    let res = unsafe { CStr::from_ptr(value) }.to_string_lossy().into_owned();
+    unsafe { libc::free(value as *mut c_void) };
+    Some(res)
+}
+
+///Card and device of an alsa device name, e.g "hw:1,0", "hw:CARD=D10s,DEV=0" or "front:CARD=PCH".
+/// Card is either the index or the card id
+fn parse_card_device(name: &str) -> (Option<&str>, Option<u32>) {
+    let Some((_, args)) = name.split_once(':') else {
+        return (None, None);
+    };
+    let mut card = None;
+    let mut device = None;
+    for (position, arg) in args.split(',').enumerate() {
+        match arg.split_once('=') {
+            Some(("CARD", value)) => card = Some(value),
+            Some(("DEV", value)) => device = value.parse().ok(),
+            Some(_) => {}
+            None if position == 0 => card = Some(arg),
+            None if position == 1 => device = arg.parse().ok(),
+            None => {}
+        }
+    }
+    (card, device)
+}
+
+///Index of the card given by index or id, None if there is no such card
+fn card_index(card: &str) -> Option<i32> {
+    let card = CString::new(card).ok()?;
+    let index = unsafe { alsa::snd_card_get_index(card.as_ptr()) };
+    (index >= 0).then_some(index)
+}
+
+///Stable name of the device, None for devices which are not bound to a card
+fn stable_device_name(name: &str) -> Option<String> {
+    let (card, device) = parse_card_device(name);
+    let card_id = card_info(card_index(card?)?).0?;
+    Some(stable_name(name, &card_id, device))
 }
-#[cfg(target_os = "linux")]
 
-impl Drop for AlsaSetup {
+///Card id and driver name of the card
+fn card_info(index: i32) -> (Option<String>, Option<String>) {
+    let Ok(name) = CString::new(format!("hw:{}", index)) else {
+        return (None, None);
+    };
+    let mut ctl: *mut alsa::snd_ctl_t = ptr::null_mut();
+    let mut info: *mut alsa::snd_ctl_card_info_t = ptr::null_mut();
+    unsafe {
+        if alsa::snd_ctl_open(&mut ctl, name.as_ptr(), 0) < 0 {
+            return (None, None);
+        }
+        let mut res = (None, None);
+        if alsa::snd_ctl_card_info_malloc(&mut info) >= 0 {
+            if alsa::snd_ctl_card_info(ctl, info) >= 0 {
+                let text = |value: *const c_char| CStr::from_ptr(value).to_string_lossy().into_owned();
+                res = (
+                    Some(text(alsa::snd_ctl_card_info_get_id(info))),
+                    Some(text(alsa::snd_ctl_card_info_get_driver(info))),
+                );
+            }
+            alsa::snd_ctl_card_info_free(info);
+        }
+        alsa::snd_ctl_close(ctl);
+        res
+    }
+}
+
+///Device name addressing the card by its id instead of the index, alsa keeps the id across reboots and re-plugging
+fn stable_name(name: &str, card_id: &str, device: Option<u32>) -> String {
+    let interface = name.split_once(':').map_or(name, |(interface, _)| interface);
+    match device {
+        Some(device) => format!("{}:CARD={},DEV={}", interface, card_id, device),
+        None => format!("{}:CARD={}", interface, card_id),
+    }
+}
+
+impl Drop for AlsaSink {
     fn drop(&mut self) {
+        if !self.hw_params.is_null() {
+            unsafe {
+                alsa::snd_pcm_hw_params_free(self.hw_params);
+            }
+        }
+        if self.playback_handle.is_null() {
+            return;
+        }
         unsafe {
             alsa::snd_pcm_drain(self.playback_handle);
             alsa::snd_pcm_close(self.playback_handle);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dop_frames_carry_alternating_markers() {
+        let left = [0x11u8, 0x22, 0x33, 0x44];
+        let right = [0xAAu8, 0xBB, 0xCC, 0xDD];
+        let mut buffers = Buffers::new(4 * 2 * 2, 2);
+        let mut marker = DOP_MARKER_A;
+        let frames = buffers.populate_dop_buffer(&[&left, &right], 4, false, 4, &mut marker);
+        assert_eq!(frames, 2);
+        assert_eq!(
+            buffers.alsa_buffer,
+            vec![
+                0x00, 0x22, 0x11, 0x05, 0x00, 0xBB, 0xAA, 0x05, //
+                0x00, 0x44, 0x33, 0xFA, 0x00, 0xDD, 0xCC, 0xFA,
+            ]
+        );
+        // Marker keeps alternating across writes
+        assert_eq!(marker, DOP_MARKER_A);
+
+        let mut buffers = Buffers::new(3 * 2, 2);
+        let mut marker = DOP_MARKER_B;
+        buffers.populate_dop_buffer(&[&[0x01, 0x80], &[0x80, 0x01]], 2, true, 3, &mut marker);
+        assert_eq!(buffers.alsa_buffer, vec![0x01, 0x80, 0xFA, 0x80, 0x01, 0xFA]);
+    }
+
+    #[test]
+    fn stable_names_use_card_id() {
+        assert_eq!(parse_card_device("hw:1,0"), (Some("1"), Some(0)));
+        assert_eq!(parse_card_device("hw:CARD=D10s,DEV=2"), (Some("D10s"), Some(2)));
+        assert_eq!(parse_card_device("front:CARD=PCH"), (Some("PCH"), None));
+        assert_eq!(parse_card_device("default"), (None, None));
+        assert_eq!(stable_name("hw:1,0", "D10s", Some(0)), "hw:CARD=D10s,DEV=0");
+        assert_eq!(stable_name("front:CARD=1", "PCH", None), "front:CARD=PCH");
+    }
+
+    #[test]
+    fn dop_falls_back_on_hw_devices_only() {
+        assert!(takes_dop(c"hw:1,0"));
+        assert!(takes_dop(c"hw:CARD=D10s,DEV=0"));
+        assert!(!takes_dop(c"plughw:1,0"));
+        assert!(!takes_dop(c"default"));
+        assert!(!takes_dop(c"dmix:CARD=PCH"));
+    }
+}
@@ -2,8 +2,12 @@
 #![cfg(target_os = "windows")]
 
 use ndsd_read::{DSDFormat, DSDReader, DSDMeta};
-use crate::players::DSDPlayer;
+use crate::players::event::EventBus;
+use crate::players::engine::track_length;
+use crate::players::{BufferLevel, ClosePolicy, DSDPlayer, DeviceInfo, OutputParams, PlaybackTime, PlayerConfig, PlayerError, PlayerEvent, SeekDirection, XrunStats};
+use std::time::Duration;
 use crate::semaphore::Semaphore;
+use crate::utils::silence::{PendingSilence, fill_silence, silence_bytes};
 
 use ndsd_asio_sys::bindings::asio_import as ai;
 use ndsd_asio_sys::bindings::errors::AsioErrorWrapper;
@@ -15,7 +19,6 @@ use ndsd_asio_sys::AsioMessageSelectors::{
 };
 use ndsd_asio_sys::AsioSampleType::{ASIOSTDSDInt8LSB1, ASIOSTDSDInt8MSB1, ASIOSTDSDInt8NER8};
 use std::ffi::{CStr, CString, c_char, c_double, c_long, c_void};
-use std::io;
 use std::ptr::null_mut;
 use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
 
@@ -459,6 +462,16 @@ pub struct AsioDsdPlayer {
     stopped: AtomicBool,
     is_playing: AtomicBool,
     need_bit_reverse: bool,
+    events: EventBus,
+    // Track following the current one, taken over in the buffer switch when the format allows it
+    next: Option<(Box<dyn DSDReader>, DSDFormat, u64)>,
+    // Bytes per channel of the current track
+    length: u64,
+    preroll: Duration,
+    mute: Duration,
+    // Played by the buffer switch before the track continues
+    silence: PendingSilence,
+    close_policy: ClosePolicy,
 }
 
 unsafe impl Send for AsioDsdPlayer {}
@@ -466,18 +479,33 @@ unsafe impl Sync for AsioDsdPlayer {}
 
 
 impl AsioDsdPlayer {
-    pub fn enumerate_supported_devices() -> Vec<(CString, CString)> {
+    ///Driver names are taken from the registry and do not change, so they are the stable ids as well
+    pub fn enumerate_supported_devices() -> Vec<DeviceInfo> {
         let asio = ndsd_asio_sys::bindings::Asio::new();
         asio.driver_names()
             .into_iter()
-            .map(|n| {
-                let c = CString::new(n).unwrap();
-                (c.clone(), c)
+            .filter_map(|n| {
+                let c = CString::new(n.as_str()).ok()?;
+                Some(DeviceInfo {
+                    id: c.clone(),
+                    stable_id: c,
+                    card_description: n.clone(),
+                    device_description: String::new(),
+                    ioid: Some("Output".to_string()),
+                    card_index: None,
+                    driver: Some(n),
+                    dop: false,
+                })
             })
             .collect()
     }
 
     pub fn new(driver_name: CString) -> Self {
+        Self::with_config(driver_name, &PlayerConfig::default())
+    }
+
+    ///Driver picks the buffer size itself, only the silence preroll and mute times are taken from the config
+    pub fn with_config(driver_name: CString, config: &PlayerConfig) -> Self {
         Self {
             driver_name,
             setup: None,
@@ -496,9 +524,57 @@ impl AsioDsdPlayer {
             stopped: AtomicBool::new(true),
             is_playing: AtomicBool::new(false),
             need_bit_reverse: false,
+            events: EventBus::new(),
+            next: None,
+            length: 0,
+            preroll: config.get_preroll(),
+            mute: config.get_mute_time(),
+            silence: PendingSilence::default(),
+            close_policy: config.get_close_policy(),
         }
     }
 
+    ///Reader, format and length in bytes per channel of the track
+    fn open_track(filename: &str) -> Result<(Box<dyn DSDReader>, DSDFormat, u64), PlayerError> {
+        let open_error = |e: std::io::Error| PlayerError::TrackOpen {
+            path: filename.into(),
+            message: e.to_string(),
+        };
+        let mut format = DSDFormat::default();
+        let reader = ndsd_read::open_dsd_auto(filename, &mut format).map_err(open_error)?;
+        let (length, _) = track_length(std::path::Path::new(filename), &format).map_err(open_error)?;
+        Ok((reader, format, length))
+    }
+
+    ///Bytes per channel played in the time, capped at the track length
+    fn bytes_at(&self, time: Duration) -> u64 {
+        let bytes = time.as_nanos() * self.format.sampling_rate as u128 / 8 / 1_000_000_000;
+        bytes.min(self.length as u128) as u64
+    }
+
+    ///Moves the reader to the position target returns for the current one, the reader aligns it to its blocks
+    fn seek_bytes(&mut self, target: impl FnOnce(u64) -> u64) -> Result<PlaybackTime, PlayerError> {
+        self.reader_semaphore.acquire();
+        let res = if let Some(reader) = self.reader.as_mut() {
+            let target = target(reader.get_position_frames());
+            reader
+                .seek_samples(target)
+                .map(|_| (reader.get_position_frames(), reader.get_position_percent()))
+                .map_err(PlayerError::read)
+        } else {
+            Err(PlayerError::NoTrack)
+        };
+        self.reader_semaphore.release();
+        let (bytes, position) = res?;
+        self.request_silence(self.mute);
+        self.events.send(PlayerEvent::Seeked { position });
+        Ok(PlaybackTime::from_bytes(bytes, self.format.sampling_rate))
+    }
+
+    fn request_silence(&self, time: Duration) {
+        self.silence.request(silence_bytes(time, self.format.sampling_rate));
+    }
+
     pub fn open(driver_name: CString, path: &str) -> Self {
         let mut p = Self::new(driver_name);
         let _ = p.load_new_track(path);
@@ -587,6 +663,12 @@ impl AsioDsdPlayer {
             return;
         }
 
+        // Preroll or mute sequence, the DAC gets the idle pattern while it locks
+        if self.silence.take(self.dsd_context.channel_buffer_size) > 0 {
+            unsafe { self.fill_silence(buffer_index) };
+            return;
+        }
+
         let Some(setup) = self.setup.as_mut() else {
             unsafe { self.fill_silence(buffer_index) };
             return;
@@ -614,12 +696,30 @@ impl AsioDsdPlayer {
         }
 
         self.reader_semaphore.acquire();
-        let read_res = reader.read(out_slices.as_mut_slice(), bytes_per_channel);
+        let mut read_res = reader.read(out_slices.as_mut_slice(), bytes_per_channel);
+        if let Ok(0) = read_res
+            && let Some((next, format, length)) = self.next.take()
+        {
+            if !format.is_different(&self.format) && format.is_lsb_first == self.format.is_lsb_first {
+                self.events.send(PlayerEvent::TrackEnded);
+                self.events.send(PlayerEvent::TrackLoaded {
+                    format,
+                    meta: next.get_metadata().cloned(),
+                });
+                *reader = next;
+                self.format = format;
+                self.length = length;
+                read_res = reader.read(out_slices.as_mut_slice(), bytes_per_channel);
+            } else {
+                // Needs driver setup for the new format, caller loads it after TrackEnded
+                self.next = Some((next, format, length));
+            }
+        }
         self.reader_semaphore.release();
 
-        let bytes = match read_res {
-            Ok(b) => b,
-            Err(_) => 0,
+        let (bytes, event) = match read_res {
+            Ok(b) => (b, PlayerEvent::TrackEnded),
+            Err(e) => (0, PlayerEvent::DeviceError(PlayerError::read(e))),
         };
 
         if bytes == 0 {
@@ -627,6 +727,7 @@ impl AsioDsdPlayer {
                 self.fill_silence(buffer_index);
                 self.stop_internal();
             }
+            self.events.send(event);
             return;
         }
 
@@ -645,6 +746,7 @@ impl AsioDsdPlayer {
             return;
         };
         let bytes_per_channel = self.dsd_context.channel_buffer_size;
+        let lsb_first = matches!(self.dsd_context.sample_format, DsdFormat::Int8Lsb1);
         for ch in 0..self.dsd_context.channels {
             unsafe {
                 let ptr = setup.buffer_infos[ch].buffers[buffer_index as usize] as *mut u8;
@@ -652,7 +754,7 @@ impl AsioDsdPlayer {
                     continue;
                 }
                 let slice = std::slice::from_raw_parts_mut(ptr, bytes_per_channel);
-                slice.fill(0x69); // DSD silence.
+                fill_silence(slice, lsb_first);
             }
         }
     }
@@ -661,14 +763,21 @@ impl AsioDsdPlayer {
 impl DSDPlayer for AsioDsdPlayer {
 
 
-    async fn pause(&self) {
-        self.paused.store(true, Relaxed);
+    async fn pause(&self) -> Result<(), PlayerError> {
+        if !self.paused.swap(true, Relaxed) {
+            self.events.send(PlayerEvent::Paused);
+        }
         self.is_playing.store(false, Relaxed);
+        Ok(())
     }
 
-    async fn play(&self) {
-        self.paused.store(false, Relaxed);
+    async fn play(&self) -> Result<(), PlayerError> {
+        if self.paused.swap(false, Relaxed) {
+            self.request_silence(self.mute);
+            self.events.send(PlayerEvent::Resumed);
+        }
         self.is_playing.store(true, Relaxed);
+        Ok(())
     }
 
     async fn get_pos(&self) -> f64 {
@@ -679,7 +788,17 @@ impl DSDPlayer for AsioDsdPlayer {
         }
     }
 
-    async fn stop(&self) {
+    ///Read position, the driver keeps only the two half buffers queued
+    async fn position(&self) -> PlaybackTime {
+        let bytes = self.reader.as_ref().map_or(0, |reader| reader.get_position_frames());
+        PlaybackTime::from_bytes(bytes, self.format.sampling_rate)
+    }
+
+    async fn duration(&self) -> PlaybackTime {
+        PlaybackTime::from_bytes(self.length, self.format.sampling_rate)
+    }
+
+    async fn stop(&self) -> Result<(), PlayerError> {
         self.stopped.store(true, Relaxed);
         self.is_playing.store(false, Relaxed);
         unsafe {
@@ -688,15 +807,17 @@ impl DSDPlayer for AsioDsdPlayer {
                 (*p).stop_internal();
             }
         }
+        self.events.send(PlayerEvent::Stopped);
+        Ok(())
     }
 
     async fn is_playing(&self) -> bool {
         self.is_playing.load(Relaxed) && !self.paused.load(Relaxed) && !self.stopped.load(Relaxed)
     }
 
-    async fn load_new_track(&mut self, filename: &str) {
-        let mut format = DSDFormat::default();
-        let reader = ndsd_read::open_dsd_auto(filename, &mut format).expect("Failed to open DSD");
+    async fn load_new_track(&mut self, filename: &str) -> Result<(), PlayerError> {
+        let (reader, format, length) = Self::open_track(filename)?;
+        self.length = length;
 
         let need_full_reset = self.format.is_different(&format);
 
@@ -704,11 +825,16 @@ impl DSDPlayer for AsioDsdPlayer {
             unsafe {
                 self.cleanup_internal(); // Full driver teardown
             }
+            self.silence.request(silence_bytes(self.preroll, format.sampling_rate));
             self.reader = Some(reader);
             self.format = format.clone();
             self.stopped.store(false, Relaxed);
             unsafe {
-                self.ensure_driver_initialized().expect("Failed to initialize ASIO");
+                self.ensure_driver_initialized().map_err(|e| PlayerError::DeviceOpen {
+                    device: self.driver_name.to_string_lossy().into_owned(),
+                    errno: 0,
+                    message: e,
+                })?;
             }
         } else {
             self.reader_semaphore.acquire();
@@ -730,16 +856,58 @@ impl DSDPlayer for AsioDsdPlayer {
             DsdFormat::Int8Msb1 => file_is_lsb,
             DsdFormat::Int8Ner8 => false,
         };
+        self.events.send(PlayerEvent::TrackLoaded {
+            format,
+            meta: self.reader.as_ref().and_then(|r| r.get_metadata().cloned()),
+        });
+        if need_full_reset {
+            self.events.send(PlayerEvent::FormatChanged(format));
+        }
+        Ok(())
     }
-    async fn seek(&mut self, percent: f64) -> Result<(), io::Error> {
+    async fn seek(&mut self, percent: f64) -> Result<(), PlayerError> {
         self.reader_semaphore.acquire();
         let res = if let Some(reader) = self.reader.as_mut() {
-            reader.seek_percent(percent)
+            reader
+                .seek_percent(percent)
+                .map(|_| reader.get_position_percent())
+                .map_err(PlayerError::read)
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "no reader"))
+            Err(PlayerError::NoTrack)
         };
         self.reader_semaphore.release();
-        res
+        let position = res?;
+        self.request_silence(self.mute);
+        self.events.send(PlayerEvent::Seeked { position });
+        Ok(())
+    }
+
+    async fn seek_to(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        let target = self.bytes_at(position);
+        self.seek_bytes(|_| target)
+    }
+
+    async fn seek_by(&mut self, offset: Duration, direction: SeekDirection) -> Result<PlaybackTime, PlayerError> {
+        let offset = self.bytes_at(offset);
+        let length = self.length;
+        self.seek_bytes(|current| match direction {
+            SeekDirection::Forward => (current + offset).min(length),
+            SeekDirection::Backward => current.saturating_sub(offset),
+        })
+    }
+
+    async fn start_at(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        let landed = self.seek_to(position).await?;
+        self.start().await?;
+        Ok(landed)
+    }
+
+    async fn enqueue_next(&mut self, filename: &str) -> Result<(), PlayerError> {
+        let track = Self::open_track(filename)?;
+        self.reader_semaphore.acquire();
+        self.next = Some(track);
+        self.reader_semaphore.release();
+        Ok(())
     }
 
     async fn get_current_file_meta(&self) -> Option<DSDMeta> {
@@ -750,16 +918,77 @@ impl DSDPlayer for AsioDsdPlayer {
         self.format.clone()
     }
 
-    async fn start(&mut self) {
+    async fn start(&mut self) -> Result<(), PlayerError> {
+        if self.reader.is_none() {
+            return Err(PlayerError::NoTrack);
+        }
         unsafe {
             // Ensure ASIO is started.
             if self.setup.is_none() {
-                if self.reader.is_some() {
-                    let _ = self.ensure_driver_initialized();
-                }
+                self.ensure_driver_initialized().map_err(|e| PlayerError::DeviceOpen {
+                    device: self.driver_name.to_string_lossy().into_owned(),
+                    errno: 0,
+                    message: e,
+                })?;
+            }
+            self.request_silence(self.preroll);
+            self.start().map_err(|e| PlayerError::Device { errno: 0, message: e })?;
+        }
+        self.events.send(PlayerEvent::Started);
+        Ok(())
+    }
+
+    async fn take_error(&self) -> Option<PlayerError> {
+        None
+    }
+
+    #[cfg(feature = "tokio")]
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PlayerEvent> {
+        self.events.subscribe()
+    }
+
+    fn subscribe_blocking(&self) -> std::sync::mpsc::Receiver<PlayerEvent> {
+        self.events.subscribe_blocking()
+    }
+
+    ///Driver callback reads straight from the track, there is no read-ahead
+    async fn buffer_level(&self) -> BufferLevel {
+        BufferLevel::default()
+    }
+
+    ///Driver keeps the stream going on its own, late buffers are not reported
+    async fn xrun_stats(&self) -> XrunStats {
+        XrunStats::default()
+    }
+
+    async fn is_device_lost(&self) -> bool {
+        false
+    }
+
+    ///Asio frames are single dsd bits, the driver plays one half buffer while the other is filled
+    async fn output_params(&self) -> Option<OutputParams> {
+        self.setup.as_ref()?;
+        let half = self.dsd_context.__buffer_size as u64;
+        Some(OutputParams::new(self.format.sampling_rate, half * 2, half, half))
+    }
+
+    async fn close(&mut self) -> Result<(), PlayerError> {
+        let playing = self.is_playing.load(Relaxed);
+        if playing && !self.paused.load(Relaxed) && self.close_policy == ClosePolicy::Drain {
+            // Buffer switch fills the idle pattern from now on, the halves already filled play out.
+            // Asio buffers last a few milliseconds, sleeping does not need a runtime timer
+            self.paused.store(true, Relaxed);
+            if let Some(params) = self.output_params().await {
+                std::thread::sleep(params.buffer_time);
             }
-            let _ = self.start();
         }
+        unsafe {
+            self.cleanup_internal();
+        }
+        if playing {
+            self.events.send(PlayerEvent::Stopped);
+        }
+        Ok(())
     }
 }
 
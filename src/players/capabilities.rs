@@ -0,0 +1,125 @@
+use ndsd_read::DSDFormat;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+///DSD64 up to DSD1024 in the 44.1k family followed by the 48k family, in bits per second per channel
+pub const DSD_RATES: [u32; 10] = [
+    2822400, 5644800, 11289600, 22579200, 45158400, //
+    3072000, 6144000, 12288000, 24576000, 49152000,
+];
+
+///Usual name of the dsd rate, DSD64 for 2822400, DSD128 for twice that and so on.
+/// The 48k family is marked, 3072000 is "DSD64 (48k)"
+pub fn dsd_rate_label(sampling_rate: u32) -> String {
+    if sampling_rate != 0 && sampling_rate.is_multiple_of(48000) {
+        return format!("DSD{} (48k)", sampling_rate / 48000);
+    }
+    format!("DSD{}", sampling_rate / 44100)
+}
+
+///Sample format dsd is delivered to the device in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DsdWordFormat {
+    U8,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+    ///DoP in 32 bit pcm frames
+    DopS32Le,
+    ///DoP in packed 24 bit pcm frames
+    DopS24Packed,
+}
+
+impl DsdWordFormat {
+    pub const NATIVE: [DsdWordFormat; 5] = [
+        DsdWordFormat::U32Be,
+        DsdWordFormat::U32Le,
+        DsdWordFormat::U16Be,
+        DsdWordFormat::U16Le,
+        DsdWordFormat::U8,
+    ];
+    pub const DOP: [DsdWordFormat; 2] = [DsdWordFormat::DopS32Le, DsdWordFormat::DopS24Packed];
+
+    pub fn is_dop(&self) -> bool {
+        matches!(self, DsdWordFormat::DopS32Le | DsdWordFormat::DopS24Packed)
+    }
+
+    ///Dsd bytes per channel carried by one device frame
+    pub fn dsd_bytes_per_frame(&self) -> u32 {
+        match self {
+            DsdWordFormat::U8 => 1,
+            DsdWordFormat::U16Le | DsdWordFormat::U16Be => 2,
+            DsdWordFormat::U32Le | DsdWordFormat::U32Be => 4,
+            DsdWordFormat::DopS32Le | DsdWordFormat::DopS24Packed => 2,
+        }
+    }
+
+    ///Device frame rate needed to play the dsd rate
+    pub fn frame_rate(&self, dsd_rate: u32) -> u32 {
+        dsd_rate / 8 / self.dsd_bytes_per_frame()
+    }
+}
+
+impl Display for DsdWordFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DsdWordFormat::U8 => "DSD_U8",
+            DsdWordFormat::U16Le => "DSD_U16_LE",
+            DsdWordFormat::U16Be => "DSD_U16_BE",
+            DsdWordFormat::U32Le => "DSD_U32_LE",
+            DsdWordFormat::U32Be => "DSD_U32_BE",
+            DsdWordFormat::DopS32Le => "DoP S32_LE",
+            DsdWordFormat::DopS24Packed => "DoP S24_3LE",
+        };
+        f.write_str(name)
+    }
+}
+
+///Word format with the dsd rates the device accepts in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatCapability {
+    pub format: DsdWordFormat,
+    ///Dsd rates out of DSD_RATES, in bits per second per channel
+    pub rates: Vec<u32>,
+}
+
+///What the device can play, reported without opening it for playback.
+/// Buffer and period limits are in device frames, their byte size depends on the word format
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCapabilities {
+    ///Native formats first, DoP formats after them
+    pub formats: Vec<FormatCapability>,
+    pub channels: RangeInclusive<u32>,
+    pub buffer_frames: RangeInclusive<u64>,
+    pub period_frames: RangeInclusive<u64>,
+}
+
+impl DeviceCapabilities {
+    ///Software outputs take whatever the track has
+    pub fn unrestricted() -> Self {
+        Self {
+            formats: vec![FormatCapability {
+                format: DsdWordFormat::U8,
+                rates: DSD_RATES.to_vec(),
+            }],
+            channels: 1..=u32::MAX,
+            buffer_frames: 0..=u64::MAX,
+            period_frames: 0..=u64::MAX,
+        }
+    }
+
+    ///Every dsd rate accepted in any format, sorted
+    pub fn rates(&self) -> Vec<u32> {
+        let mut rates: Vec<u32> = self.formats.iter().flat_map(|f| f.rates.iter().copied()).collect();
+        rates.sort_unstable();
+        rates.dedup();
+        rates
+    }
+
+    ///True if the track can be played natively or over DoP
+    pub fn can_play(&self, format: &DSDFormat) -> bool {
+        self.channels.contains(&format.num_channels)
+            && self.formats.iter().any(|f| f.rates.contains(&format.sampling_rate))
+    }
+}
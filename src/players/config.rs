@@ -0,0 +1,248 @@
+use crate::players::PlayerError;
+use crate::players::engine::DEFAULT_READ_AHEAD;
+use std::time::Duration;
+
+///Silence played before the track starts and after a format switch, DACs lock to the new stream during it
+pub const DEFAULT_PREROLL: Duration = Duration::from_millis(200);
+///Silence inserted around seeks, pauses and stops
+pub const DEFAULT_MUTE_TIME: Duration = Duration::from_millis(30);
+
+///Trade-off between reaction time and robustness of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatencyProfile {
+    ///Small device buffer, pause and seek react at once but a busy system can underrun
+    LowLatency,
+    #[default]
+    Balanced,
+    ///Large device buffer for slow or busy machines
+    HighBuffer,
+}
+
+impl LatencyProfile {
+    fn buffer_time(&self) -> Duration {
+        match self {
+            LatencyProfile::LowLatency => Duration::from_millis(40),
+            LatencyProfile::Balanced => Duration::from_millis(200),
+            LatencyProfile::HighBuffer => Duration::from_millis(1000),
+        }
+    }
+
+    fn period_time(&self) -> Duration {
+        match self {
+            LatencyProfile::LowLatency => Duration::from_millis(10),
+            LatencyProfile::Balanced => Duration::from_millis(25),
+            LatencyProfile::HighBuffer => Duration::from_millis(100),
+        }
+    }
+}
+
+///How the player retries a device which disappeared during playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    ///Pause between two attempts to reopen the device
+    pub interval: Duration,
+    ///None retries until the player is stopped
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    ///Playback stops with DeviceError right away
+    pub const DISABLED: ReconnectPolicy = ReconnectPolicy {
+        interval: Duration::ZERO,
+        max_attempts: Some(0),
+    };
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            max_attempts: None,
+        }
+    }
+}
+
+///What happens to the audio queued in the device when the player is closed or dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClosePolicy {
+    ///Queued audio is discarded, the output goes quiet at once
+    #[default]
+    Drop,
+    ///Audio already in the device buffer is played out, the read-ahead is discarded
+    Drain,
+}
+
+///Output settings passed to create_player. Built from a latency profile, every value can be overridden:
+/// PlayerConfig::new().profile(LatencyProfile::LowLatency).period_time(Duration::from_millis(5)).
+/// Devices round the values to what they support, the granted ones are reported by DSDPlayer::output_params
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlayerConfig {
+    profile: LatencyProfile,
+    buffer_time: Option<Duration>,
+    period_time: Option<Duration>,
+    start_threshold: Option<Duration>,
+    read_ahead: Option<Duration>,
+    reconnect: ReconnectPolicy,
+    preroll: Option<Duration>,
+    mute_time: Option<Duration>,
+    close_policy: ClosePolicy,
+}
+
+impl PlayerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn profile(mut self, profile: LatencyProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    ///Audio the device buffer holds
+    pub fn buffer_time(mut self, buffer_time: Duration) -> Self {
+        self.buffer_time = Some(buffer_time);
+        self
+    }
+
+    ///Audio passed to the device in a single write, at most half of the buffer
+    pub fn period_time(mut self, period_time: Duration) -> Self {
+        self.period_time = Some(period_time);
+        self
+    }
+
+    ///Device starts playing once this much is buffered, the whole buffer by default
+    pub fn start_threshold(mut self, start_threshold: Duration) -> Self {
+        self.start_threshold = Some(start_threshold);
+        self
+    }
+
+    ///Audio read from the track ahead of the output
+    pub fn read_ahead(mut self, read_ahead: Duration) -> Self {
+        self.read_ahead = Some(read_ahead);
+        self
+    }
+
+    ///Retries of a device lost during playback, every second until stopped by default
+    pub fn reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    ///Idle pattern played before the start and after a format switch, zero disables it
+    pub fn preroll(mut self, preroll: Duration) -> Self {
+        self.preroll = Some(preroll);
+        self
+    }
+
+    ///Idle pattern played after a seek, before a pause or stop and after a resume, zero disables it
+    pub fn mute_time(mut self, mute_time: Duration) -> Self {
+        self.mute_time = Some(mute_time);
+        self
+    }
+
+    ///Handling of the queued audio on close and drop, dropped by default
+    pub fn close_policy(mut self, close_policy: ClosePolicy) -> Self {
+        self.close_policy = close_policy;
+        self
+    }
+
+    pub fn get_profile(&self) -> LatencyProfile {
+        self.profile
+    }
+
+    pub fn get_buffer_time(&self) -> Duration {
+        self.buffer_time.unwrap_or(self.profile.buffer_time())
+    }
+
+    pub fn get_period_time(&self) -> Duration {
+        self.period_time.unwrap_or(self.profile.period_time())
+    }
+
+    pub fn get_start_threshold(&self) -> Duration {
+        self.start_threshold.unwrap_or(self.get_buffer_time())
+    }
+
+    pub fn get_read_ahead(&self) -> Duration {
+        self.read_ahead.unwrap_or(DEFAULT_READ_AHEAD)
+    }
+
+    pub fn get_reconnect(&self) -> ReconnectPolicy {
+        self.reconnect
+    }
+
+    pub fn get_preroll(&self) -> Duration {
+        self.preroll.unwrap_or(DEFAULT_PREROLL)
+    }
+
+    pub fn get_mute_time(&self) -> Duration {
+        self.mute_time.unwrap_or(DEFAULT_MUTE_TIME)
+    }
+
+    pub fn get_close_policy(&self) -> ClosePolicy {
+        self.close_policy
+    }
+
+    ///Checks that the values fit together, create_player does it before opening anything
+    pub fn validate(&self) -> Result<(), PlayerError> {
+        let invalid = |message: String| Err(PlayerError::InvalidConfig { message });
+        let (buffer, period) = (self.get_buffer_time(), self.get_period_time());
+        if period.is_zero() {
+            return invalid("period time must be above zero".to_string());
+        }
+        if buffer < period * 2 {
+            return invalid(format!(
+                "buffer time {:?} must hold at least two periods of {:?}",
+                buffer, period
+            ));
+        }
+        let start_threshold = self.get_start_threshold();
+        if start_threshold.is_zero() || start_threshold > buffer {
+            return invalid(format!(
+                "start threshold {:?} must be above zero and within the buffer time {:?}",
+                start_threshold, buffer
+            ));
+        }
+        if self.get_read_ahead() < period {
+            return invalid(format!(
+                "read-ahead {:?} must hold at least one period of {:?}",
+                self.get_read_ahead(),
+                period
+            ));
+        }
+        if self.reconnect.interval.is_zero() && self.reconnect.max_attempts != Some(0) {
+            return invalid("reconnect interval must be above zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+///Values the device granted for the requested config, sizes are in device frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputParams {
+    ///Device frame rate, for DoP and the wider dsd words it is below the dsd rate
+    pub rate: u32,
+    pub buffer_frames: u64,
+    pub period_frames: u64,
+    pub start_threshold_frames: u64,
+    pub buffer_time: Duration,
+    pub period_time: Duration,
+}
+
+impl OutputParams {
+    pub fn new(rate: u32, buffer_frames: u64, period_frames: u64, start_threshold_frames: u64) -> Self {
+        let time = |frames: u64| {
+            if rate == 0 {
+                return Duration::ZERO;
+            }
+            Duration::from_nanos((frames as u128 * 1_000_000_000 / rate as u128) as u64)
+        };
+        Self {
+            rate,
+            buffer_frames,
+            period_frames,
+            start_threshold_frames,
+            buffer_time: time(buffer_frames),
+            period_time: time(period_frames),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::ffi::CString;
+
+///Output device found by enumerate_supported_devices
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    ///Name to pass to create_player, card numbers in it may change after reboot or re-plugging
+    pub id: CString,
+    ///Name which survives reboots and re-plugging, meant to be saved in settings.
+    /// Accepted by create_player the same way as id
+    pub stable_id: CString,
+    ///First line of the device description, usually the card name
+    pub card_description: String,
+    ///Second line of the device description, empty if there is none
+    pub device_description: String,
+    ///"Output" or "Input", None when the device does both
+    pub ioid: Option<String>,
+    pub card_index: Option<i32>,
+    pub driver: Option<String>,
+    ///Device has no native dsd format and plays over DoP
+    pub dop: bool,
+}
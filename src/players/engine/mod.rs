@@ -0,0 +1,929 @@
+use crate::players::event::{EventBus, POSITION_TICK_MS};
+use crate::utils::silence::{PendingSilence, fill_silence, silence_bytes};
+use crate::players::{
+    AudioSink, BufferLevel, ClosePolicy, DSDPlayer, OutputParams, PlaybackTime, PlayerConfig, PlayerError, PlayerEvent,
+    ReconnectPolicy, SeekDirection, Wakeup, Xrun, XrunKind, XrunStats,
+};
+use atomic_float::AtomicF64;
+use ndsd_read::{DSDFormat, DSDMeta, DSDReader};
+use read_ahead::{Block, Item, Level, ReadAhead, SeekTarget};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use reply::Reply;
+
+mod read_ahead;
+mod reply;
+
+///Read-ahead depth used by PlaybackEngine::new
+pub const DEFAULT_READ_AHEAD: Duration = Duration::from_secs(2);
+// How long the output waits for the read-ahead when it has nothing to write
+const UNDERRUN_WAIT: Duration = Duration::from_millis(10);
+// Longest wait for the output to take a period, position ticks are sent in between
+const OUTPUT_WAIT: Duration = Duration::from_millis(50);
+
+pub enum ControlRequest {
+    LoadTrack(PathBuf),
+    EnqueueNext(PathBuf),
+    Start,
+    StartAt(Duration),
+    Stop,
+    Seek(f64),
+    SeekTo(Duration),
+    SeekBy(Duration, SeekDirection),
+    Pause,
+    Play,
+    Terminate,
+}
+
+struct Track {
+    reader: Box<dyn DSDReader>,
+    format: DSDFormat,
+    meta: Option<DSDMeta>,
+    // Bytes per channel of audio
+    length: u64,
+    // Reads stop there instead of running into DSF block padding
+    end: Option<u64>,
+}
+
+impl Track {
+    fn open(path: &Path) -> Result<Self, PlayerError> {
+        let open_error = |e: std::io::Error| PlayerError::TrackOpen {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        };
+        let mut format = DSDFormat::default();
+        let reader = ndsd_read::open_dsd_auto(&path.to_string_lossy(), &mut format).map_err(open_error)?;
+        let (length, dsf) = track_length(path, &format).map_err(open_error)?;
+        Ok(Self {
+            meta: reader.get_metadata().cloned(),
+            reader,
+            format,
+            length,
+            end: dsf.then_some(length),
+        })
+    }
+}
+
+///Audio length in bytes per channel and whether the file is DSF.
+/// DSF counts samples in bits, DSDIFF in bytes, the reader positions are in bytes for both
+pub(crate) fn track_length(path: &Path, format: &DSDFormat) -> std::io::Result<(u64, bool)> {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)?.read_exact(&mut magic)?;
+    let dsf = &magic == b"DSD ";
+    let length = if dsf { format.total_samples / 8 } else { format.total_samples };
+    Ok((length, dsf))
+}
+
+///Tracks can follow each other without reopening the output only if the output setup is the same
+fn needs_reopen(current: &DSDFormat, next: &DSDFormat) -> bool {
+    current.is_different(next) || current.is_lsb_first != next.is_lsb_first
+}
+
+struct PlayerState {
+    read_ahead: ReadAhead,
+    // Serial of the track being written, 0 until a track is loaded
+    serial: u64,
+    last_serial: u64,
+    // Serial of the enqueued track until the output reaches it, 0 if nothing is enqueued
+    enqueued: u64,
+    format: DSDFormat,
+    track_ended: bool,
+    // Block which did not fit into the previous period and the bytes of it already written
+    partial: Option<(Block, usize)>,
+    // Bytes per channel of the current track
+    length: u64,
+    // Position of the last byte written into the sink
+    position_bytes: u64,
+    // Track bytes written since the output was opened or silence was written, anything queued beyond it is silence
+    music_written: u64,
+    silence: PendingSilence,
+    preroll: Duration,
+    mute: Duration,
+    sink: Box<dyn AudioSink>,
+    sink_ready: bool,
+    playing: bool,
+    paused: bool,
+    first_paused: bool,
+    released_pause: bool,
+    work: Vec<Vec<u8>>,
+    reconnect: ReconnectPolicy,
+    lost: Option<Lost>,
+    close_policy: ClosePolicy,
+    shared: Arc<Shared>,
+}
+
+///Device which disappeared during playback, the read-ahead waits at the position heard last
+struct Lost {
+    error: PlayerError,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+impl PlayerState {
+    fn emit(&self, event: PlayerEvent) {
+        self.shared.events.send(event);
+    }
+}
+
+///State published by the player thread
+struct Shared {
+    current_pos: AtomicF64,
+    clock: Mutex<Clock>,
+    is_playing: AtomicBool,
+    device_lost: AtomicBool,
+    cur_format: Mutex<DSDFormat>,
+    cur_meta: Mutex<Option<DSDMeta>>,
+    last_error: Mutex<Option<PlayerError>>,
+    output_params: Mutex<Option<OutputParams>>,
+    xruns: Mutex<XrunStats>,
+    events: EventBus,
+    level: Arc<Level>,
+    // Fired after every command, so the thread does not sit in the output wait
+    wakeup: Wakeup,
+}
+
+///What the output played, sampled after every write. In between the position is advanced by the elapsed time
+#[derive(Default)]
+struct Clock {
+    // Bytes per channel, heard excludes what is still queued in the output
+    heard: u64,
+    written: u64,
+    length: u64,
+    sampling_rate: u32,
+    running: bool,
+    at: Option<Instant>,
+}
+
+impl Clock {
+    fn heard_now(&self) -> u64 {
+        let mut heard = self.heard;
+        if self.running
+            && let Some(at) = self.at
+        {
+            heard += (at.elapsed().as_secs_f64() * self.sampling_rate as f64 / 8.) as u64;
+        }
+        heard.min(self.written)
+    }
+}
+
+/// Transport shared by every output backend.
+/// Tracks are read ahead on a dedicated thread, the player thread only packs the planar dsd into periods
+/// and writes them into the sink it was created with
+#[allow(unused)]
+pub struct PlaybackEngine {
+    // Taken once the thread was joined by close or drop
+    player_thread: Option<std::thread::JoinHandle<()>>,
+    message_channel: Sender<(ControlRequest, Reply)>,
+    shared: Arc<Shared>,
+}
+
+#[async_trait::async_trait]
+impl DSDPlayer for PlaybackEngine {
+    async fn start(&mut self) -> Result<(), PlayerError> {
+        self.request(ControlRequest::Start).await.map(|_| ())
+    }
+
+    async fn pause(&self) -> Result<(), PlayerError> {
+        self.request(ControlRequest::Pause).await.map(|_| ())
+    }
+
+    async fn play(&self) -> Result<(), PlayerError> {
+        self.request(ControlRequest::Play).await.map(|_| ())
+    }
+
+    async fn get_pos(&self) -> f64 {
+        self.shared.current_pos.load(Relaxed)
+    }
+
+    async fn position(&self) -> PlaybackTime {
+        let clock = self.shared.clock.lock().unwrap();
+        PlaybackTime::from_bytes(clock.heard_now(), clock.sampling_rate)
+    }
+
+    async fn duration(&self) -> PlaybackTime {
+        let clock = self.shared.clock.lock().unwrap();
+        PlaybackTime::from_bytes(clock.length, clock.sampling_rate)
+    }
+
+    async fn stop(&self) -> Result<(), PlayerError> {
+        self.request(ControlRequest::Stop).await.map(|_| ())
+    }
+
+    async fn is_playing(&self) -> bool {
+        self.shared.is_playing.load(Relaxed)
+    }
+
+    async fn load_new_track(&mut self, filename: &str) -> Result<(), PlayerError> {
+        self.request(ControlRequest::LoadTrack(PathBuf::from(filename))).await.map(|_| ())
+    }
+
+    async fn seek(&mut self, percent: f64) -> Result<(), PlayerError> {
+        self.request(ControlRequest::Seek(percent)).await.map(|_| ())
+    }
+
+    async fn seek_to(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        self.request(ControlRequest::SeekTo(position)).await
+    }
+
+    async fn seek_by(&mut self, offset: Duration, direction: SeekDirection) -> Result<PlaybackTime, PlayerError> {
+        self.request(ControlRequest::SeekBy(offset, direction)).await
+    }
+
+    async fn start_at(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        self.request(ControlRequest::StartAt(position)).await
+    }
+
+    async fn enqueue_next(&mut self, filename: &str) -> Result<(), PlayerError> {
+        self.request(ControlRequest::EnqueueNext(PathBuf::from(filename))).await.map(|_| ())
+    }
+
+    async fn get_format_info(&self) -> DSDFormat {
+        *self.shared.cur_format.lock().unwrap()
+    }
+
+    async fn get_current_file_meta(&self) -> Option<DSDMeta> {
+        self.shared.cur_meta.lock().unwrap().clone()
+    }
+
+    async fn take_error(&self) -> Option<PlayerError> {
+        self.shared.last_error.lock().unwrap().take()
+    }
+
+    #[cfg(feature = "tokio")]
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PlayerEvent> {
+        self.shared.events.subscribe()
+    }
+
+    fn subscribe_blocking(&self) -> std::sync::mpsc::Receiver<PlayerEvent> {
+        self.shared.events.subscribe_blocking()
+    }
+
+    async fn buffer_level(&self) -> BufferLevel {
+        self.shared.level.report()
+    }
+
+    async fn output_params(&self) -> Option<OutputParams> {
+        *self.shared.output_params.lock().unwrap()
+    }
+
+    async fn xrun_stats(&self) -> XrunStats {
+        self.shared.xruns.lock().unwrap().clone()
+    }
+
+    async fn is_device_lost(&self) -> bool {
+        self.shared.device_lost.load(Relaxed)
+    }
+
+    async fn close(&mut self) -> Result<(), PlayerError> {
+        let Some(thread) = self.player_thread.take() else {
+            return Ok(());
+        };
+        let result = self.request(ControlRequest::Terminate).await;
+        // Reply is sent after the sink was released, the thread is about to return
+        let _ = thread.join();
+        result.map(|_| ())
+    }
+}
+
+impl Drop for PlaybackEngine {
+    fn drop(&mut self) {
+        let Some(thread) = self.player_thread.take() else {
+            return;
+        };
+        // Closed channel ends the player loop the same way Terminate does
+        let (closed, _) = channel::unbounded();
+        drop(std::mem::replace(&mut self.message_channel, closed));
+        self.shared.wakeup.wake();
+        let _ = thread.join();
+    }
+}
+
+impl PlaybackEngine {
+    pub fn new(sink: Box<dyn AudioSink>) -> Self {
+        Self::with_read_ahead(sink, DEFAULT_READ_AHEAD)
+    }
+
+    ///Engine which keeps up to read_ahead of audio read from the track in front of the output
+    pub fn with_read_ahead(sink: Box<dyn AudioSink>, read_ahead: Duration) -> Self {
+        Self::with_config(sink, &PlayerConfig::new().read_ahead(read_ahead))
+    }
+
+    ///Takes the read-ahead depth and reconnect policy from the config, the sink applies the rest when it is created
+    pub fn with_config(sink: Box<dyn AudioSink>, config: &PlayerConfig) -> Self {
+        // Every caller waits for its reply, so only a few commands are ever queued
+        let (commands, channel) = channel::unbounded::<(ControlRequest, Reply)>();
+        let shared = Arc::new(Shared {
+            current_pos: AtomicF64::new(0.),
+            clock: Mutex::new(Clock::default()),
+            is_playing: AtomicBool::new(false),
+            device_lost: AtomicBool::new(false),
+            cur_format: Mutex::new(DSDFormat::default()),
+            cur_meta: Mutex::new(None),
+            last_error: Mutex::new(None),
+            output_params: Mutex::new(None),
+            xruns: Mutex::new(XrunStats::default()),
+            events: EventBus::new(),
+            level: Arc::new(Level::default()),
+            wakeup: Wakeup::new(),
+        });
+        Self {
+            player_thread: Some(Self::player_main(sink, config, channel, shared.clone())),
+            message_channel: commands,
+            shared,
+        }
+    }
+
+    ///Sends the command to the player thread and waits until it is executed
+    async fn request(&self, request: ControlRequest) -> Result<PlaybackTime, PlayerError> {
+        let (reply, result) = reply::channel();
+        self.message_channel
+            .send((request, reply))
+            .map_err(|_| PlayerError::Terminated)?;
+        self.shared.wakeup.wake();
+        result.await.ok_or(PlayerError::Terminated)?
+    }
+
+    fn player_main(
+        sink: Box<dyn AudioSink>,
+        config: &PlayerConfig,
+        channel: Receiver<(ControlRequest, Reply)>,
+        shared: Arc<Shared>,
+    ) -> std::thread::JoinHandle<()> {
+        let (read_ahead, reconnect) = (config.get_read_ahead(), config.get_reconnect());
+        let (preroll, mute) = (config.get_preroll(), config.get_mute_time());
+        let close_policy = config.get_close_policy();
+        std::thread::spawn(move || {
+            let mut state: PlayerState = PlayerState {
+                read_ahead: ReadAhead::new(read_ahead, shared.level.clone()),
+                serial: 0,
+                last_serial: 0,
+                enqueued: 0,
+                format: Default::default(),
+                track_ended: false,
+                partial: None,
+                length: 0,
+                position_bytes: 0,
+                music_written: 0,
+                silence: PendingSilence::default(),
+                preroll,
+                mute,
+                sink,
+                sink_ready: false,
+                playing: false,
+                paused: false,
+                first_paused: false,
+                released_pause: false,
+                work: Vec::new(),
+                reconnect,
+                lost: None,
+                close_policy,
+                shared,
+            };
+            let tick = Duration::from_millis(POSITION_TICK_MS);
+            let mut last_tick = Instant::now();
+            let mut terminated = None;
+            loop {
+                // Stopped or paused player has nothing to do until the next command
+                let idle = (!state.playing || (state.paused && !state.first_paused)) && state.lost.is_none();
+                let request = if idle {
+                    state.shared.is_playing.store(state.playing, Relaxed);
+                    // Channel is closed once the player was dropped
+                    let Ok(request) = channel.recv() else {
+                        break;
+                    };
+                    Some(request)
+                } else {
+                    state.shared.wakeup.clear();
+                    match channel.try_recv() {
+                        Ok(request) => Some(request),
+                        Err(TryRecvError::Disconnected) => break,
+                        Err(TryRecvError::Empty) => None,
+                    }
+                };
+                if let Some((cmd, reply)) = request {
+                    if let ControlRequest::Terminate = cmd {
+                        terminated = Some(reply);
+                        break;
+                    }
+                    let res = Self::process_command(cmd, &mut state);
+                    state.shared.is_playing.store(state.playing, Relaxed);
+                    let heard = Self::publish_position(&mut state);
+                    reply.send(res.map(|_| PlaybackTime::from_bytes(heard, state.format.sampling_rate)));
+                }
+                if state.lost.is_some() {
+                    Self::reconnect_poll(&mut state);
+                } else if state.playing {
+                    match Self::playback_poll(&mut state) {
+                        Err(e @ PlayerError::DeviceLost { .. }) => Self::device_lost(&mut state, e),
+                        Err(e) => Self::fail(&mut state, e),
+                        Ok(()) => {}
+                    }
+                    let heard = Self::publish_position(&mut state);
+                    let position = Self::percent(&state, heard);
+                    if state.playing && !state.paused && last_tick.elapsed() >= tick {
+                        last_tick = Instant::now();
+                        state.emit(PlayerEvent::PositionTick { position });
+                    }
+                    state.shared.is_playing.store(state.playing, Relaxed);
+                }
+            }
+            let result = Self::shutdown(&mut state);
+            // Sinks release the device when dropped, close returns after that
+            drop(state);
+            if let Some(reply) = terminated {
+                reply.send(result.map(|_| PlaybackTime::default()));
+            }
+        })
+    }
+
+    ///Stops the output on close or drop as the close policy says
+    fn shutdown(state: &mut PlayerState) -> Result<(), PlayerError> {
+        let was_playing = state.playing && !state.paused;
+        if state.playing {
+            state.emit(PlayerEvent::Stopped);
+        }
+        state.playing = false;
+        state.shared.is_playing.store(false, Relaxed);
+        Self::set_lost(state, None);
+        if !state.sink_ready {
+            return Ok(());
+        }
+        state.silence.clear();
+        if !was_playing || state.close_policy == ClosePolicy::Drop {
+            state.sink.flush()?;
+        }
+        if was_playing && (state.close_policy == ClosePolicy::Drain || Self::mutes(state)) {
+            Self::write_mute(state)?;
+            state.sink.drain()?;
+        }
+        state.sink.finish()
+    }
+
+    fn process_command(
+        command: ControlRequest,
+        state: &mut PlayerState,
+    ) -> Result<(), PlayerError> {
+        let mut setup_reload_required = false;
+        let started = matches!(command, ControlRequest::Start | ControlRequest::StartAt(_));
+        match command {
+            ControlRequest::LoadTrack(path) => {
+                let track = Track::open(&path)?;
+                let (format, meta, length) = (track.format, track.meta.clone(), track.length);
+                let serial = Self::next_serial(state);
+                state.read_ahead.load(track, serial, state.serial);
+                state.serial = serial;
+                Self::reset_position(state, 0);
+                setup_reload_required = Self::switch_track(state, format, meta, length);
+            }
+            ControlRequest::EnqueueNext(path) => {
+                let track = Track::open(&path)?;
+                let serial = Self::next_serial(state);
+                state.read_ahead.enqueue(track, serial);
+                state.enqueued = serial;
+            }
+            ControlRequest::Start => {
+                setup_reload_required = Self::start(state)?;
+            }
+            ControlRequest::StartAt(time) => {
+                let target = Self::bytes_at(state, time);
+                Self::seek(state, SeekTarget::Bytes(target))?;
+                setup_reload_required = Self::start(state)?;
+            }
+            ControlRequest::Stop => {
+                let was_playing = state.playing && !state.paused;
+                // Pause reached the output unless it was still pending, a resume might not have released it yet
+                let output_paused = (state.paused && !state.first_paused) || state.released_pause;
+                state.playing = false;
+                state.paused = false;
+                state.first_paused = false;
+                state.released_pause = false;
+                Self::set_lost(state, None);
+                let mut unplayed = 0;
+                if state.sink_ready {
+                    if output_paused {
+                        state.sink.pause(false)?;
+                    }
+                    unplayed = Self::unplayed(state);
+                    state.sink.flush()?;
+                    state.silence.clear();
+                    if was_playing && Self::mutes(state) {
+                        Self::write_mute(state)?;
+                        state.sink.drain()?;
+                    }
+                }
+                // Read-ahead is in front of the output, next start continues from what was actually played
+                if state.serial != 0 && !state.track_ended {
+                    let heard = state.position_bytes.saturating_sub(unplayed);
+                    Self::reposition(state, SeekTarget::Bytes(heard))?;
+                }
+                state.emit(PlayerEvent::Stopped);
+            }
+            ControlRequest::Seek(f64) => {
+                Self::seek(state, SeekTarget::Percent(f64))?;
+            }
+            ControlRequest::SeekTo(time) => {
+                let target = Self::bytes_at(state, time);
+                Self::seek(state, SeekTarget::Bytes(target))?;
+            }
+            ControlRequest::SeekBy(offset, direction) => {
+                let offset = Self::bytes_at(state, offset);
+                let heard = Self::heard(state);
+                let target = match direction {
+                    SeekDirection::Forward => (heard + offset).min(state.length),
+                    SeekDirection::Backward => heard.saturating_sub(offset),
+                };
+                Self::seek(state, SeekTarget::Bytes(target))?;
+            }
+            ControlRequest::Pause => {
+                // Stopped player has nothing to pause, start plays again
+                if state.playing && !state.paused {
+                    state.emit(PlayerEvent::Paused);
+                    state.paused = true;
+                    state.first_paused = true;
+                }
+            }
+            ControlRequest::Play => {
+                if state.paused {
+                    state.emit(PlayerEvent::Resumed);
+                    // Output is only resumed if the pause reached it, a running output is left alone
+                    state.released_pause = !state.first_paused;
+                    state.first_paused = false;
+                }
+                state.paused = false;
+            }
+            // Handled by the player loop
+            ControlRequest::Terminate => {}
+        }
+        if setup_reload_required && let Err(e) = Self::open_sink(state) {
+            // Failures while playing are reported by fail, this one goes to the caller and the subscribers
+            state.emit(PlayerEvent::DeviceError(e.clone()));
+            return Err(e);
+        }
+        if started {
+            state.emit(PlayerEvent::Started);
+        }
+        Ok(())
+    }
+
+    ///Returns true if the output has to be opened first
+    fn start(state: &mut PlayerState) -> Result<bool, PlayerError> {
+        if state.serial == 0 {
+            return Err(PlayerError::NoTrack);
+        }
+        if state.track_ended {
+            Self::reposition(state, SeekTarget::Start)?;
+            state.track_ended = false;
+        }
+        // Running stream goes on as it is, a reopened output gets the preroll from try_open_sink
+        if !state.playing && state.sink_ready {
+            Self::request_silence(state, state.preroll);
+        }
+        state.playing = true;
+        Ok(!state.sink_ready)
+    }
+
+    ///Works while stopped as well, the reader lands on the DSF block or DST frame containing the target
+    fn seek(state: &mut PlayerState, target: SeekTarget) -> Result<(), PlayerError> {
+        if state.serial == 0 {
+            return Err(PlayerError::NoTrack);
+        }
+        Self::reposition(state, target)?;
+        state.track_ended = false;
+        if state.sink_ready {
+            state.sink.flush()?;
+            state.music_written = 0;
+            Self::request_silence(state, state.mute);
+        }
+        state.emit(PlayerEvent::Seeked {
+            position: Self::percent(state, state.position_bytes),
+        });
+        Ok(())
+    }
+
+    ///Bytes per channel played in the time, capped at the track length
+    fn bytes_at(state: &PlayerState, time: Duration) -> u64 {
+        let bytes = time.as_nanos() * state.format.sampling_rate as u128 / 8 / 1_000_000_000;
+        bytes.min(state.length as u128) as u64
+    }
+
+    fn next_serial(state: &mut PlayerState) -> u64 {
+        state.last_serial += 1;
+        state.last_serial
+    }
+
+    fn reset_position(state: &mut PlayerState, position_bytes: u64) {
+        if let Some((block, _)) = state.partial.take() {
+            state.read_ahead.recycle(block.data);
+        }
+        state.position_bytes = position_bytes;
+    }
+
+    ///Moves the read-ahead within the current track, anything read before is dropped
+    fn reposition(state: &mut PlayerState, target: SeekTarget) -> Result<(), PlayerError> {
+        let position_bytes = state.read_ahead.seek(target, state.serial)?;
+        Self::reset_position(state, position_bytes);
+        Ok(())
+    }
+
+    fn percent(state: &PlayerState, bytes: u64) -> f64 {
+        if state.length == 0 {
+            return 0.;
+        }
+        (bytes as f64 / state.length as f64).min(1.)
+    }
+
+    ///Bytes per channel the output played so far
+    fn heard(state: &PlayerState) -> u64 {
+        state.position_bytes.saturating_sub(Self::unplayed(state))
+    }
+
+    ///Track bytes queued in the output, silence in front of them is not counted
+    fn unplayed(state: &PlayerState) -> u64 {
+        if !state.sink_ready {
+            return 0;
+        }
+        (state.sink.delay() as u64).min(state.music_written)
+    }
+
+    ///Samples what the output has played so far and returns it
+    fn publish_position(state: &mut PlayerState) -> u64 {
+        let heard = Self::heard(state);
+        state.shared.current_pos.store(Self::percent(state, heard), Relaxed);
+        let mut clock = state.shared.clock.lock().unwrap();
+        *clock = Clock {
+            heard,
+            written: state.position_bytes,
+            length: state.length,
+            sampling_rate: state.format.sampling_rate,
+            running: state.playing && !state.paused && state.sink_ready,
+            at: Some(Instant::now()),
+        };
+        heard
+    }
+
+    ///Makes the track current, returns true if the output has to be reopened for it
+    fn switch_track(state: &mut PlayerState, format: DSDFormat, meta: Option<DSDMeta>, length: u64) -> bool {
+        let reopen = needs_reopen(&state.format, &format);
+        state.length = length;
+        *state.shared.cur_meta.lock().unwrap() = meta.clone();
+        *state.shared.cur_format.lock().unwrap() = format;
+        state.format = format;
+        state.track_ended = false;
+        state.emit(PlayerEvent::TrackLoaded { format, meta });
+        if reopen {
+            state.emit(PlayerEvent::FormatChanged(format));
+        }
+        reopen
+    }
+
+    fn open_sink(state: &mut PlayerState) -> Result<(), PlayerError> {
+        if let Err(e) = Self::try_open_sink(state) {
+            state.playing = false;
+            Self::set_lost(state, None);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn try_open_sink(state: &mut PlayerState) -> Result<(), PlayerError> {
+        state.sink_ready = false;
+        state.sink.open(&state.format)?;
+        state.sink_ready = true;
+        state.music_written = 0;
+        // New stream, the DAC has to lock to it again
+        state.silence.clear();
+        Self::request_silence(state, state.preroll);
+        *state.shared.output_params.lock().unwrap() = state.sink.output_params();
+        let period_bytes = state.sink.period_bytes();
+        state.work = (0..state.format.num_channels)
+            .map(|_| vec![0u8; period_bytes])
+            .collect();
+        Ok(())
+    }
+
+    ///Playback stops, the error is kept for take_error
+    fn fail(state: &mut PlayerState, e: PlayerError) {
+        state.playing = false;
+        state.shared.is_playing.store(false, Relaxed);
+        *state.shared.last_error.lock().unwrap() = Some(e.clone());
+        state.emit(PlayerEvent::DeviceError(e));
+    }
+
+    fn set_lost(state: &mut PlayerState, lost: Option<Lost>) {
+        state.shared.device_lost.store(lost.is_some(), Relaxed);
+        state.lost = lost;
+    }
+
+    ///Remembers what was heard and lets the reconnect loop take over
+    fn device_lost(state: &mut PlayerState, e: PlayerError) {
+        state.sink_ready = false;
+        if state.reconnect.max_attempts == Some(0) {
+            return Self::fail(state, e);
+        }
+        // Whatever was queued in the device is gone with it
+        let heard = state.shared.clock.lock().unwrap().heard;
+        if let Err(e) = Self::reposition(state, SeekTarget::Bytes(heard)) {
+            return Self::fail(state, e);
+        }
+        state.emit(PlayerEvent::DeviceLost(e.clone()));
+        let next_attempt = Instant::now() + state.reconnect.interval;
+        Self::set_lost(state, Some(Lost { error: e, attempts: 0, next_attempt }));
+    }
+
+    fn reconnect_poll(state: &mut PlayerState) {
+        let Some(lost) = state.lost.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        if now < lost.next_attempt {
+            // Commands wake the wait
+            state.shared.wakeup.wait(lost.next_attempt - now);
+            return;
+        }
+        lost.attempts += 1;
+        lost.next_attempt = now + state.reconnect.interval;
+        let attempts = lost.attempts;
+        if Self::try_open_sink(state).is_ok() {
+            Self::set_lost(state, None);
+            // Paused player pauses the reopened device with the next poll
+            state.first_paused = state.paused;
+            state.released_pause = false;
+            state.emit(PlayerEvent::DeviceReconnected);
+            return;
+        }
+        if state.reconnect.max_attempts.is_some_and(|max| attempts >= max)
+            && let Some(lost) = state.lost.take()
+        {
+            Self::set_lost(state, None);
+            Self::fail(state, lost.error);
+        }
+    }
+
+    fn write_period(state: &mut PlayerState, bytes: usize) -> Result<(), PlayerError> {
+        if bytes == 0 {
+            return Ok(());
+        }
+        Self::write_work(state, bytes)?;
+        state.music_written += bytes as u64;
+        Ok(())
+    }
+
+    fn write_work(state: &mut PlayerState, bytes: usize) -> Result<(), PlayerError> {
+        let planar: Vec<&[u8]> = state.work.iter().map(|v| &v[..bytes]).collect();
+        state.sink.write(&planar, bytes)?;
+        if let Some(kind) = state.sink.take_xrun() {
+            Self::record_xrun(state, kind);
+        }
+        Ok(())
+    }
+
+    ///Writes the idle pattern in periods, blocks until the output took all of it
+    fn write_silence(state: &mut PlayerState, mut bytes: u64) -> Result<(), PlayerError> {
+        let period_bytes = state.sink.period_bytes();
+        while bytes > 0 {
+            let chunk = (bytes as usize).min(period_bytes);
+            for work in state.work.iter_mut() {
+                fill_silence(&mut work[..chunk], state.format.is_lsb_first);
+            }
+            Self::write_work(state, chunk)?;
+            bytes -= chunk as u64;
+        }
+        state.music_written = 0;
+        Ok(())
+    }
+
+    ///Silence is only played to outputs ending in a DAC
+    fn request_silence(state: &mut PlayerState, time: Duration) {
+        if state.sink.needs_silence() {
+            state.silence.request(silence_bytes(time, state.format.sampling_rate));
+        }
+    }
+
+    ///Lets the output end on silence instead of cutting the music, written before a drain
+    fn write_mute(state: &mut PlayerState) -> Result<(), PlayerError> {
+        if !Self::mutes(state) {
+            return Ok(());
+        }
+        Self::write_silence(state, silence_bytes(state.mute, state.format.sampling_rate))
+    }
+
+    fn mutes(state: &PlayerState) -> bool {
+        state.sink.needs_silence() && !state.mute.is_zero()
+    }
+
+    fn record_xrun(state: &mut PlayerState, kind: XrunKind) {
+        let xrun = Xrun {
+            kind,
+            at: SystemTime::now(),
+            position: PlaybackTime::from_bytes(Self::heard(state), state.format.sampling_rate),
+        };
+        state.shared.xruns.lock().unwrap().record(xrun);
+        state.emit(PlayerEvent::Xrun(xrun));
+    }
+
+    fn playback_poll(state: &mut PlayerState) -> Result<(), PlayerError> {
+        if !state.playing || state.serial == 0 {
+            return Ok(());
+        }
+
+        if state.paused {
+            if state.first_paused {
+                state.first_paused = false;
+                if Self::mutes(state) {
+                    // Queued music is dropped for the mute sequence, resume continues from what was heard
+                    let heard = Self::heard(state);
+                    state.sink.flush()?;
+                    state.silence.clear();
+                    Self::reposition(state, SeekTarget::Bytes(heard))?;
+                    Self::write_mute(state)?;
+                    state.sink.drain()?;
+                }
+                state.sink.pause(true)?;
+            }
+            return Ok(());
+        } else if state.released_pause {
+            state.released_pause = false;
+            state.sink.pause(false)?;
+            Self::request_silence(state, state.mute);
+        }
+        // Commands interrupt the wait, the loop handles them before the next period is written
+        if !state.sink.wait_writable(&state.shared.wakeup, OUTPUT_WAIT)? {
+            return Ok(());
+        }
+        let silence = state.silence.take(state.sink.period_bytes());
+        if silence > 0 {
+            return Self::write_silence(state, silence as u64);
+        }
+
+        let period_bytes = state.sink.period_bytes();
+        let mut filled = 0;
+        while filled < period_bytes {
+            let (block, offset) = match state.partial.take() {
+                Some(partial) => partial,
+                None => match state.read_ahead.pop() {
+                    Some(Item::Data(block)) => (block, 0),
+                    Some(Item::TrackStart { serial, format, meta, length }) => {
+                        if state.enqueued == serial {
+                            state.enqueued = 0;
+                        }
+                        state.serial = serial;
+                        state.position_bytes = 0;
+                        if !needs_reopen(&state.format, &format) {
+                            // Same output setup, the rest of the period comes from the next track and the sink never notices the switch
+                            state.emit(PlayerEvent::TrackEnded);
+                            Self::switch_track(state, format, meta, length);
+                            continue;
+                        }
+                        Self::write_period(state, filled)?;
+                        Self::write_mute(state)?;
+                        state.sink.drain()?;
+                        state.emit(PlayerEvent::TrackEnded);
+                        Self::switch_track(state, format, meta, length);
+                        return Self::open_sink(state);
+                    }
+                    // Enqueued track was sent after the read-ahead had finished, its start follows
+                    Some(Item::End) if state.enqueued != 0 => continue,
+                    Some(Item::End) => {
+                        Self::write_period(state, filled)?;
+                        Self::write_mute(state)?;
+                        state.sink.drain()?;
+                        state.emit(PlayerEvent::TrackEnded);
+                        state.playing = false;
+                        state.track_ended = true;
+                        return Ok(());
+                    }
+                    Some(Item::Error(e)) => {
+                        // Next start begins the track again
+                        state.track_ended = true;
+                        Self::write_period(state, filled)?;
+                        return Err(e);
+                    }
+                    // Read-ahead fell behind, write what is there
+                    None => break,
+                },
+            };
+            let take = (block.bytes - offset).min(period_bytes - filled);
+            for (work, data) in state.work.iter_mut().zip(block.data.iter()) {
+                work[filled..filled + take].copy_from_slice(&data[offset..offset + take]);
+            }
+            filled += take;
+            let left = block.bytes - offset - take;
+            state.position_bytes = block.position_bytes - left as u64;
+            if left > 0 {
+                state.partial = Some((block, offset + take));
+            } else {
+                state.read_ahead.recycle(block.data);
+            }
+        }
+        if filled == 0 {
+            state.read_ahead.wait(UNDERRUN_WAIT);
+            return Ok(());
+        }
+        Self::write_period(state, filled)
+    }
+}
@@ -0,0 +1,416 @@
+use super::Track;
+use crate::players::{BufferLevel, PlayerError};
+use crossbeam::channel;
+use crossbeam::queue::ArrayQueue;
+use crossbeam::sync::{Parker, Unparker};
+use ndsd_read::{DSDFormat, DSDMeta};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Producer reads blocks of 1/BLOCKS_PER_SECOND second
+const BLOCKS_PER_SECOND: u64 = 100;
+const PRODUCER_IDLE_WAIT: Duration = Duration::from_millis(20);
+
+///Planar dsd read from the track, position is the reader position after the block in bytes per channel
+pub(super) struct Block {
+    pub data: Vec<Vec<u8>>,
+    pub bytes: usize,
+    pub position_bytes: u64,
+}
+
+pub(super) enum Item {
+    Data(Block),
+    ///Enqueued track starts here, everything before belongs to the previous one
+    TrackStart {
+        serial: u64,
+        format: DSDFormat,
+        meta: Option<DSDMeta>,
+        length: u64,
+    },
+    ///Track ended and nothing was enqueued after it
+    End,
+    Error(PlayerError),
+}
+
+pub(super) enum SeekTarget {
+    Start,
+    Percent(f64),
+    ///Bytes per channel from the track start
+    Bytes(u64),
+}
+
+///Track with the serial the engine gave it, serials tell which track the consumer is on
+struct Slot {
+    track: Track,
+    serial: u64,
+}
+
+enum Command {
+    Load(Slot, u64, u64, channel::Sender<()>),
+    Enqueue(Slot),
+    Seek(SeekTarget, u64, u64, channel::Sender<Result<u64, PlayerError>>),
+    Quit,
+}
+
+///Fill level shared with the player handle, all values in bytes per channel
+#[derive(Default)]
+pub(super) struct Level {
+    queued: AtomicU64,
+    limit: AtomicU64,
+    bytes_per_second: AtomicU64,
+}
+
+impl Level {
+    pub fn report(&self) -> BufferLevel {
+        let bytes_per_second = self.bytes_per_second.load(Relaxed).max(1) as f64;
+        BufferLevel {
+            filled: Duration::from_secs_f64(self.queued.load(Relaxed) as f64 / bytes_per_second),
+            depth: Duration::from_secs_f64(self.limit.load(Relaxed) as f64 / bytes_per_second),
+        }
+    }
+}
+
+///Reads tracks on its own thread into a lock-free SPSC ring, so slow storage or DST decoding
+/// does not reach the output as long as the ring has data.
+/// Every load and seek starts a new generation, items of older generations are dropped on the consumer side
+pub(super) struct ReadAhead {
+    queue: Arc<ArrayQueue<(u64, Item)>>,
+    free: Arc<ArrayQueue<Vec<Vec<u8>>>>,
+    level: Arc<Level>,
+    control: channel::Sender<Command>,
+    generation: u64,
+    // First item of the current generation found while dropping stale ones
+    pending: Option<Item>,
+    parker: Parker,
+    producer_unparker: Unparker,
+    producer: Option<JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    pub fn new(depth: Duration, level: Arc<Level>) -> Self {
+        let capacity = ((depth.as_secs_f64() * BLOCKS_PER_SECOND as f64).ceil() as usize).max(4) * 2;
+        let queue = Arc::new(ArrayQueue::new(capacity));
+        let free = Arc::new(ArrayQueue::new(capacity));
+        let (control, commands) = channel::unbounded();
+        let parker = Parker::new();
+        let producer_parker = Parker::new();
+        let producer_unparker = producer_parker.unparker().clone();
+        let producer = Producer {
+            queue: queue.clone(),
+            free: free.clone(),
+            level: level.clone(),
+            depth,
+            commands,
+            parker: producer_parker,
+            consumer: parker.unparker().clone(),
+            generation: 0,
+            current: None,
+            previous: None,
+            next: None,
+            finished: false,
+            block_bytes: 0,
+        };
+        Self {
+            queue,
+            free,
+            level,
+            control,
+            generation: 0,
+            pending: None,
+            parker,
+            producer_unparker,
+            producer: Some(std::thread::spawn(move || producer.run())),
+        }
+    }
+
+    fn send(&self, command: Command) {
+        let _ = self.control.send(command);
+        self.producer_unparker.unpark();
+    }
+
+    ///Replaces the track the consumer is on, enqueued track stays
+    pub fn load(&mut self, track: Track, serial: u64, playing_serial: u64) {
+        self.generation += 1;
+        let (ack, done) = channel::bounded(1);
+        self.send(Command::Load(Slot { track, serial }, playing_serial, self.generation, ack));
+        let _ = done.recv();
+        self.discard_stale();
+    }
+
+    ///Track read right after the current one.
+    /// Once the producer got to the enqueued track it is kept and the new one plays after it
+    pub fn enqueue(&mut self, track: Track, serial: u64) {
+        self.send(Command::Enqueue(Slot { track, serial }));
+    }
+
+    ///Repositions the track the consumer is on, returns the position it landed on in bytes per channel
+    pub fn seek(&mut self, target: SeekTarget, playing_serial: u64) -> Result<u64, PlayerError> {
+        self.generation += 1;
+        let (reply, result) = channel::bounded(1);
+        self.send(Command::Seek(target, playing_serial, self.generation, reply));
+        let res = result.recv().map_err(|_| PlayerError::Terminated)?;
+        self.discard_stale();
+        res
+    }
+
+    ///Next item of the current generation, None if the producer has nothing read yet
+    pub fn pop(&mut self) -> Option<Item> {
+        if let Some(item) = self.pending.take() {
+            return Some(item);
+        }
+        while let Some((generation, item)) = self.take() {
+            if generation == self.generation {
+                return Some(item);
+            }
+            self.drop_item(item);
+        }
+        None
+    }
+
+    ///Returns buffers of a consumed block to the producer
+    pub fn recycle(&self, data: Vec<Vec<u8>>) {
+        let _ = self.free.push(data);
+    }
+
+    ///Waits until the producer pushed something or the timeout expired
+    pub fn wait(&self, timeout: Duration) {
+        if self.pending.is_none() && self.queue.is_empty() {
+            self.parker.park_timeout(timeout);
+        }
+    }
+
+    fn take(&self) -> Option<(u64, Item)> {
+        let entry = self.queue.pop()?;
+        if let (_, Item::Data(block)) = &entry {
+            self.level.queued.fetch_sub(block.bytes as u64, Relaxed);
+        }
+        self.producer_unparker.unpark();
+        Some(entry)
+    }
+
+    fn drop_item(&self, item: Item) {
+        if let Item::Data(block) = item {
+            self.recycle(block.data);
+        }
+    }
+
+    ///Producer already switched to the new generation, so everything before its first item is stale
+    fn discard_stale(&mut self) {
+        if let Some(item) = self.pending.take() {
+            self.drop_item(item);
+        }
+        while let Some((generation, item)) = self.take() {
+            if generation == self.generation {
+                self.pending = Some(item);
+                return;
+            }
+            self.drop_item(item);
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(producer) = self.producer.take() {
+            let _ = producer.join();
+        }
+    }
+}
+
+struct Producer {
+    queue: Arc<ArrayQueue<(u64, Item)>>,
+    free: Arc<ArrayQueue<Vec<Vec<u8>>>>,
+    level: Arc<Level>,
+    depth: Duration,
+    commands: channel::Receiver<Command>,
+    parker: Parker,
+    consumer: Unparker,
+    generation: u64,
+    current: Option<Slot>,
+    // Track the consumer may still be playing after the producer moved to the enqueued one
+    previous: Option<Slot>,
+    next: Option<Slot>,
+    finished: bool,
+    block_bytes: usize,
+}
+
+impl Producer {
+    fn run(mut self) {
+        loop {
+            let idle = self.current.is_none() || self.finished;
+            let command = if idle {
+                // Nothing to read, sleep until the next command
+                match self.commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            } else {
+                self.commands.try_recv().ok()
+            };
+            if let Some(command) = command {
+                if !self.handle(command) {
+                    return;
+                }
+                continue;
+            }
+            let full = self.level.queued.load(Relaxed) >= self.level.limit.load(Relaxed)
+                || self.queue.len() + 2 >= self.queue.capacity();
+            if full {
+                self.parker.park_timeout(PRODUCER_IDLE_WAIT);
+                continue;
+            }
+            self.read_block();
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Load(slot, playing_serial, generation, ack) => {
+                self.generation = generation;
+                self.rewind(playing_serial);
+                self.set_current(slot);
+                let _ = ack.send(());
+            }
+            Command::Enqueue(slot) => {
+                if self.finished {
+                    // End was pushed already, consumer expects the track start behind it
+                    self.advance(slot);
+                } else {
+                    self.next = Some(slot);
+                }
+            }
+            Command::Seek(target, playing_serial, generation, reply) => {
+                self.generation = generation;
+                self.rewind(playing_serial);
+                let _ = reply.send(self.seek(target));
+            }
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    fn seek(&mut self, target: SeekTarget) -> Result<u64, PlayerError> {
+        let Some(slot) = self.current.as_mut() else {
+            return Err(PlayerError::NoTrack);
+        };
+        let reader = &mut slot.track.reader;
+        match target {
+            SeekTarget::Start => reader.reset(),
+            SeekTarget::Percent(percent) => reader.seek_percent(percent),
+            SeekTarget::Bytes(bytes) => reader.seek_samples(bytes),
+        }
+        .map_err(PlayerError::read)?;
+        self.finished = false;
+        Ok(reader.get_position_frames())
+    }
+
+    ///Goes back to the track the consumer plays, the track read ahead of it becomes enqueued again
+    fn rewind(&mut self, playing_serial: u64) {
+        let Some(previous) = self.previous.take() else {
+            return;
+        };
+        if previous.serial != playing_serial {
+            return;
+        }
+        if let Some(mut slot) = self.current.take() {
+            // Failed reset shows up as read error once the track is reached
+            let _ = slot.track.reader.reset();
+            self.next = Some(slot);
+        }
+        self.current = Some(previous);
+    }
+
+    fn set_current(&mut self, slot: Slot) {
+        let bytes_per_second = (slot.track.format.sampling_rate / 8) as u64;
+        // Multiple of 8 bytes keeps every output word size aligned inside a block
+        self.block_bytes = ((bytes_per_second / BLOCKS_PER_SECOND) as usize)
+            .next_multiple_of(8)
+            .max(8);
+        self.level.bytes_per_second.store(bytes_per_second, Relaxed);
+        self.level
+            .limit
+            .store((bytes_per_second as f64 * self.depth.as_secs_f64()) as u64, Relaxed);
+        self.current = Some(slot);
+        self.finished = false;
+    }
+
+    fn advance(&mut self, next: Slot) {
+        self.push(Item::TrackStart {
+            serial: next.serial,
+            format: next.track.format,
+            meta: next.track.meta.clone(),
+            length: next.track.length,
+        });
+        self.previous = self.current.take();
+        self.set_current(next);
+    }
+
+    fn push(&self, item: Item) {
+        if let Item::Data(block) = &item {
+            self.level.queued.fetch_add(block.bytes as u64, Relaxed);
+        }
+        let mut entry = (self.generation, item);
+        // Space is checked before reading, so this waits only while the consumer drops stale items
+        while let Err(back) = self.queue.push(entry) {
+            entry = back;
+            self.parker.park_timeout(PRODUCER_IDLE_WAIT);
+        }
+        self.consumer.unpark();
+    }
+
+    fn read_block(&mut self) {
+        let Some(slot) = self.current.as_mut() else {
+            return;
+        };
+        let track = &mut slot.track;
+        let mut want = self.block_bytes;
+        if let Some(end) = track.end {
+            want = want.min(end.saturating_sub(track.reader.get_position_frames()) as usize);
+        }
+        let mut data = self.free.pop().unwrap_or_default();
+        data.resize_with(track.format.num_channels as usize, Vec::new);
+        for channel in data.iter_mut() {
+            channel.resize(self.block_bytes, 0);
+        }
+        let bytes = if want == 0 {
+            0
+        } else {
+            let mut slices: Vec<&mut [u8]> = data.iter_mut().map(|c| &mut c[..want]).collect();
+            match track.reader.read(&mut slices, want) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    self.finished = true;
+                    self.push(Item::Error(PlayerError::read(e)));
+                    return;
+                }
+            }
+        };
+        // Reader can run into the end of file before its sample count, so an empty read ends the track as well
+        let finished = bytes == 0
+            || track.reader.eof()
+            || track.end.is_some_and(|end| track.reader.get_position_frames() >= end);
+        if bytes > 0 {
+            let block = Block {
+                data,
+                bytes,
+                position_bytes: track.reader.get_position_frames(),
+            };
+            self.push(Item::Data(block));
+        } else {
+            let _ = self.free.push(data);
+        }
+        if finished {
+            match self.next.take() {
+                Some(next) => self.advance(next),
+                None => {
+                    self.finished = true;
+                    self.push(Item::End);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::players::{PlaybackTime, PlayerError};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+type Outcome = Result<PlaybackTime, PlayerError>;
+
+///Answer of the player thread to a single command.
+/// Needs no async runtime, the receiver is awaited on any executor or blocked on by SyncPlayer
+pub(super) fn channel() -> (Reply, ReplyReceiver) {
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    (Reply(slot.clone()), ReplyReceiver(slot))
+}
+
+#[derive(Default)]
+struct Slot {
+    outcome: Option<Outcome>,
+    // Set when the reply was sent or dropped without an answer
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+pub(super) struct Reply(Arc<Mutex<Slot>>);
+
+impl Reply {
+    pub fn send(self, outcome: Outcome) {
+        self.0.lock().unwrap().outcome = Some(outcome);
+        // Drop closes the slot and wakes the receiver
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        let mut state = self.0.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+///None if the player thread is gone without answering
+pub(super) struct ReplyReceiver(Arc<Mutex<Slot>>);
+
+impl Future for ReplyReceiver {
+    type Output = Option<Outcome>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(state.outcome.take());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+///Everything that can go wrong between the track and the DAC.
+/// Device errors carry the errno reported by the driver (positive, 0 when there is none) and its text,
+/// for ALSA that is what snd_strerror returns
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerError {
+    ///Device does not exist, is busy or can not be opened
+    DeviceOpen { device: String, errno: i32, message: String },
+    ///Device has neither native dsd nor DoP capable format
+    UnsupportedFormat { errno: i32, message: String },
+    ///Device can not run at the rate required for the track, rate is the device rate, not the dsd rate
+    UnsupportedRate { rate: u32, errno: i32, message: String },
+    UnsupportedChannels { channels: u32, errno: i32, message: String },
+    ///Device rejected hardware or software parameters
+    DeviceSetup { errno: i32, message: String },
+    ///Underrun or suspend the device could not recover from
+    Xrun { errno: i32, message: String },
+    ///Device disappeared during playback, e.g usb DAC was unplugged
+    DeviceLost { device: String, errno: i32, message: String },
+    ///Any other device failure during playback
+    Device { errno: i32, message: String },
+    ///Track could not be opened or its header is broken
+    TrackOpen { path: PathBuf, message: String },
+    ///Reading or seeking the track failed
+    Read { kind: io::ErrorKind, message: String },
+    ///Output which is not a sound device failed, e.g capture file or network connection
+    Io { kind: io::ErrorKind, message: String },
+    ///PlayerConfig values do not fit together
+    InvalidConfig { message: String },
+    ///Command requires a track, but none was loaded
+    NoTrack,
+    ///Player thread is gone, the player has to be recreated
+    Terminated,
+}
+
+impl PlayerError {
+    ///Wraps reader failure
+    pub fn read(e: io::Error) -> Self {
+        PlayerError::Read {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+
+    ///Errno reported by the device driver, if the error came from it
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            PlayerError::DeviceOpen { errno, .. }
+            | PlayerError::UnsupportedFormat { errno, .. }
+            | PlayerError::UnsupportedRate { errno, .. }
+            | PlayerError::UnsupportedChannels { errno, .. }
+            | PlayerError::DeviceSetup { errno, .. }
+            | PlayerError::Xrun { errno, .. }
+            | PlayerError::DeviceLost { errno, .. }
+            | PlayerError::Device { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PlayerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerError::DeviceOpen { device, errno, message } => {
+                write!(f, "cannot open audio device {}: {} (errno {})", device, message, errno)
+            }
+            PlayerError::UnsupportedFormat { errno, message } => {
+                write!(f, "device has no dsd or DoP format: {} (errno {})", message, errno)
+            }
+            PlayerError::UnsupportedRate { rate, errno, message } => {
+                write!(f, "device does not support rate {}: {} (errno {})", rate, message, errno)
+            }
+            PlayerError::UnsupportedChannels { channels, errno, message } => {
+                write!(f, "device does not support {} channels: {} (errno {})", channels, message, errno)
+            }
+            PlayerError::DeviceSetup { errno, message } => {
+                write!(f, "cannot configure audio device: {} (errno {})", message, errno)
+            }
+            PlayerError::Xrun { errno, message } => {
+                write!(f, "unrecoverable xrun: {} (errno {})", message, errno)
+            }
+            PlayerError::DeviceLost { device, errno, message } => {
+                write!(f, "audio device {} disconnected: {} (errno {})", device, message, errno)
+            }
+            PlayerError::Device { errno, message } => {
+                write!(f, "audio device error: {} (errno {})", message, errno)
+            }
+            PlayerError::TrackOpen { path, message } => {
+                write!(f, "cannot open track {}: {}", path.display(), message)
+            }
+            PlayerError::Read { message, .. } => write!(f, "cannot read track: {}", message),
+            PlayerError::Io { message, .. } => write!(f, "output error: {}", message),
+            PlayerError::InvalidConfig { message } => write!(f, "invalid player config: {}", message),
+            PlayerError::NoTrack => write!(f, "no track loaded"),
+            PlayerError::Terminated => write!(f, "player thread terminated"),
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {}
+
+///Output errors of sinks which are not sound devices
+impl From<io::Error> for PlayerError {
+    fn from(e: io::Error) -> Self {
+        PlayerError::Io {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<PlayerError> for io::Error {
+    fn from(e: PlayerError) -> Self {
+        match e {
+            PlayerError::Io { kind, message } | PlayerError::Read { kind, message } => {
+                io::Error::new(kind, message)
+            }
+            e => io::Error::other(e),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::players::{PlayerError, Xrun};
+use ndsd_read::{DSDFormat, DSDMeta};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, TrySendError};
+
+///Capacity of the event channel. Slow async subscribers lose the oldest events and get RecvError::Lagged,
+/// blocking ones lose the newest while their channel is full
+pub const EVENT_CHANNEL_CAPACITY: usize = 64;
+///How often PositionTick is sent while playing
+pub const POSITION_TICK_MS: u64 = 250;
+
+///What happened to the player, delivered to every receiver returned by DSDPlayer::subscribe and subscribe_blocking
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    TrackLoaded {
+        format: DSDFormat,
+        meta: Option<DSDMeta>,
+    },
+    Started,
+    Paused,
+    Resumed,
+    ///Stopped by the caller, as opposed to TrackEnded
+    Stopped,
+    ///Position in percent (0..1) playback continues from
+    Seeked { position: f64 },
+    ///Position in percent (0..1), sent every POSITION_TICK_MS while playing
+    PositionTick { position: f64 },
+    ///Track was played to the end and the output drained
+    TrackEnded,
+    ///New track needs different output setup than the previous one
+    FormatChanged(DSDFormat),
+    ///Output underran or was suspended and playback went on after recovery
+    Xrun(Xrun),
+    ///Device disappeared, the player keeps reopening it as the ReconnectPolicy says
+    DeviceLost(PlayerError),
+    ///Lost device is back, playback continues where it was heard last
+    DeviceReconnected,
+    ///Playback stopped because of the error
+    DeviceError(PlayerError),
+}
+
+///Sends every event to the async and the blocking subscribers of a player
+pub(crate) struct EventBus {
+    #[cfg(feature = "tokio")]
+    broadcast: tokio::sync::broadcast::Sender<PlayerEvent>,
+    blocking: Mutex<Vec<mpsc::SyncSender<PlayerEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "tokio")]
+            broadcast: tokio::sync::broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            blocking: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn send(&self, event: PlayerEvent) {
+        let mut blocking = self.blocking.lock().unwrap();
+        // Receivers which were dropped are forgotten, full ones miss the event
+        blocking.retain(|sender| !matches!(sender.try_send(event.clone()), Err(TrySendError::Disconnected(_))));
+        // Nobody listening is not an error
+        #[cfg(feature = "tokio")]
+        let _ = self.broadcast.send(event);
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PlayerEvent> {
+        self.broadcast.subscribe()
+    }
+
+    pub fn subscribe_blocking(&self) -> mpsc::Receiver<PlayerEvent> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        self.blocking.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,399 @@
+use crate::players::{AudioSink, PlayerError};
+use crate::utils::bit_reverse_table::BIT_REVERSE_TABLE;
+use ndsd_read::DSDFormat;
+use std::fs::File;
+use std::io::{BufWriter, Error, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+///Pseudo device id prefix, "file:/tmp/capture.dsf" creates a FileSink instead of opening a device
+pub const FILE_DEVICE_PREFIX: &str = "file:";
+
+const DSF_BLOCK_SIZE: usize = 4096;
+const DSF_FMT_OFFSET: u64 = 28;
+const DSF_DATA_OFFSET: u64 = DSF_FMT_OFFSET + 52;
+const PERIOD_BYTES: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Container {
+    Dsf,
+    Dff,
+}
+
+struct Capture {
+    out: BufWriter<File>,
+    container: Container,
+    format: DSDFormat,
+    // DSF stores data in per channel blocks, so we keep the tail of every channel until block is full
+    pending: Vec<Vec<u8>>,
+    bytes_per_channel: u64,
+    dff_data_offset: u64,
+}
+
+///Writes everything the player would send to the DAC into a DSF or DFF file.
+/// Container is picked from the file extension, DSF is the default.
+/// Data is stored in the bit order it came from the reader, so the capture can be compared byte by byte with the source.
+/// Every format switch starts a new file: capture.dsf, capture-1.dsf, capture-2.dsf...
+pub struct FileSink {
+    path: PathBuf,
+    capture: Option<Capture>,
+    files_written: usize,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            capture: None,
+            files_written: 0,
+        }
+    }
+
+    ///Paths of files produced by this sink so far, in order of creation
+    pub fn written_files(&self) -> Vec<PathBuf> {
+        (0..self.files_written).map(|i| self.nth_path(i)).collect()
+    }
+
+    fn nth_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let stem = self
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = match self.path.extension() {
+            Some(ext) => format!("{}-{}.{}", stem, index, ext.to_string_lossy()),
+            None => format!("{}-{}", stem, index),
+        };
+        self.path.with_file_name(name)
+    }
+
+    fn container_for(path: &Path) -> Container {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("dff") => Container::Dff,
+            _ => Container::Dsf,
+        }
+    }
+}
+
+impl Capture {
+    fn create(path: &Path, container: Container, format: &DSDFormat) -> Result<Self, Error> {
+        let mut res = Self {
+            out: BufWriter::new(File::create(path)?),
+            container,
+            format: *format,
+            pending: (0..format.num_channels).map(|_| Vec::new()).collect(),
+            bytes_per_channel: 0,
+            dff_data_offset: 0,
+        };
+        match container {
+            Container::Dsf => res.write_dsf_header()?,
+            Container::Dff => res.write_dff_header()?,
+        }
+        Ok(res)
+    }
+
+    fn write_dsf_header(&mut self) -> Result<(), Error> {
+        let out = &mut self.out;
+        out.write_all(b"DSD ")?;
+        out.write_all(&28u64.to_le_bytes())?;
+        out.write_all(&0u64.to_le_bytes())?; // total file size, patched later
+        out.write_all(&0u64.to_le_bytes())?; // no metadata chunk
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&52u64.to_le_bytes())?;
+        out.write_all(&1u32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        let channel_type: u32 = match self.format.num_channels {
+            5 => 6,
+            6 => 7,
+            n => n,
+        };
+        out.write_all(&channel_type.to_le_bytes())?;
+        out.write_all(&self.format.num_channels.to_le_bytes())?;
+        out.write_all(&self.format.sampling_rate.to_le_bytes())?;
+        let bits_per_sample: u32 = if self.format.is_lsb_first { 1 } else { 8 };
+        out.write_all(&bits_per_sample.to_le_bytes())?;
+        out.write_all(&0u64.to_le_bytes())?; // sample count, patched later
+        out.write_all(&(DSF_BLOCK_SIZE as u32).to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&12u64.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write_dff_header(&mut self) -> Result<(), Error> {
+        let channels = self.format.num_channels as usize;
+        let ids: Vec<[u8; 4]> = match channels {
+            2 => vec![*b"SLFT", *b"SRGT"],
+            5 => vec![*b"MLFT", *b"MRGT", *b"C   ", *b"LS  ", *b"RS  "],
+            6 => vec![*b"MLFT", *b"MRGT", *b"C   ", *b"LFE ", *b"LS  ", *b"RS  "],
+            _ => (0..channels)
+                .map(|i| {
+                    let mut id = *b"C000";
+                    id[1..].copy_from_slice(format!("{:03}", i).as_bytes());
+                    id
+                })
+                .collect(),
+        };
+        let cmpr_name = b"not compressed";
+        let fs_size = 4u64;
+        let chnl_size = 2 + 4 * channels as u64;
+        let cmpr_size = 4 + 1 + cmpr_name.len() as u64 + 1;
+        let prop_size = 4 + (12 + fs_size) + (12 + chnl_size) + (12 + cmpr_size);
+
+        let out = &mut self.out;
+        out.write_all(b"FRM8")?;
+        out.write_all(&0u64.to_be_bytes())?; // patched later
+        out.write_all(b"DSD ")?;
+
+        out.write_all(b"FVER")?;
+        out.write_all(&4u64.to_be_bytes())?;
+        out.write_all(&0x0105_0000u32.to_be_bytes())?;
+
+        out.write_all(b"PROP")?;
+        out.write_all(&prop_size.to_be_bytes())?;
+        out.write_all(b"SND ")?;
+        out.write_all(b"FS  ")?;
+        out.write_all(&fs_size.to_be_bytes())?;
+        out.write_all(&self.format.sampling_rate.to_be_bytes())?;
+        out.write_all(b"CHNL")?;
+        out.write_all(&chnl_size.to_be_bytes())?;
+        out.write_all(&(channels as u16).to_be_bytes())?;
+        for id in ids.iter() {
+            out.write_all(id)?;
+        }
+        out.write_all(b"CMPR")?;
+        out.write_all(&cmpr_size.to_be_bytes())?;
+        out.write_all(b"DSD ")?;
+        out.write_all(&[cmpr_name.len() as u8])?;
+        out.write_all(cmpr_name)?;
+        out.write_all(&[0u8])?; // pad to even size
+
+        out.write_all(b"DSD ")?;
+        out.write_all(&0u64.to_be_bytes())?; // patched later
+        self.dff_data_offset = 12 + 4 + (12 + 4) + (12 + prop_size) + 12;
+        Ok(())
+    }
+
+    fn append(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), Error> {
+        match self.container {
+            Container::Dsf => {
+                for (pending, channel) in self.pending.iter_mut().zip(data.iter()) {
+                    pending.extend_from_slice(&channel[..bytes_per_channel]);
+                }
+                self.flush_blocks(false)?;
+            }
+            Container::Dff => {
+                // DFF is always msb first and byte interleaved
+                let mut frame = vec![0u8; data.len() * bytes_per_channel];
+                for i in 0..bytes_per_channel {
+                    for (ch, channel) in data.iter().enumerate() {
+                        let byte = channel[i];
+                        frame[i * data.len() + ch] = if self.format.is_lsb_first {
+                            BIT_REVERSE_TABLE[byte as usize]
+                        } else {
+                            byte
+                        };
+                    }
+                }
+                self.out.write_all(&frame)?;
+            }
+        }
+        self.bytes_per_channel += bytes_per_channel as u64;
+        Ok(())
+    }
+
+    fn flush_blocks(&mut self, pad_last: bool) -> Result<(), Error> {
+        if self.container != Container::Dsf {
+            return Ok(());
+        }
+        while self.pending.first().is_some_and(|p| p.len() >= DSF_BLOCK_SIZE) {
+            for pending in self.pending.iter_mut() {
+                self.out.write_all(&pending[..DSF_BLOCK_SIZE])?;
+                pending.drain(..DSF_BLOCK_SIZE);
+            }
+        }
+        if pad_last && self.pending.first().is_some_and(|p| !p.is_empty()) {
+            for pending in self.pending.iter_mut() {
+                pending.resize(DSF_BLOCK_SIZE, 0);
+                self.out.write_all(pending)?;
+                pending.clear();
+            }
+        }
+        Ok(())
+    }
+
+    ///Patches chunk sizes and sample count, so the file stays readable while capture is still going on
+    fn update_header(&mut self) -> Result<(), Error> {
+        // Tail kept for the next block is not in the file yet
+        let written = self.bytes_per_channel - self.pending.first().map_or(0, |p| p.len() as u64);
+        self.out.flush()?;
+        let file = self.out.get_mut();
+        let end = file.seek(SeekFrom::End(0))?;
+        match self.container {
+            Container::Dsf => {
+                file.seek(SeekFrom::Start(12))?;
+                file.write_all(&end.to_le_bytes())?;
+                file.seek(SeekFrom::Start(DSF_FMT_OFFSET + 36))?;
+                file.write_all(&(written * 8).to_le_bytes())?;
+                file.seek(SeekFrom::Start(DSF_DATA_OFFSET + 4))?;
+                file.write_all(&(end - DSF_DATA_OFFSET).to_le_bytes())?;
+            }
+            Container::Dff => {
+                let data_size = end - self.dff_data_offset;
+                file.seek(SeekFrom::Start(4))?;
+                file.write_all(&(end - 12).to_be_bytes())?;
+                file.seek(SeekFrom::Start(self.dff_data_offset - 8))?;
+                file.write_all(&data_size.to_be_bytes())?;
+            }
+        }
+        file.seek(SeekFrom::End(0))?;
+        file.flush()
+    }
+}
+
+impl AudioSink for FileSink {
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+        self.finish()?;
+        let path = self.nth_path(self.files_written);
+        let container = Self::container_for(&self.path);
+        self.capture = Some(Capture::create(&path, container, format)?);
+        self.files_written += 1;
+        Ok(())
+    }
+
+    fn period_bytes(&self) -> usize {
+        PERIOD_BYTES
+    }
+
+    fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+        match self.capture.as_mut() {
+            Some(capture) => Ok(capture.append(data, bytes_per_channel)?),
+            None => Err(Error::other("file sink is not opened").into()),
+        }
+    }
+
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+        if paused && let Some(capture) = self.capture.as_mut() {
+            capture.update_header()?;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<(), PlayerError> {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.update_header()?;
+        }
+        Ok(())
+    }
+
+    fn delay(&self) -> usize {
+        0
+    }
+
+    ///Pads the last DSF block and completes the header of the current file, nothing is written into it afterwards
+    fn finish(&mut self) -> Result<(), PlayerError> {
+        if let Some(mut capture) = self.capture.take() {
+            capture.flush_blocks(true)?;
+            capture.update_header()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        // Errors reach the caller through finish, a sink dropped without it keeps the header of the last drain
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(channels: usize, bytes: usize) -> Vec<Vec<u8>> {
+        (0..channels)
+            .map(|ch| (0..bytes).map(|i| (i * 7 + ch * 31) as u8).collect())
+            .collect()
+    }
+
+    fn roundtrip(name: &str, lsb_first: bool) {
+        let path = std::env::temp_dir().join(name);
+        let format = DSDFormat {
+            sampling_rate: 2822400,
+            num_channels: 2,
+            total_samples: 0,
+            is_lsb_first: lsb_first,
+        };
+        let source = pattern(2, 3 * DSF_BLOCK_SIZE);
+        {
+            let mut sink = FileSink::new(&path);
+            sink.open(&format).unwrap();
+            for chunk in 0..3 {
+                let range = chunk * DSF_BLOCK_SIZE..(chunk + 1) * DSF_BLOCK_SIZE;
+                let slices: Vec<&[u8]> = source.iter().map(|c| &c[range.clone()]).collect();
+                sink.write(&slices, DSF_BLOCK_SIZE).unwrap();
+            }
+        }
+
+        let mut read_format = DSDFormat::default();
+        let mut reader = ndsd_read::open_dsd_auto(path.to_str().unwrap(), &mut read_format).unwrap();
+        assert_eq!(read_format.sampling_rate, format.sampling_rate);
+        assert_eq!(read_format.num_channels, format.num_channels);
+
+        let mut out = vec![vec![0u8; source[0].len()]; 2];
+        let mut slices: Vec<&mut [u8]> = out.iter_mut().map(|v| v.as_mut_slice()).collect();
+        let read = reader.read(&mut slices, source[0].len()).unwrap();
+        assert_eq!(read, source[0].len());
+        for (ch, data) in out.iter().enumerate() {
+            let expected: Vec<u8> = if read_format.is_lsb_first == lsb_first {
+                source[ch].clone()
+            } else {
+                source[ch].iter().map(|b| BIT_REVERSE_TABLE[*b as usize]).collect()
+            };
+            assert_eq!(data, &expected);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn paused_capture_counts_written_blocks() {
+        let path = std::env::temp_dir().join("ndsd_capture_paused.dsf");
+        let format = DSDFormat {
+            sampling_rate: 2822400,
+            num_channels: 2,
+            total_samples: 0,
+            is_lsb_first: true,
+        };
+        let source = pattern(2, DSF_BLOCK_SIZE + DSF_BLOCK_SIZE / 2);
+        let mut sink = FileSink::new(&path);
+        sink.open(&format).unwrap();
+        let slices: Vec<&[u8]> = source.iter().map(|c| c.as_slice()).collect();
+        sink.write(&slices, source[0].len()).unwrap();
+        sink.pause(true).unwrap();
+
+        let mut read_format = DSDFormat::default();
+        let mut reader = ndsd_read::open_dsd_auto(path.to_str().unwrap(), &mut read_format).unwrap();
+        assert_eq!(read_format.total_samples, DSF_BLOCK_SIZE as u64 * 8);
+        let mut out = vec![vec![0u8; source[0].len()]; 2];
+        let mut slices: Vec<&mut [u8]> = out.iter_mut().map(|v| v.as_mut_slice()).collect();
+        assert_eq!(reader.read(&mut slices, source[0].len()).unwrap(), DSF_BLOCK_SIZE);
+        assert_eq!(out[1][..DSF_BLOCK_SIZE], source[1][..DSF_BLOCK_SIZE]);
+        drop(sink);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn dsf_capture_is_bit_perfect() {
+        roundtrip("ndsd_capture_test.dsf", true);
+    }
+
+    #[test]
+    fn dff_capture_is_bit_perfect() {
+        roundtrip("ndsd_capture_test.dff", true);
+    }
+}
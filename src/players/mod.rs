@@ -1,5 +1,5 @@
 use std::ffi::CString;
-use std::io;
+use std::time::Duration;
 use async_trait::async_trait;
 use ndsd_read::{DSDFormat, DSDMeta};
 
@@ -7,38 +7,250 @@ use ndsd_read::{DSDFormat, DSDMeta};
 pub mod asio;
 #[cfg(target_os = "linux")]
 pub mod alsa;
+pub mod capabilities;
+pub mod config;
+pub mod device;
+pub mod engine;
+pub mod error;
+pub mod event;
+pub mod file;
+pub mod net;
+pub mod null;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub mod pipewire;
+pub mod sync;
+pub mod wakeup;
+pub mod xrun;
 
+pub use capabilities::{DeviceCapabilities, DsdWordFormat, FormatCapability, dsd_rate_label};
+pub use config::{ClosePolicy, LatencyProfile, OutputParams, PlayerConfig, ReconnectPolicy};
+pub use device::DeviceInfo;
+pub use engine::PlaybackEngine;
+pub use error::PlayerError;
+pub use event::PlayerEvent;
+pub use sync::SyncPlayer;
+pub use wakeup::Wakeup;
+pub use xrun::{Xrun, XrunKind, XrunStats};
 
+///Creates player for the id or stable_id of a device returned by enumerate_supported_devices.
+/// Pseudo ids are accepted as well: "file:/path/capture.dsf" renders into a DSF/DFF file,
+/// "null" or "null:<speed>" plays into a device-less sink with virtual clock.
+/// On linux "dop:<alsa device>" forces DoP output, otherwise DoP is used only for hw devices without native dsd formats.
+/// With pipewire feature "pipewire" or "pipewire:<node>" streams dsd through the PipeWire daemon.
+/// "tcp:<host>:<port>" streams to a remote ndsd-receiver.
+/// Fails only if the config does not validate, the device itself is opened with the first track
+pub fn create_player(device_id: CString, config: PlayerConfig) -> Result<Box<dyn DSDPlayer>, PlayerError> {
+    config.validate()?;
+    if let Some(sink) = create_virtual_sink(&device_id, &config) {
+        return Ok(Box::new(PlaybackEngine::with_config(sink, &config)));
+    }
+    create_device_player(device_id, config)
+}
+
+///Creates only the output part for the device id, accepts the same ids as create_player.
+/// Returns None for devices which are not driven by PlaybackEngine (asio)
+pub fn create_sink(device_id: &CString, config: &PlayerConfig) -> Option<Box<dyn AudioSink>> {
+    if let Some(sink) = create_virtual_sink(device_id, config) {
+        return Some(sink);
+    }
+    create_device_sink(device_id, config)
+}
+
+fn create_virtual_sink(device_id: &CString, config: &PlayerConfig) -> Option<Box<dyn AudioSink>> {
+    let id = device_id.to_str().ok()?;
+    if let Some(path) = id.strip_prefix(file::FILE_DEVICE_PREFIX) {
+        return Some(Box::new(file::FileSink::new(path)));
+    }
+    if let Some(sink) = null::NullSink::from_device_id(id, config) {
+        return Some(Box::new(sink));
+    }
+    if let Some(address) = id.strip_prefix(net::NET_DEVICE_PREFIX) {
+        return Some(Box::new(net::NetSink::new(address)));
+    }
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    if let Some(sink) = pipewire::PipeWireSink::from_device_id(id) {
+        return Some(Box::new(sink));
+    }
+    None
+}
+
+///Formats, rates, channels and buffer limits the device supports, probed without starting playback.
+/// Virtual outputs (file, null, network, PipeWire) accept every track
+pub fn device_capabilities(device_id: &CString) -> Result<DeviceCapabilities, PlayerError> {
+    if create_virtual_sink(device_id, &PlayerConfig::default()).is_some() {
+        return Ok(DeviceCapabilities::unrestricted());
+    }
+    device_hw_capabilities(device_id)
+}
+
+#[cfg(target_os = "linux")]
+pub fn enumerate_supported_devices() -> Vec<DeviceInfo> {
+    alsa::AlsaSink::enumerate_supported_devices()
+}
 #[cfg(target_os = "linux")]
-pub fn enumerate_supported_devices() -> Vec<(CString, CString)> {
-    alsa::AlsaPlayer::enumerate_supported_devices()
+fn create_device_player(device_id: CString, config: PlayerConfig) -> Result<Box<dyn DSDPlayer>, PlayerError> {
+    let sink = Box::new(alsa::AlsaSink::from_device_id(device_id, &config));
+    Ok(Box::new(PlaybackEngine::with_config(sink, &config)))
 }
 #[cfg(target_os = "linux")]
-pub fn create_player(device_id: CString) -> Option<Box<dyn DSDPlayer>>{
-    Some(Box::new(alsa::AlsaPlayer::new(device_id.to_str().unwrap())))
+fn create_device_sink(device_id: &CString, config: &PlayerConfig) -> Option<Box<dyn AudioSink>> {
+    Some(Box::new(alsa::AlsaSink::from_device_id(device_id.clone(), config)))
+}
+#[cfg(target_os = "linux")]
+fn device_hw_capabilities(device_id: &CString) -> Result<DeviceCapabilities, PlayerError> {
+    alsa::AlsaSink::capabilities(device_id)
 }
 
 #[cfg(target_os = "windows")]
 
-pub fn enumerate_supported_devices() -> Vec<(CString, CString)> {
+pub fn enumerate_supported_devices() -> Vec<DeviceInfo> {
     asio::AsioDsdPlayer::enumerate_supported_devices()
 }
 
 #[cfg(target_os = "windows")]
-pub fn create_player(device_id: CString) -> Option<Box<dyn DSDPlayer>>{
-    Some(Box::new(asio::AsioDsdPlayer::new(device_id)))
+fn create_device_player(device_id: CString, config: PlayerConfig) -> Result<Box<dyn DSDPlayer>, PlayerError> {
+    Ok(Box::new(asio::AsioDsdPlayer::with_config(device_id, &config)))
+}
+#[cfg(target_os = "windows")]
+fn create_device_sink(_device_id: &CString, _config: &PlayerConfig) -> Option<Box<dyn AudioSink>> {
+    None
+}
+#[cfg(target_os = "windows")]
+fn device_hw_capabilities(_device_id: &CString) -> Result<DeviceCapabilities, PlayerError> {
+    // Only one asio driver can be loaded at a time, probing would stop the running player
+    Err(PlayerError::DeviceSetup {
+        errno: 0,
+        message: "capability probing is not available for asio drivers".to_string(),
+    })
 }
 
+///Commands return once the player thread has executed them, device and track errors are reported right away.
+/// Errors which happen later, while the player writes in background, stop the playback and are kept for take_error.
+/// The futures only wait for the player thread and run on any executor, SyncPlayer blocks on them without one
 #[async_trait]
 pub trait DSDPlayer: Send + Sync{
-    async fn start(&mut self);
-    async fn pause(&self);
-    async fn play(&self);
+    async fn start(&mut self) -> Result<(), PlayerError>;
+    async fn pause(&self) -> Result<(), PlayerError>;
+    async fn play(&self) -> Result<(), PlayerError>;
+    ///Position of what is heard right now in percent of the track
     async fn get_pos(&self) -> f64;
-    async fn stop(&self);
+    ///Position of what is heard right now, audio still queued in the output is not counted
+    async fn position(&self) -> PlaybackTime;
+    ///Length of the current track
+    async fn duration(&self) -> PlaybackTime;
+    async fn stop(&self) -> Result<(), PlayerError>;
     async fn is_playing(&self) -> bool;
-    async fn load_new_track(&mut self, filename: &str);
-    async fn seek(&mut self, percent: f64) -> Result<(), io::Error>;
+    async fn load_new_track(&mut self, filename: &str) -> Result<(), PlayerError>;
+    async fn seek(&mut self, percent: f64) -> Result<(), PlayerError>;
+    ///Seeks to the time from the start of the track, also while stopped.
+    /// Lands on the DSF block or DST frame boundary at or before it and returns where it landed
+    async fn seek_to(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError>;
+    ///Seeks relative to what is heard right now, stops at the start and the end of the track
+    async fn seek_by(&mut self, offset: Duration, direction: SeekDirection) -> Result<PlaybackTime, PlayerError>;
+    ///Seeks like seek_to and starts the playback from there
+    async fn start_at(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError>;
+    ///Opens the track which plays right after the current one ends.
+    /// When both tracks have the same format there is no gap, otherwise the output is set up again in between.
+    /// Replaces the track enqueued before
+    async fn enqueue_next(&mut self, filename: &str) -> Result<(), PlayerError>;
     async fn get_format_info(&self) -> DSDFormat;
     async fn get_current_file_meta(&self) -> Option<DSDMeta>;
+    ///Error which stopped the playback in background, cleared once taken
+    async fn take_error(&self) -> Option<PlayerError>;
+    ///New receiver of player events, it gets everything sent after the call
+    #[cfg(feature = "tokio")]
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PlayerEvent>;
+    ///Same as subscribe for callers without an async runtime
+    fn subscribe_blocking(&self) -> std::sync::mpsc::Receiver<PlayerEvent>;
+    ///How much of the track is read ahead of the output
+    async fn buffer_level(&self) -> BufferLevel;
+    ///Buffer values the device granted for the PlayerConfig, None until the output is opened
+    async fn output_params(&self) -> Option<OutputParams>;
+    ///Underruns and suspends the output recovered from
+    async fn xrun_stats(&self) -> XrunStats;
+    ///True while the device is gone and the player tries to reopen it
+    async fn is_device_lost(&self) -> bool;
+    ///Stops the playback as the ClosePolicy says, releases the device and ends the player thread.
+    /// Dropping the player does the same, commands sent after it fail with Terminated
+    async fn close(&mut self) -> Result<(), PlayerError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekDirection {
+    Forward,
+    Backward,
+}
+
+///Point in the track as time and as dsd frames, one frame is a single bit per channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PlaybackTime {
+    pub time: Duration,
+    pub frames: u64,
+}
+
+impl PlaybackTime {
+    pub fn from_frames(frames: u64, sampling_rate: u32) -> Self {
+        let time = if sampling_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos((frames as u128 * 1_000_000_000 / sampling_rate as u128) as u64)
+        };
+        Self { time, frames }
+    }
+
+    ///Position in bytes per channel as the readers count it
+    pub fn from_bytes(bytes: u64, sampling_rate: u32) -> Self {
+        Self::from_frames(bytes * 8, sampling_rate)
+    }
+}
+
+///Fill level of the read-ahead buffer: audio read from the track but not passed to the output yet,
+/// and the most it keeps for the current track
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferLevel {
+    pub filled: Duration,
+    pub depth: Duration,
+}
+
+///Output backend driven by the PlaybackEngine.
+/// Sinks receive planar dsd exactly as it was read from file, one slice per channel.
+/// Bit order conversion and interleaving is up to the sink
+pub trait AudioSink: Send {
+    ///Opens the output for the format, reopens it if it was opened before
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError>;
+    ///Amount of bytes per channel the engine should pass into a single write
+    fn period_bytes(&self) -> usize;
+    ///Writes planar dsd data, blocks until the output accepted it
+    fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError>;
+    ///Pauses or resumes the output
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError>;
+    ///Blocks until everything written was played
+    fn drain(&mut self) -> Result<(), PlayerError>;
+    ///Drops everything written but not played yet, used on seek and stop
+    fn flush(&mut self) -> Result<(), PlayerError> {
+        Ok(())
+    }
+    ///Bytes per channel written to the output but not played yet
+    fn delay(&self) -> usize;
+    ///Buffer values the output granted when it was opened, None for outputs without a device buffer
+    fn output_params(&self) -> Option<OutputParams> {
+        None
+    }
+    ///Waits until a period can be written without blocking, returns false if the wakeup fired or the timeout expired.
+    /// Outputs which can not tell return true right away and block in write instead
+    fn wait_writable(&mut self, _wakeup: &Wakeup, _timeout: Duration) -> Result<bool, PlayerError> {
+        Ok(true)
+    }
+    ///Xrun the last write recovered from, writes keep going after it
+    fn take_xrun(&mut self) -> Option<XrunKind> {
+        None
+    }
+    ///True for outputs ending in a DAC, the engine plays the idle pattern around starts, seeks and pauses for them
+    fn needs_silence(&self) -> bool {
+        false
+    }
+    ///Completes the output before the player releases it, the error is returned from close
+    fn finish(&mut self) -> Result<(), PlayerError> {
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,643 @@
+use crate::players::{AudioSink, PlayerError};
+use ndsd_read::DSDFormat;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+///Pseudo device id prefix, "tcp:192.168.1.20:7171" streams to an ndsd-receiver instead of opening a device
+pub const NET_DEVICE_PREFIX: &str = "tcp:";
+///Port ndsd-receiver listens on by default
+pub const DEFAULT_PORT: u16 = 7171;
+
+const MAGIC: &[u8; 4] = b"NDSD";
+const PROTOCOL_VERSION: u8 = 1;
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+const PERIOD_SECONDS: f64 = 0.02;
+// Amount of data the sender may run ahead of the receiver's DAC
+const WINDOW_SECONDS: f64 = 1.0;
+// Longest wait for the receiver to answer or make room, a receiver stalled for longer counts as lost
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const FRAME_FORMAT: u8 = 1;
+const FRAME_DATA: u8 = 2;
+const FRAME_PAUSE: u8 = 3;
+const FRAME_FLUSH: u8 = 4;
+const FRAME_DRAIN: u8 = 5;
+const FRAME_DRAINED: u8 = 6;
+const FRAME_STATUS: u8 = 7;
+
+///Single message of the streaming protocol.
+/// Every frame is [type u8][payload length u32 BE][payload], all integers are big endian.
+/// Connection starts with "NDSD" and the protocol version sent by the player
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    ///Stream format changed, receiver reopens its sink
+    Format(DSDFormat),
+    ///Planar dsd, one buffer per channel, bit order as in the source file
+    Data(Vec<Vec<u8>>),
+    Pause(bool),
+    ///Drop everything queued but not played yet
+    Flush,
+    ///Play out everything queued, receiver answers with Drained
+    Drain,
+    Drained,
+    ///Receiver to player: bytes per channel taken from the stream so far and bytes per channel queued in the device
+    Status { consumed: u64, device_delay: u64 },
+}
+
+impl Frame {
+    pub fn write_to(&self, out: &mut impl Write) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        match self {
+            Frame::Format(format) => {
+                let mut payload = Vec::with_capacity(17);
+                payload.extend_from_slice(&format.sampling_rate.to_be_bytes());
+                payload.extend_from_slice(&format.num_channels.to_be_bytes());
+                payload.extend_from_slice(&format.total_samples.to_be_bytes());
+                payload.push(format.is_lsb_first as u8);
+                put_frame(&mut buf, FRAME_FORMAT, &payload);
+            }
+            Frame::Data(channels) => {
+                let slices: Vec<&[u8]> = channels.iter().map(|c| c.as_slice()).collect();
+                let bytes = channels.first().map_or(0, |c| c.len());
+                put_data_frame(&mut buf, &slices, bytes);
+            }
+            Frame::Pause(paused) => put_frame(&mut buf, FRAME_PAUSE, &[*paused as u8]),
+            Frame::Flush => put_frame(&mut buf, FRAME_FLUSH, &[]),
+            Frame::Drain => put_frame(&mut buf, FRAME_DRAIN, &[]),
+            Frame::Drained => put_frame(&mut buf, FRAME_DRAINED, &[]),
+            Frame::Status { consumed, device_delay } => {
+                let mut payload = Vec::with_capacity(16);
+                payload.extend_from_slice(&consumed.to_be_bytes());
+                payload.extend_from_slice(&device_delay.to_be_bytes());
+                put_frame(&mut buf, FRAME_STATUS, &payload);
+            }
+        }
+        out.write_all(&buf)
+    }
+
+    pub fn read_from(input: &mut impl Read) -> Result<Self, Error> {
+        let mut header = [0u8; 5];
+        input.read_exact(&mut header)?;
+        let len = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "Frame is too large"));
+        }
+        let mut payload = vec![0u8; len];
+        input.read_exact(&mut payload)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "Malformed frame");
+        let u32_at = |at: usize| -> Result<u32, Error> {
+            Ok(u32::from_be_bytes(payload.get(at..at + 4).ok_or_else(invalid)?.try_into().unwrap()))
+        };
+        let u64_at = |at: usize| -> Result<u64, Error> {
+            Ok(u64::from_be_bytes(payload.get(at..at + 8).ok_or_else(invalid)?.try_into().unwrap()))
+        };
+        match header[0] {
+            FRAME_FORMAT => Ok(Frame::Format(DSDFormat {
+                sampling_rate: u32_at(0)?,
+                num_channels: u32_at(4)?,
+                total_samples: u64_at(8)?,
+                is_lsb_first: *payload.get(16).ok_or_else(invalid)? != 0,
+            })),
+            FRAME_DATA => {
+                let channels = u32_at(0)? as usize;
+                let bytes = u32_at(4)? as usize;
+                if channels.checked_mul(bytes) != payload.len().checked_sub(8) {
+                    return Err(invalid());
+                }
+                Ok(Frame::Data(payload[8..].chunks(bytes.max(1)).map(|c| c.to_vec()).collect()))
+            }
+            FRAME_PAUSE => Ok(Frame::Pause(*payload.first().ok_or_else(invalid)? != 0)),
+            FRAME_FLUSH => Ok(Frame::Flush),
+            FRAME_DRAIN => Ok(Frame::Drain),
+            FRAME_DRAINED => Ok(Frame::Drained),
+            FRAME_STATUS => Ok(Frame::Status {
+                consumed: u64_at(0)?,
+                device_delay: u64_at(8)?,
+            }),
+            _ => Err(Error::new(ErrorKind::InvalidData, "Unknown frame type")),
+        }
+    }
+}
+
+fn put_frame(buf: &mut Vec<u8>, frame_type: u8, payload: &[u8]) {
+    buf.push(frame_type);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+fn put_data_frame(buf: &mut Vec<u8>, data: &[&[u8]], bytes_per_channel: usize) {
+    buf.push(FRAME_DATA);
+    buf.extend_from_slice(&((8 + data.len() * bytes_per_channel) as u32).to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&(bytes_per_channel as u32).to_be_bytes());
+    for channel in data {
+        buf.extend_from_slice(&channel[..bytes_per_channel]);
+    }
+}
+
+#[derive(Default)]
+struct RemoteState {
+    consumed: u64,
+    device_delay: u64,
+    drained: u64,
+    error: Option<String>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    state: Arc<(Mutex<RemoteState>, Condvar)>,
+    reader: JoinHandle<()>,
+}
+
+///Streams dsd to an ndsd-receiver over TCP.
+/// The receiver reports how much it has played, the sink never runs further than WINDOW_SECONDS ahead of it.
+/// So the receiver's DAC is the only clock in the chain and there is no drift between player and DAC
+pub struct NetSink {
+    address: String,
+    connection: Option<Connection>,
+    bytes_per_second: f64,
+    period_bytes: usize,
+    sent: u64,
+    frame: Vec<u8>,
+    ack_timeout: Duration,
+}
+
+impl NetSink {
+    ///Address is "host:port" or just "host" for the default port
+    pub fn new(address: &str) -> Self {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+        Self {
+            address,
+            connection: None,
+            bytes_per_second: 0.0,
+            period_bytes: 0,
+            sent: 0,
+            frame: Vec::new(),
+            ack_timeout: ACK_TIMEOUT,
+        }
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        let mut stream = TcpStream::connect(&self.address)?;
+        stream.set_nodelay(true)?;
+        stream.write_all(MAGIC)?;
+        stream.write_all(&[PROTOCOL_VERSION])?;
+
+        let state = Arc::new((Mutex::new(RemoteState::default()), Condvar::new()));
+        let mut input = stream.try_clone()?;
+        let reader_state = state.clone();
+        let reader = std::thread::spawn(move || {
+            let (lock, cvar) = &*reader_state;
+            loop {
+                let frame = Frame::read_from(&mut input);
+                let mut remote = lock.lock().unwrap();
+                match frame {
+                    Ok(Frame::Status { consumed, device_delay }) => {
+                        remote.consumed = consumed;
+                        remote.device_delay = device_delay;
+                    }
+                    Ok(Frame::Drained) => remote.drained += 1,
+                    Ok(_) => {}
+                    Err(e) => {
+                        remote.error = Some(format!("Receiver connection lost: {}", e));
+                        cvar.notify_all();
+                        return;
+                    }
+                }
+                cvar.notify_all();
+            }
+        });
+        self.sent = 0;
+        self.connection = Some(Connection { stream, state, reader });
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+            let _ = connection.reader.join();
+        }
+    }
+
+    fn send(&mut self, frame: &Frame) -> Result<(), Error> {
+        let connection = self.connection.as_mut().ok_or_else(not_connected)?;
+        let res = frame.write_to(&mut connection.stream);
+        if res.is_err() {
+            self.disconnect();
+        }
+        res
+    }
+
+    ///Waits until condition holds for the receiver state.
+    /// Disconnects if the connection is lost or the receiver keeps it open without answering for ack_timeout
+    fn wait_remote(&mut self, condition: impl Fn(&RemoteState) -> bool) -> Result<(), Error> {
+        let connection = self.connection.as_ref().ok_or_else(not_connected)?;
+        let (lock, cvar) = &*connection.state;
+        let started = Instant::now();
+        let mut remote = lock.lock().unwrap();
+        let e = loop {
+            if let Some(e) = remote.error.as_ref() {
+                break Error::new(ErrorKind::BrokenPipe, e.clone());
+            }
+            if condition(&remote) {
+                return Ok(());
+            }
+            match self.ack_timeout.checked_sub(started.elapsed()) {
+                Some(left) => remote = cvar.wait_timeout(remote, left).unwrap().0,
+                None => break Error::new(ErrorKind::TimedOut, "Receiver did not respond"),
+            }
+        };
+        drop(remote);
+        self.disconnect();
+        Err(e)
+    }
+
+    ///Connection failures while streaming, the player reconnects like to an unplugged device
+    fn lost(&self, e: Error) -> PlayerError {
+        PlayerError::DeviceLost {
+            device: format!("{}{}", NET_DEVICE_PREFIX, self.address),
+            errno: e.raw_os_error().unwrap_or(0),
+            message: e.to_string(),
+        }
+    }
+}
+
+fn not_connected() -> Error {
+    Error::new(ErrorKind::NotConnected, "Receiver is not connected")
+}
+
+impl AudioSink for NetSink {
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+        if self.connection.is_none() {
+            self.connect()?;
+        }
+        self.bytes_per_second = format.sampling_rate as f64 / 8.0;
+        // Multiple of 8 keeps every native and DoP word size of the receiver aligned
+        self.period_bytes = ((self.bytes_per_second * PERIOD_SECONDS) as usize).div_ceil(8) * 8;
+        Ok(self.send(&Frame::Format(*format))?)
+    }
+
+    fn period_bytes(&self) -> usize {
+        self.period_bytes
+    }
+
+    fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+        let window = (self.bytes_per_second * WINDOW_SECONDS) as u64;
+        let sent = self.sent;
+        self.wait_remote(|remote| sent.saturating_sub(remote.consumed) <= window)
+            .map_err(|e| self.lost(e))?;
+
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.clear();
+        put_data_frame(&mut frame, data, bytes_per_channel);
+        let connection = self.connection.as_mut().ok_or_else(not_connected)?;
+        let res = connection.stream.write_all(&frame);
+        self.frame = frame;
+        if let Err(e) = res {
+            self.disconnect();
+            return Err(self.lost(e));
+        }
+        self.sent += bytes_per_channel as u64;
+        Ok(())
+    }
+
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+        self.send(&Frame::Pause(paused)).map_err(|e| self.lost(e))
+    }
+
+    fn drain(&mut self) -> Result<(), PlayerError> {
+        let Some(connection) = self.connection.as_ref() else {
+            return Err(self.lost(not_connected()));
+        };
+        let drained = connection.state.0.lock().unwrap().drained;
+        self.send(&Frame::Drain).map_err(|e| self.lost(e))?;
+        self.wait_remote(|remote| remote.drained > drained).map_err(|e| self.lost(e))
+    }
+
+    fn flush(&mut self) -> Result<(), PlayerError> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        self.send(&Frame::Flush).map_err(|e| self.lost(e))?;
+        // Receiver counts dropped data as consumed, so delay() is right once it answered
+        let sent = self.sent;
+        self.wait_remote(|remote| remote.consumed >= sent).map_err(|e| self.lost(e))
+    }
+
+    fn delay(&self) -> usize {
+        match self.connection.as_ref() {
+            Some(connection) => {
+                let remote = connection.state.0.lock().unwrap();
+                (self.sent.saturating_sub(remote.consumed) + remote.device_delay) as usize
+            }
+            None => 0,
+        }
+    }
+}
+
+impl Drop for NetSink {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+#[derive(Default)]
+struct Inbox {
+    frames: VecDeque<Frame>,
+    // Bytes per channel of Data frames in the queue
+    queued: u64,
+    // Bytes per channel dropped by Flush before they reached the sink
+    dropped: u64,
+    closed: Option<Error>,
+}
+
+///Receiving side of NetSink, plays a single player connection into the sink until the player disconnects.
+/// After every format change, flush or underrun playback waits until prebuffer worth of data has arrived
+pub fn serve_connection(
+    stream: TcpStream,
+    sink: &mut dyn AudioSink,
+    prebuffer: Duration,
+) -> Result<(), Error> {
+    let mut input = stream.try_clone()?;
+    let mut output = stream;
+    output.set_nodelay(true)?;
+    let mut hello = [0u8; 5];
+    input.read_exact(&mut hello)?;
+    if &hello[..4] != MAGIC || hello[4] != PROTOCOL_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Not an ndsd stream or unsupported protocol version"));
+    }
+
+    let inbox = Arc::new((Mutex::new(Inbox::default()), Condvar::new()));
+    let reader_inbox = inbox.clone();
+    let reader = std::thread::spawn(move || {
+        let (lock, cvar) = &*reader_inbox;
+        loop {
+            let frame = Frame::read_from(&mut input);
+            let mut inbox = lock.lock().unwrap();
+            match frame {
+                Ok(Frame::Data(data)) => {
+                    inbox.queued += data.first().map_or(0, |c| c.len()) as u64;
+                    inbox.frames.push_back(Frame::Data(data));
+                }
+                Ok(Frame::Flush) => {
+                    // Handled here, so the flush does not wait behind the data it is meant to drop
+                    let mut dropped = 0;
+                    inbox.frames.retain(|frame| match frame {
+                        Frame::Data(data) => {
+                            dropped += data.first().map_or(0, |c| c.len()) as u64;
+                            false
+                        }
+                        _ => true,
+                    });
+                    inbox.queued -= dropped;
+                    inbox.dropped += dropped;
+                    inbox.frames.push_back(Frame::Flush);
+                }
+                Ok(frame) => inbox.frames.push_back(frame),
+                Err(e) => {
+                    inbox.closed = Some(e);
+                    cvar.notify_all();
+                    return;
+                }
+            }
+            cvar.notify_all();
+        }
+    });
+
+    let res = run_receiver(&inbox, &mut output, sink, prebuffer);
+    let _ = output.shutdown(Shutdown::Both);
+    let _ = reader.join();
+    res
+}
+
+fn run_receiver(
+    inbox: &(Mutex<Inbox>, Condvar),
+    output: &mut TcpStream,
+    sink: &mut dyn AudioSink,
+    prebuffer: Duration,
+) -> Result<(), Error> {
+    let (lock, cvar) = inbox;
+    let mut written = 0u64;
+    let mut prebuffer_bytes = 0u64;
+    let mut buffering = true;
+    let mut paused = false;
+    let mut sink_ready = false;
+    loop {
+        let frame = {
+            let mut inbox = lock.lock().unwrap();
+            if buffering && prebuffer_bytes > 0 {
+                let started = Instant::now();
+                // Control frames end the prebuffering, e.g. a drain of a track shorter than the prebuffer
+                while inbox.queued < prebuffer_bytes
+                    && inbox.closed.is_none()
+                    && inbox.frames.iter().all(|f| matches!(f, Frame::Data(_)))
+                    && started.elapsed() < prebuffer * 4
+                {
+                    inbox = cvar.wait_timeout(inbox, prebuffer).unwrap().0;
+                }
+            }
+            buffering = false;
+            loop {
+                if let Some(frame) = inbox.frames.pop_front() {
+                    if let Frame::Data(data) = &frame {
+                        inbox.queued -= data.first().map_or(0, |c| c.len()) as u64;
+                    }
+                    break frame;
+                }
+                if let Some(e) = inbox.closed.take() {
+                    return match e.kind() {
+                        ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset => Ok(()),
+                        _ => Err(e),
+                    };
+                }
+                if sink_ready && !paused {
+                    buffering = true;
+                }
+                inbox = cvar.wait(inbox).unwrap();
+            }
+        };
+        match frame {
+            Frame::Format(format) => {
+                sink.open(&format)?;
+                sink_ready = true;
+                paused = false;
+                buffering = true;
+                prebuffer_bytes = (format.sampling_rate as f64 / 8.0 * prebuffer.as_secs_f64()) as u64;
+            }
+            Frame::Data(data) => {
+                if !sink_ready {
+                    return Err(Error::new(ErrorKind::InvalidData, "Data before format"));
+                }
+                let bytes = data.first().map_or(0, |c| c.len());
+                let period = sink.period_bytes().max(1);
+                let mut offset = 0;
+                while offset < bytes {
+                    let chunk = period.min(bytes - offset);
+                    let slices: Vec<&[u8]> = data.iter().map(|c| &c[offset..offset + chunk]).collect();
+                    sink.write(&slices, chunk)?;
+                    offset += chunk;
+                }
+                written += bytes as u64;
+                send_status(lock, output, written, sink)?;
+            }
+            Frame::Pause(pause) => {
+                if sink_ready {
+                    sink.pause(pause)?;
+                }
+                paused = pause;
+            }
+            Frame::Flush => {
+                if sink_ready {
+                    sink.flush()?;
+                }
+                buffering = true;
+                send_status(lock, output, written, sink)?;
+            }
+            Frame::Drain => {
+                if sink_ready {
+                    sink.drain()?;
+                }
+                Frame::Drained.write_to(output)?;
+                send_status(lock, output, written, sink)?;
+            }
+            Frame::Drained | Frame::Status { .. } => {}
+        }
+    }
+}
+
+fn send_status(
+    inbox: &Mutex<Inbox>,
+    output: &mut TcpStream,
+    written: u64,
+    sink: &dyn AudioSink,
+) -> Result<(), Error> {
+    let dropped = inbox.lock().unwrap().dropped;
+    Frame::Status {
+        consumed: written + dropped,
+        device_delay: sink.delay() as u64,
+    }
+    .write_to(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn frames_roundtrip() {
+        let frames = vec![
+            Frame::Format(DSDFormat {
+                sampling_rate: 5644800,
+                num_channels: 2,
+                total_samples: 1234567,
+                is_lsb_first: true,
+            }),
+            Frame::Data(vec![vec![1, 2, 3], vec![4, 5, 6]]),
+            Frame::Pause(true),
+            Frame::Flush,
+            Frame::Drain,
+            Frame::Drained,
+            Frame::Status { consumed: 42, device_delay: 7 },
+        ];
+        let mut buf = Vec::new();
+        for frame in &frames {
+            frame.write_to(&mut buf).unwrap();
+        }
+        let mut input = buf.as_slice();
+        for frame in &frames {
+            assert_eq!(&Frame::read_from(&mut input).unwrap(), frame);
+        }
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn stalled_receiver_is_lost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        // Takes everything and never answers, the connection stays open until the sink gives up
+        let receiver = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = std::io::copy(&mut stream, &mut std::io::sink());
+        });
+
+        let mut sink = NetSink::new(&address);
+        sink.ack_timeout = Duration::from_millis(100);
+        sink.open(&DSDFormat {
+            sampling_rate: 2822400,
+            num_channels: 2,
+            total_samples: 0,
+            is_lsb_first: true,
+        })
+        .unwrap();
+        let period = vec![0x69; sink.period_bytes()];
+        let started = Instant::now();
+        let e = loop {
+            if let Err(e) = sink.write(&[&period, &period], period.len()) {
+                break e;
+            }
+        };
+        assert!(matches!(e, PlayerError::DeviceLost { .. }), "{:?}", e);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(matches!(sink.drain(), Err(PlayerError::DeviceLost { .. })));
+        receiver.join().unwrap();
+    }
+
+    // Only the player end needs the runtime, the protocol is covered without it
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn loopback_stream_is_bit_perfect() {
+        use crate::players::{create_player, PlayerConfig};
+        use crate::players::file::FileSink;
+
+        let track = crate::test_util::write_test_track("ndsd_net_source.dsf", 2822400, 1.0);
+        let capture = std::env::temp_dir().join("ndsd_net_capture.dsf");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver_capture = capture.clone();
+        let receiver = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut sink = FileSink::new(receiver_capture);
+            serve_connection(stream, &mut sink, Duration::from_millis(100)).unwrap();
+        });
+
+        let device = std::ffi::CString::new(format!("tcp:127.0.0.1:{}", port)).unwrap();
+        let mut player = create_player(device, PlayerConfig::default()).unwrap();
+        player.load_new_track(&track).await.unwrap();
+        player.start().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut waited = 0;
+        while player.is_playing().await {
+            assert!(waited < 5000, "track did not finish");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            waited += 20;
+        }
+        drop(player);
+        receiver.join().unwrap();
+
+        let read_all = |path: &str| {
+            let mut format = DSDFormat::default();
+            let mut reader = ndsd_read::open_dsd_auto(path, &mut format).unwrap();
+            let bytes = 2822400 / 8;
+            let mut out = vec![vec![0u8; bytes]; 2];
+            let mut slices: Vec<&mut [u8]> = out.iter_mut().map(|v| v.as_mut_slice()).collect();
+            let read = reader.read(&mut slices, bytes).unwrap();
+            (format, read, out)
+        };
+        let source = read_all(&track);
+        let captured = read_all(capture.to_str().unwrap());
+        assert_eq!(source.0.sampling_rate, captured.0.sampling_rate);
+        assert_eq!(source.1, captured.1);
+        assert_eq!(source.2, captured.2);
+        let _ = std::fs::remove_file(track);
+        let _ = std::fs::remove_file(capture);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::players::{AudioSink, OutputParams, PlayerConfig, PlayerError, Wakeup};
+use ndsd_read::DSDFormat;
+use std::time::{Duration, Instant};
+
+///Pseudo device id of NullSink. "null" plays in real time, "null:8" runs the clock 8 times faster
+pub const NULL_DEVICE_ID: &str = "null";
+
+///Device-less output, consumes dsd at the rate of the stream format like a real DAC would.
+/// Keeps a small virtual buffer, so writes block, pause holds the clock and drain waits for the buffer to play out.
+/// Clock starts once the start threshold is queued or on drain, again after every underrun or flush
+pub struct NullSink {
+    speed: f64,
+    config: PlayerConfig,
+    bytes_per_second: f64,
+    period_bytes: usize,
+    buffer_bytes: u64,
+    start_threshold_bytes: u64,
+    written: u64,
+    // Clock is restarted on every start or resume, played bytes are counted from that point
+    clock_start: Instant,
+    played_at_start: u64,
+    started: bool,
+    paused: bool,
+}
+
+impl NullSink {
+    pub fn new(speed: f64) -> Self {
+        Self::with_config(speed, PlayerConfig::default())
+    }
+
+    ///Virtual buffer takes the buffer and period times of the config
+    pub fn with_config(speed: f64, config: PlayerConfig) -> Self {
+        Self {
+            speed: if speed > 0.0 { speed } else { 1.0 },
+            config,
+            bytes_per_second: 0.0,
+            period_bytes: 0,
+            buffer_bytes: 0,
+            start_threshold_bytes: 0,
+            written: 0,
+            clock_start: Instant::now(),
+            played_at_start: 0,
+            started: false,
+            paused: false,
+        }
+    }
+
+    ///Parses "null" or "null:<speed>"
+    pub fn from_device_id(device_id: &str, config: &PlayerConfig) -> Option<Self> {
+        let rest = device_id.strip_prefix(NULL_DEVICE_ID)?;
+        if rest.is_empty() {
+            return Some(Self::with_config(1.0, config.clone()));
+        }
+        let speed = rest.strip_prefix(':')?.parse::<f64>().ok()?;
+        Some(Self::with_config(speed, config.clone()))
+    }
+
+    fn played(&self) -> u64 {
+        if self.paused || !self.started {
+            return self.played_at_start;
+        }
+        let elapsed = self.clock_start.elapsed().as_secs_f64();
+        let played = self.played_at_start + (elapsed * self.bytes_per_second * self.speed) as u64;
+        played.min(self.written)
+    }
+
+    // Queued bytes which still leave room for a period
+    fn writable_limit(&self) -> u64 {
+        self.buffer_bytes - self.period_bytes as u64
+    }
+
+    fn restart_clock(&mut self) {
+        self.played_at_start = self.played();
+        self.clock_start = Instant::now();
+    }
+
+    fn start(&mut self) {
+        self.restart_clock();
+        self.started = true;
+    }
+
+    fn wait_until_queued(&self, max_queued: u64) {
+        while let Some(wait) = self.time_until_queued(max_queued) {
+            std::thread::sleep(wait.max(Duration::from_micros(200)));
+        }
+    }
+
+    ///None once no more than max_queued bytes are left to play
+    fn time_until_queued(&self, max_queued: u64) -> Option<Duration> {
+        let queued = self.written - self.played();
+        if queued <= max_queued || self.paused || !self.started || self.bytes_per_second == 0.0 {
+            return None;
+        }
+        let seconds = (queued - max_queued) as f64 / (self.bytes_per_second * self.speed);
+        Some(Duration::from_secs_f64(seconds))
+    }
+}
+
+impl AudioSink for NullSink {
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+        self.bytes_per_second = format.sampling_rate as f64 / 8.0;
+        let bytes = |time: Duration| ((self.bytes_per_second * time.as_secs_f64()) as u64).max(1);
+        self.period_bytes = bytes(self.config.get_period_time()) as usize;
+        self.buffer_bytes = bytes(self.config.get_buffer_time()).max(self.period_bytes as u64 * 2);
+        self.start_threshold_bytes = bytes(self.config.get_start_threshold()).min(self.buffer_bytes);
+        self.written = 0;
+        self.played_at_start = 0;
+        self.clock_start = Instant::now();
+        self.started = false;
+        self.paused = false;
+        Ok(())
+    }
+
+    fn period_bytes(&self) -> usize {
+        self.period_bytes
+    }
+
+    fn write(&mut self, _data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+        if self.started && self.played() >= self.written {
+            // Underrun, the device waits for the start threshold again
+            self.played_at_start = self.written;
+            self.started = false;
+        }
+        self.wait_until_queued(self.writable_limit());
+        self.written += bytes_per_channel as u64;
+        if !self.started && self.written - self.played_at_start >= self.start_threshold_bytes {
+            self.start();
+        }
+        Ok(())
+    }
+
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+        if paused != self.paused {
+            self.restart_clock();
+            self.paused = paused;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<(), PlayerError> {
+        if !self.started {
+            self.start();
+        }
+        self.wait_until_queued(0);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PlayerError> {
+        self.played_at_start = self.written;
+        self.started = false;
+        Ok(())
+    }
+
+    fn delay(&self) -> usize {
+        (self.written - self.played()) as usize
+    }
+
+    fn wait_writable(&mut self, wakeup: &Wakeup, timeout: Duration) -> Result<bool, PlayerError> {
+        let Some(wait) = self.time_until_queued(self.writable_limit()) else {
+            return Ok(true);
+        };
+        if wait > timeout {
+            wakeup.wait(timeout);
+            return Ok(false);
+        }
+        Ok(!wakeup.wait(wait))
+    }
+
+    ///Frames of the virtual device are dsd bytes per channel
+    fn output_params(&self) -> Option<OutputParams> {
+        if self.bytes_per_second == 0.0 {
+            return None;
+        }
+        Some(OutputParams::new(
+            self.bytes_per_second as u32,
+            self.buffer_bytes,
+            self.period_bytes as u64,
+            self.start_threshold_bytes,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_waits_for_the_start_threshold() {
+        let config = PlayerConfig::new()
+            .buffer_time(Duration::from_millis(200))
+            .period_time(Duration::from_millis(20))
+            .start_threshold(Duration::from_millis(100));
+        let mut sink = NullSink::with_config(1.0, config);
+        sink.open(&DSDFormat {
+            sampling_rate: 2822400,
+            num_channels: 2,
+            total_samples: 0,
+            is_lsb_first: true,
+        })
+        .unwrap();
+        let period = vec![0x69; sink.period_bytes()];
+        let threshold = sink.output_params().unwrap().start_threshold_frames;
+        assert_eq!(threshold, 5 * period.len() as u64);
+
+        sink.write(&[&period, &period], period.len()).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(sink.delay(), period.len());
+        for _ in 0..4 {
+            sink.write(&[&period, &period], period.len()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(sink.delay() < 5 * period.len());
+
+        // Drain plays out what never reached the threshold
+        sink.flush().unwrap();
+        sink.write(&[&period, &period], period.len()).unwrap();
+        sink.drain().unwrap();
+        assert_eq!(sink.delay(), 0);
+    }
+}
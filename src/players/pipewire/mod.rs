@@ -0,0 +1,524 @@
+#![cfg(all(target_os = "linux", feature = "pipewire"))]
+
+use crate::players::{AudioSink, PlayerError, Wakeup};
+use crate::utils::bit_reverse_table::BIT_REVERSE_TABLE;
+use crate::utils::silence::{DSD_SILENCE, fill_silence};
+use ::pipewire as pw;
+use crossbeam::queue::ArrayQueue;
+use ndsd_read::DSDFormat;
+use pw::spa;
+use pw::spa::pod::{Object, Pod, Property, Value, ValueArray};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+///Pseudo device id, "pipewire" plays to the default node, "pipewire:<node name or serial>" targets a node
+pub const PIPEWIRE_DEVICE_ID: &str = "pipewire";
+
+// PipeWire dsd frames carry 4 bytes of every channel, like SND_PCM_FORMAT_DSD_U32_BE
+const INTERLEAVE: usize = 4;
+const BUFFER_SECONDS: f64 = 0.25;
+// Writes are about a period each, the ring holds more of them than the buffer time needs
+const RING_CHUNKS: usize = 64;
+// Longest blocking wait before the stream error is checked again
+const ERROR_POLL: Duration = Duration::from_millis(100);
+
+enum Control {
+    Pause(bool),
+    Quit,
+}
+
+///Interleaved dsd handed to the realtime process callback without locks.
+/// Every flush starts a new generation, chunks of older generations are dropped by the callback
+struct Ring {
+    chunks: ArrayQueue<(u64, Vec<u8>)>,
+    // Consumed chunks go back to the writer, so the callback neither allocates nor frees
+    free: ArrayQueue<Vec<u8>>,
+    generation: AtomicU64,
+    // Bytes of all channels in the ring, including the part of a chunk the callback holds
+    queued: AtomicUsize,
+    capacity: usize,
+    // Eventfd signalled by the callback whenever it took data, writers poll it
+    space: libc::c_int,
+    // Set from the main loop thread, never touched by the callback
+    error: Mutex<Option<String>>,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Self {
+            chunks: ArrayQueue::new(RING_CHUNKS),
+            free: ArrayQueue::new(RING_CHUNKS + 2),
+            generation: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            capacity,
+            space: unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) },
+            error: Mutex::new(None),
+        }
+    }
+
+    fn signal(&self) {
+        let one: u64 = 1;
+        unsafe { libc::write(self.space, &one as *const u64 as *const libc::c_void, 8) };
+    }
+
+    ///Waits until the callback took data, the wakeup fired or the timeout expired
+    fn wait(&self, wakeup: Option<&Wakeup>, timeout: Duration) {
+        let mut fds = vec![libc::pollfd { fd: self.space, events: libc::POLLIN, revents: 0 }];
+        if let Some(wakeup) = wakeup.filter(|wakeup| wakeup.fd() >= 0) {
+            fds.push(libc::pollfd { fd: wakeup.fd(), events: libc::POLLIN, revents: 0 });
+        }
+        // Interrupted poll returns early, callers check again either way
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout.as_millis() as _) };
+        let mut count: u64 = 0;
+        unsafe { libc::read(self.space, &mut count as *mut u64 as *mut libc::c_void, 8) };
+    }
+
+    fn recycle(&self, chunk: Vec<u8>) {
+        let _ = self.free.push(chunk);
+    }
+
+    ///Drops everything queued, the callback drops the chunk it is in the middle of
+    fn clear(&self) {
+        self.generation.fetch_add(1, Release);
+        while let Some((_, chunk)) = self.chunks.pop() {
+            self.queued.fetch_sub(chunk.len(), Relaxed);
+            self.recycle(chunk);
+        }
+    }
+
+    fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    ///Copies queued data into the pipewire buffer, returns the bytes taken. Runs on the realtime thread
+    fn fill(&self, pending: &mut Option<(u64, Vec<u8>, usize)>, out: &mut [u8]) -> usize {
+        let generation = self.generation.load(Acquire);
+        let mut filled = 0;
+        while filled < out.len() {
+            let (chunk_generation, chunk, offset) = match pending.take() {
+                Some(pending) => pending,
+                None => match self.chunks.pop() {
+                    Some((chunk_generation, chunk)) => (chunk_generation, chunk, 0),
+                    None => break,
+                },
+            };
+            if chunk_generation != generation {
+                // Flushed while it was queued, only the part not counted off yet is left in queued
+                self.queued.fetch_sub(chunk.len() - offset, Relaxed);
+                self.recycle(chunk);
+                continue;
+            }
+            let take = (chunk.len() - offset).min(out.len() - filled);
+            out[filled..filled + take].copy_from_slice(&chunk[offset..offset + take]);
+            filled += take;
+            self.queued.fetch_sub(take, Relaxed);
+            if offset + take < chunk.len() {
+                *pending = Some((chunk_generation, chunk, offset + take));
+            } else {
+                self.recycle(chunk);
+            }
+        }
+        if filled > 0 {
+            self.signal();
+        }
+        filled
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if self.space >= 0 {
+            unsafe { libc::close(self.space) };
+        }
+    }
+}
+
+struct Connection {
+    thread: JoinHandle<()>,
+    control: pw::channel::Sender<Control>,
+}
+
+///Native dsd output through PipeWire, so the player can share the device with a running session daemon.
+/// The target node has to accept dsd, e.g alsa node with DSD in iec958.codecs, or a null-sink for testing
+pub struct PipeWireSink {
+    target: Option<String>,
+    ring: Arc<Ring>,
+    connection: Option<Connection>,
+    format: DSDFormat,
+    period_bytes: usize,
+    // Bytes per channel short of a whole pipewire frame, written in front of the next write
+    tail: Vec<Vec<u8>>,
+}
+
+impl PipeWireSink {
+    pub fn new(target: Option<String>) -> Self {
+        Self {
+            target,
+            ring: Arc::new(Ring::new(0)),
+            connection: None,
+            format: DSDFormat::default(),
+            period_bytes: 0,
+            tail: Vec::new(),
+        }
+    }
+
+    ///Parses "pipewire" or "pipewire:<target>"
+    pub fn from_device_id(device_id: &str) -> Option<Self> {
+        let rest = device_id.strip_prefix(PIPEWIRE_DEVICE_ID)?;
+        if rest.is_empty() {
+            return Some(Self::new(None));
+        }
+        let target = rest.strip_prefix(':')?;
+        Some(Self::new(Some(target.to_string())))
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.control.send(Control::Quit);
+            let _ = connection.thread.join();
+        }
+        self.tail.clear();
+    }
+
+    fn check_error(&self) -> Result<(), PlayerError> {
+        match self.ring.error() {
+            Some(e) => Err(stream_error(&e)),
+            None => Ok(()),
+        }
+    }
+
+    ///Interleaves whole pipewire frames of the carried tail and the data, the rest is carried to the next write
+    fn interleave(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Vec<u8> {
+        self.tail.resize_with(data.len(), Vec::new);
+        let carried = self.tail[0].len();
+        if carried > 0 {
+            for (tail, channel) in self.tail.iter_mut().zip(data.iter()) {
+                tail.extend_from_slice(&channel[..bytes_per_channel]);
+            }
+        }
+        let frames = (carried + bytes_per_channel) / INTERLEAVE;
+        let used = frames * INTERLEAVE;
+        let mut chunk = self.ring.free.pop().unwrap_or_default();
+        chunk.clear();
+        chunk.reserve(used * data.len());
+        for frame in 0..frames {
+            for (index, &channel) in data.iter().enumerate() {
+                let channel = if carried > 0 { &self.tail[index][..] } else { channel };
+                for &byte in &channel[frame * INTERLEAVE..(frame + 1) * INTERLEAVE] {
+                    chunk.push(if self.format.is_lsb_first {
+                        BIT_REVERSE_TABLE[byte as usize]
+                    } else {
+                        byte
+                    });
+                }
+            }
+        }
+        for (tail, channel) in self.tail.iter_mut().zip(data.iter()) {
+            if carried == 0 {
+                tail.extend_from_slice(&channel[used..bytes_per_channel]);
+            } else {
+                tail.drain(..used);
+            }
+        }
+        chunk
+    }
+
+    ///Queues the chunk for the callback, blocks while the ring is full
+    fn push(&self, mut chunk: Vec<u8>) -> Result<(), PlayerError> {
+        let ring = &self.ring;
+        let generation = ring.generation.load(Relaxed);
+        loop {
+            self.check_error()?;
+            let queued = ring.queued.load(Relaxed);
+            // Chunk larger than the ring still goes in once the ring is empty
+            if queued == 0 || queued + chunk.len() <= ring.capacity {
+                // Counted first, the callback may take it right after the push
+                ring.queued.fetch_add(chunk.len(), Relaxed);
+                match ring.chunks.push((generation, chunk)) {
+                    Ok(()) => return Ok(()),
+                    Err((_, rejected)) => {
+                        ring.queued.fetch_sub(rejected.len(), Relaxed);
+                        chunk = rejected;
+                    }
+                }
+            }
+            ring.wait(None, ERROR_POLL);
+        }
+    }
+
+    fn format_pod(format: &DSDFormat) -> Vec<u8> {
+        let mut properties = vec![
+            Property::new(
+                spa::sys::SPA_FORMAT_mediaType,
+                Value::Id(spa::utils::Id(spa::sys::SPA_MEDIA_TYPE_audio)),
+            ),
+            Property::new(
+                spa::sys::SPA_FORMAT_mediaSubtype,
+                Value::Id(spa::utils::Id(spa::sys::SPA_MEDIA_SUBTYPE_dsd)),
+            ),
+            Property::new(
+                spa::sys::SPA_FORMAT_AUDIO_bitorder,
+                Value::Id(spa::utils::Id(spa::sys::SPA_PARAM_BITORDER_msb)),
+            ),
+            Property::new(
+                spa::sys::SPA_FORMAT_AUDIO_interleave,
+                Value::Int(INTERLEAVE as i32),
+            ),
+            // spa dsd rate is in bytes per second
+            Property::new(
+                spa::sys::SPA_FORMAT_AUDIO_rate,
+                Value::Int((format.sampling_rate / 8) as i32),
+            ),
+            Property::new(
+                spa::sys::SPA_FORMAT_AUDIO_channels,
+                Value::Int(format.num_channels as i32),
+            ),
+        ];
+        let positions: &[u32] = match format.num_channels {
+            1 => &[spa::sys::SPA_AUDIO_CHANNEL_MONO],
+            2 => &[spa::sys::SPA_AUDIO_CHANNEL_FL, spa::sys::SPA_AUDIO_CHANNEL_FR],
+            5 => &[
+                spa::sys::SPA_AUDIO_CHANNEL_FL,
+                spa::sys::SPA_AUDIO_CHANNEL_FR,
+                spa::sys::SPA_AUDIO_CHANNEL_FC,
+                spa::sys::SPA_AUDIO_CHANNEL_RL,
+                spa::sys::SPA_AUDIO_CHANNEL_RR,
+            ],
+            6 => &[
+                spa::sys::SPA_AUDIO_CHANNEL_FL,
+                spa::sys::SPA_AUDIO_CHANNEL_FR,
+                spa::sys::SPA_AUDIO_CHANNEL_FC,
+                spa::sys::SPA_AUDIO_CHANNEL_LFE,
+                spa::sys::SPA_AUDIO_CHANNEL_RL,
+                spa::sys::SPA_AUDIO_CHANNEL_RR,
+            ],
+            _ => &[],
+        };
+        if !positions.is_empty() {
+            properties.push(Property::new(
+                spa::sys::SPA_FORMAT_AUDIO_position,
+                Value::ValueArray(ValueArray::Id(
+                    positions.iter().copied().map(spa::utils::Id).collect(),
+                )),
+            ));
+        }
+        spa::pod::serialize::PodSerializer::serialize(
+            std::io::Cursor::new(Vec::new()),
+            &Value::Object(Object {
+                type_: spa::sys::SPA_TYPE_OBJECT_Format,
+                id: spa::sys::SPA_PARAM_EnumFormat,
+                properties,
+            }),
+        )
+        .unwrap()
+        .0
+        .into_inner()
+    }
+
+    fn run_stream(
+        target: Option<String>,
+        format: DSDFormat,
+        ring: Arc<Ring>,
+        control: pw::channel::Receiver<Control>,
+        ready: std::sync::mpsc::Sender<Result<(), String>>,
+    ) {
+        let res = (|| -> Result<(), pw::Error> {
+            let mainloop = pw::main_loop::MainLoopRc::new(None)?;
+            let context = pw::context::ContextRc::new(&mainloop, None)?;
+            let core = context.connect_rc(None)?;
+
+            let mut props = pw::properties::properties! {
+                *pw::keys::MEDIA_TYPE => "Audio",
+                *pw::keys::MEDIA_CATEGORY => "Playback",
+                *pw::keys::MEDIA_ROLE => "Music",
+                *pw::keys::NODE_NAME => "ndsd-playback",
+            };
+            if let Some(target) = target.as_ref() {
+                props.insert(*pw::keys::TARGET_OBJECT, target.as_str());
+            }
+            let stream = pw::stream::StreamRc::new(core, "ndsd-playback", props)?;
+
+            let stride = INTERLEAVE * format.num_channels as usize;
+            let process_ring = ring.clone();
+            let error_ring = ring.clone();
+            // Chunk the callback is in the middle of, with its generation and the bytes already taken
+            let mut pending = None;
+            let _listener = stream
+                .add_local_listener::<()>()
+                .process(move |stream, _| {
+                    let Some(mut buffer) = stream.dequeue_buffer() else {
+                        return;
+                    };
+                    let data = &mut buffer.datas_mut()[0];
+                    let mut filled = 0;
+                    if let Some(slice) = data.data() {
+                        filled = (slice.len() / stride) * stride;
+                        let take = process_ring.fill(&mut pending, &mut slice[..filled]);
+                        // Underrun, keep the DAC locked with dsd silence
+                        slice[take..filled].fill(DSD_SILENCE);
+                    }
+                    let chunk = data.chunk_mut();
+                    *chunk.offset_mut() = 0;
+                    *chunk.stride_mut() = stride as _;
+                    *chunk.size_mut() = filled as _;
+                })
+                .state_changed(move |_, _, _, new| {
+                    if let pw::stream::StreamState::Error(e) = new {
+                        *error_ring.error.lock().unwrap() = Some(e);
+                        error_ring.signal();
+                    }
+                })
+                .register()?;
+
+            let values = Self::format_pod(&format);
+            let mut params = [Pod::from_bytes(&values).unwrap()];
+            stream.connect(
+                spa::utils::Direction::Output,
+                None,
+                pw::stream::StreamFlags::AUTOCONNECT
+                    | pw::stream::StreamFlags::MAP_BUFFERS
+                    | pw::stream::StreamFlags::RT_PROCESS,
+                &mut params,
+            )?;
+
+            let control_stream = stream.clone();
+            let control_loop = mainloop.clone();
+            let _receiver = control.attach(mainloop.loop_(), move |msg| match msg {
+                Control::Pause(paused) => {
+                    let _ = control_stream.set_active(!paused);
+                }
+                Control::Quit => control_loop.quit(),
+            });
+            let _ = ready.send(Ok(()));
+            mainloop.run();
+            let _ = stream.disconnect();
+            Ok(())
+        })();
+        if let Err(e) = res {
+            let _ = ready.send(Err(e.to_string()));
+        }
+    }
+}
+
+fn stream_error(message: &str) -> PlayerError {
+    PlayerError::Device {
+        errno: 0,
+        message: message.to_string(),
+    }
+}
+
+impl AudioSink for PipeWireSink {
+    fn open(&mut self, format: &DSDFormat) -> Result<(), PlayerError> {
+        self.disconnect();
+        pw::init();
+        let bytes_per_second = (format.sampling_rate / 8) as usize;
+        // Period is 20ms, rounded to whole pipewire frames
+        self.period_bytes = (bytes_per_second / 50 / INTERLEAVE).max(1) * INTERLEAVE;
+        self.format = *format;
+        // New stream gets a ring of its own, nothing of the previous format reaches it
+        self.ring = Arc::new(Ring::new(
+            (bytes_per_second as f64 * BUFFER_SECONDS) as usize * format.num_channels as usize,
+        ));
+
+        let (control_tx, control_rx) = pw::channel::channel::<Control>();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let ring = self.ring.clone();
+        let target = self.target.clone();
+        let format = *format;
+        let thread = std::thread::spawn(move || {
+            Self::run_stream(target, format, ring, control_rx, ready_tx)
+        });
+        match ready_rx.recv() {
+            Ok(Ok(())) => {
+                self.connection = Some(Connection {
+                    thread,
+                    control: control_tx,
+                });
+                Ok(())
+            }
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(PlayerError::DeviceOpen {
+                    device: self.target.clone().unwrap_or_else(|| PIPEWIRE_DEVICE_ID.to_string()),
+                    errno: 0,
+                    message: e,
+                })
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err(stream_error("pipewire thread exited"))
+            }
+        }
+    }
+
+    fn period_bytes(&self) -> usize {
+        self.period_bytes
+    }
+
+    fn write(&mut self, data: &[&[u8]], bytes_per_channel: usize) -> Result<(), PlayerError> {
+        let chunk = self.interleave(data, bytes_per_channel);
+        if chunk.is_empty() {
+            self.ring.recycle(chunk);
+            return self.check_error();
+        }
+        self.push(chunk)
+    }
+
+    fn wait_writable(&mut self, wakeup: &Wakeup, timeout: Duration) -> Result<bool, PlayerError> {
+        let ring = &self.ring;
+        let period = self.period_bytes * self.format.num_channels as usize;
+        let writable = || ring.queued.load(Relaxed) + period <= ring.capacity;
+        if self.connection.is_none() || writable() {
+            return Ok(true);
+        }
+        ring.wait(Some(wakeup), timeout);
+        self.check_error()?;
+        Ok(writable())
+    }
+
+    fn pause(&mut self, paused: bool) -> Result<(), PlayerError> {
+        if let Some(connection) = self.connection.as_ref() {
+            let _ = connection.control.send(Control::Pause(paused));
+        }
+        self.check_error()
+    }
+
+    fn drain(&mut self) -> Result<(), PlayerError> {
+        if self.tail.first().is_some_and(|tail| !tail.is_empty()) {
+            // Last frame is completed with silence instead of being lost
+            let mut frame = vec![0u8; INTERLEAVE - self.tail[0].len()];
+            fill_silence(&mut frame, self.format.is_lsb_first);
+            let padding: Vec<&[u8]> = self.tail.iter().map(|_| frame.as_slice()).collect();
+            self.write(&padding, frame.len())?;
+        }
+        while self.connection.is_some() && self.ring.queued.load(Relaxed) > 0 {
+            self.check_error()?;
+            self.ring.wait(None, ERROR_POLL);
+        }
+        self.check_error()
+    }
+
+    fn flush(&mut self) -> Result<(), PlayerError> {
+        self.tail.clear();
+        self.ring.clear();
+        self.check_error()
+    }
+
+    fn delay(&self) -> usize {
+        let channels = (self.format.num_channels as usize).max(1);
+        self.ring.queued.load(Relaxed) / channels
+    }
+
+    fn needs_silence(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for PipeWireSink {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
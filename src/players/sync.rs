@@ -0,0 +1,175 @@
+use crate::players::{
+    BufferLevel, DSDPlayer, OutputParams, PlaybackTime, PlayerConfig, PlayerError, PlayerEvent, SeekDirection,
+    XrunStats, create_player,
+};
+use ndsd_read::{DSDFormat, DSDMeta};
+use std::ffi::CString;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use std::time::Duration;
+
+///Blocking facade over DSDPlayer for code without an async runtime, every call returns once the player answered.
+/// Player futures only wait for the player thread, so the calling thread is parked instead of running an executor.
+/// Calls block the thread they are made from, inside an async task use DSDPlayer directly
+pub struct SyncPlayer {
+    player: Box<dyn DSDPlayer>,
+}
+
+impl SyncPlayer {
+    ///Accepts the same device ids as create_player
+    pub fn create(device_id: CString, config: PlayerConfig) -> Result<Self, PlayerError> {
+        Ok(Self::new(create_player(device_id, config)?))
+    }
+
+    pub fn new(player: Box<dyn DSDPlayer>) -> Self {
+        Self { player }
+    }
+
+    ///Async player for the parts of the program which have a runtime
+    pub fn into_inner(self) -> Box<dyn DSDPlayer> {
+        self.player
+    }
+
+    pub fn start(&mut self) -> Result<(), PlayerError> {
+        block_on(self.player.start())
+    }
+
+    pub fn pause(&self) -> Result<(), PlayerError> {
+        block_on(self.player.pause())
+    }
+
+    pub fn play(&self) -> Result<(), PlayerError> {
+        block_on(self.player.play())
+    }
+
+    ///Position of what is heard right now in percent of the track
+    pub fn get_pos(&self) -> f64 {
+        block_on(self.player.get_pos())
+    }
+
+    pub fn position(&self) -> PlaybackTime {
+        block_on(self.player.position())
+    }
+
+    pub fn duration(&self) -> PlaybackTime {
+        block_on(self.player.duration())
+    }
+
+    pub fn stop(&self) -> Result<(), PlayerError> {
+        block_on(self.player.stop())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        block_on(self.player.is_playing())
+    }
+
+    pub fn load_new_track(&mut self, filename: &str) -> Result<(), PlayerError> {
+        block_on(self.player.load_new_track(filename))
+    }
+
+    pub fn seek(&mut self, percent: f64) -> Result<(), PlayerError> {
+        block_on(self.player.seek(percent))
+    }
+
+    pub fn seek_to(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        block_on(self.player.seek_to(position))
+    }
+
+    pub fn seek_by(&mut self, offset: Duration, direction: SeekDirection) -> Result<PlaybackTime, PlayerError> {
+        block_on(self.player.seek_by(offset, direction))
+    }
+
+    pub fn start_at(&mut self, position: Duration) -> Result<PlaybackTime, PlayerError> {
+        block_on(self.player.start_at(position))
+    }
+
+    pub fn enqueue_next(&mut self, filename: &str) -> Result<(), PlayerError> {
+        block_on(self.player.enqueue_next(filename))
+    }
+
+    pub fn get_format_info(&self) -> DSDFormat {
+        block_on(self.player.get_format_info())
+    }
+
+    pub fn get_current_file_meta(&self) -> Option<DSDMeta> {
+        block_on(self.player.get_current_file_meta())
+    }
+
+    pub fn take_error(&self) -> Option<PlayerError> {
+        block_on(self.player.take_error())
+    }
+
+    ///New receiver of player events, it gets everything sent after the call
+    pub fn subscribe(&self) -> mpsc::Receiver<PlayerEvent> {
+        self.player.subscribe_blocking()
+    }
+
+    pub fn buffer_level(&self) -> BufferLevel {
+        block_on(self.player.buffer_level())
+    }
+
+    pub fn output_params(&self) -> Option<OutputParams> {
+        block_on(self.player.output_params())
+    }
+
+    pub fn xrun_stats(&self) -> XrunStats {
+        block_on(self.player.xrun_stats())
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        block_on(self.player.is_device_lost())
+    }
+
+    pub fn close(&mut self) -> Result<(), PlayerError> {
+        block_on(self.player.close())
+    }
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+///Polls the future on the calling thread, parking it until the player wakes the future up
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            // Spurious unparks only cause another poll
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_test_track;
+
+    #[test]
+    fn plays_without_a_runtime() {
+        let path = write_test_track("ndsd_sync_player.dsf", 2822400, 0.1);
+
+        let mut player = SyncPlayer::create(c"null:4".into(), PlayerConfig::default()).unwrap();
+        let events = player.subscribe();
+        player.load_new_track(&path).unwrap();
+        assert_eq!(player.duration().time, Duration::from_millis(100));
+        player.start().unwrap();
+        let ended = std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(3)).ok())
+            .any(|event| event == PlayerEvent::TrackEnded);
+        assert!(ended);
+        player.close().unwrap();
+        assert_eq!(player.start(), Err(PlayerError::Terminated));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+///Interrupts the player thread while it waits for the output, set by every command sent to the player.
+/// Sinks polling file descriptors wait on fd() next to their own, others call wait
+pub struct Wakeup {
+    woken: Mutex<bool>,
+    signal: Condvar,
+    #[cfg(target_os = "linux")]
+    fd: libc::c_int,
+}
+
+impl Wakeup {
+    pub fn new() -> Self {
+        Self {
+            woken: Mutex::new(false),
+            signal: Condvar::new(),
+            #[cfg(target_os = "linux")]
+            fd: unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) },
+        }
+    }
+
+    pub fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.signal.notify_all();
+        #[cfg(target_os = "linux")]
+        if self.fd >= 0 {
+            let one: u64 = 1;
+            unsafe { libc::write(self.fd, &one as *const u64 as *const libc::c_void, 8) };
+        }
+    }
+
+    ///Forgets a pending wake, called right before the commands are read
+    pub fn clear(&self) {
+        *self.woken.lock().unwrap() = false;
+        #[cfg(target_os = "linux")]
+        if self.fd >= 0 {
+            let mut count: u64 = 0;
+            unsafe { libc::read(self.fd, &mut count as *mut u64 as *mut libc::c_void, 8) };
+        }
+    }
+
+    ///Sleeps until woken or the timeout expired, true if woken
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let woken = self.woken.lock().unwrap();
+        let (woken, _) = self.signal.wait_timeout_while(woken, timeout, |woken| !*woken).unwrap();
+        *woken
+    }
+
+    ///Readable while a wake is pending, negative if the eventfd could not be created
+    #[cfg(target_os = "linux")]
+    pub fn fd(&self) -> libc::c_int {
+        self.fd
+    }
+}
+
+impl Default for Wakeup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use crate::players::PlaybackTime;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+///How many of the latest xruns XrunStats keeps with their timestamps
+pub const XRUN_HISTORY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XrunKind {
+    ///Output ran out of data, alsa -EPIPE
+    Underrun,
+    ///System was suspended while playing, alsa -ESTRPIPE
+    Suspend,
+}
+
+///Xrun the output recovered from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Xrun {
+    pub kind: XrunKind,
+    pub at: SystemTime,
+    ///Heard position of the track when it was detected
+    pub position: PlaybackTime,
+}
+
+///Xruns since the player was created, counts never reset
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct XrunStats {
+    pub underruns: u64,
+    pub suspends: u64,
+    ///Latest XRUN_HISTORY xruns, oldest first
+    pub recent: VecDeque<Xrun>,
+}
+
+impl XrunStats {
+    pub fn total(&self) -> u64 {
+        self.underruns + self.suspends
+    }
+
+    pub fn record(&mut self, xrun: Xrun) {
+        match xrun.kind {
+            XrunKind::Underrun => self.underruns += 1,
+            XrunKind::Suspend => self.suspends += 1,
+        }
+        if self.recent.len() == XRUN_HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(xrun);
+    }
+}
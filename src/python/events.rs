@@ -0,0 +1,177 @@
+use super::{PyDSDFormat, PyDSDMeta, in_executor, seconds};
+use crate::players::{PlayerError, PlayerEvent, XrunKind};
+use pyo3::IntoPyObjectExt;
+use pyo3::exceptions::PyStopAsyncIteration;
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often a waiting receiver checks for a closed player and for Ctrl+C
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+///PlayerEvent for Python, kind is the snake case name of the variant and the fields which do not belong to
+/// the kind are None
+#[pyclass(frozen, skip_from_py_object, get_all, name = "PlayerEvent", module = "ndsdplayback")]
+#[derive(Debug, Clone)]
+pub struct PyPlayerEvent {
+    kind: &'static str,
+    ///Percent (0..1) for seeked and position_tick
+    position: Option<f64>,
+    ///Track format for track_loaded and format_changed
+    format: Option<PyDSDFormat>,
+    meta: Option<PyDSDMeta>,
+    ///"underrun" or "suspend" for xrun
+    xrun: Option<&'static str>,
+    ///Text of the error for device_lost and device_error
+    error: Option<String>,
+}
+
+impl From<PlayerEvent> for PyPlayerEvent {
+    fn from(event: PlayerEvent) -> Self {
+        let mut out = PyPlayerEvent {
+            kind: "",
+            position: None,
+            format: None,
+            meta: None,
+            xrun: None,
+            error: None,
+        };
+        out.kind = match event {
+            PlayerEvent::TrackLoaded { format, meta } => {
+                out.format = Some(format.into());
+                out.meta = meta.map(PyDSDMeta::from);
+                "track_loaded"
+            }
+            PlayerEvent::Started => "started",
+            PlayerEvent::Paused => "paused",
+            PlayerEvent::Resumed => "resumed",
+            PlayerEvent::Stopped => "stopped",
+            PlayerEvent::Seeked { position } => {
+                out.position = Some(position);
+                "seeked"
+            }
+            PlayerEvent::PositionTick { position } => {
+                out.position = Some(position);
+                "position_tick"
+            }
+            PlayerEvent::TrackEnded => "track_ended",
+            PlayerEvent::FormatChanged(format) => {
+                out.format = Some(format.into());
+                "format_changed"
+            }
+            PlayerEvent::Xrun(xrun) => {
+                out.xrun = Some(match xrun.kind {
+                    XrunKind::Underrun => "underrun",
+                    XrunKind::Suspend => "suspend",
+                });
+                "xrun"
+            }
+            PlayerEvent::DeviceLost(e) => {
+                out.error = Some(e.to_string());
+                "device_lost"
+            }
+            PlayerEvent::DeviceReconnected => "device_reconnected",
+            PlayerEvent::DeviceError(e) => {
+                out.error = Some(e.to_string());
+                "device_error"
+            }
+        };
+        out
+    }
+}
+
+#[pymethods]
+impl PyPlayerEvent {
+    fn __repr__(&self) -> String {
+        format!("PlayerEvent(kind={:?})", self.kind)
+    }
+}
+
+///Receiver returned by DSDPlayer.subscribe. Iterating with for or async for ends once the player is closed
+#[pyclass(frozen, skip_from_py_object, name = "PlayerEvents", module = "ndsdplayback")]
+pub struct PyPlayerEvents {
+    events: Mutex<Receiver<PlayerEvent>>,
+    closed: Arc<AtomicBool>,
+}
+
+///Why a receiver returned without an event
+enum Missed {
+    Timeout,
+    Closed,
+}
+
+impl PyPlayerEvents {
+    pub fn new(events: Receiver<PlayerEvent>, closed: Arc<AtomicBool>) -> Self {
+        Self {
+            events: Mutex::new(events),
+            closed,
+        }
+    }
+
+    ///Waits without the GIL, events sent before the player was closed are still delivered
+    fn receive(&self, py: Python<'_>, timeout: Option<Duration>) -> PyResult<Result<PlayerEvent, Missed>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let wait = deadline.map_or(STOP_POLL, |deadline| {
+                deadline.saturating_duration_since(Instant::now()).min(STOP_POLL)
+            });
+            match py.detach(|| self.events.lock().unwrap().recv_timeout(wait)) {
+                Ok(event) => return Ok(Ok(event)),
+                Err(RecvTimeoutError::Disconnected) => return Ok(Err(Missed::Closed)),
+                Err(RecvTimeoutError::Timeout) if self.closed.load(Relaxed) => return Ok(Err(Missed::Closed)),
+                Err(RecvTimeoutError::Timeout) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    return Ok(Err(Missed::Timeout));
+                }
+                Err(RecvTimeoutError::Timeout) => py.check_signals()?,
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl PyPlayerEvents {
+    ///Next event, None once the timeout in seconds passed. Raises PlayerError when the player is closed
+    #[pyo3(signature = (timeout = None))]
+    fn recv(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<Option<PyPlayerEvent>> {
+        let timeout = timeout.map(|timeout| seconds(timeout, "timeout")).transpose()?;
+        match self.receive(py, timeout)? {
+            Ok(event) => Ok(Some(event.into())),
+            Err(Missed::Timeout) => Ok(None),
+            Err(Missed::Closed) => Err(PlayerError::Terminated.into()),
+        }
+    }
+
+    #[pyo3(signature = (timeout = None))]
+    fn recv_async<'py>(slf: &Bound<'py, Self>, timeout: Option<f64>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "recv", vec![timeout.into_bound_py_any(slf.py())?])
+    }
+
+    fn __iter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyPlayerEvent>> {
+        match self.receive(py, None)? {
+            Ok(event) => Ok(Some(event.into())),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn __aiter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __anext__<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "_next_or_stop", vec![])
+    }
+
+    // StopIteration can not leave an executor, the end of async for is signalled with StopAsyncIteration
+    fn _next_or_stop(&self, py: Python<'_>) -> PyResult<PyPlayerEvent> {
+        match self.receive(py, None)? {
+            Ok(event) => Ok(event.into()),
+            Err(_) => Err(PyStopAsyncIteration::new_err(())),
+        }
+    }
+}
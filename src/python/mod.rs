@@ -0,0 +1,579 @@
+//! Python module ndsdplayback over enumerate_supported_devices, create_player and the player operations,
+//! built with the python feature.
+//!
+//! Blocking methods release the GIL while the player works, every control method has an _async variant which
+//! runs it in the default executor of the running asyncio loop. Times are seconds as float.
+mod events;
+
+use crate::players::{self, ClosePolicy, LatencyProfile, PlayerConfig, ReconnectPolicy, SeekDirection, SyncPlayer};
+use events::{PyPlayerEvent, PyPlayerEvents};
+use ndsd_read::{DSDFormat, DSDMeta, MetaPicture};
+use pyo3::IntoPyObjectExt;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyTuple};
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+create_exception!(ndsdplayback, PlayerError, PyException, "Failure reported by the player");
+
+impl From<players::PlayerError> for PyErr {
+    fn from(e: players::PlayerError) -> Self {
+        PlayerError::new_err(e.to_string())
+    }
+}
+
+fn seconds(value: f64, name: &str) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(value)
+        .map_err(|_| PyValueError::new_err(format!("{name} must be a finite, positive number of seconds")))
+}
+
+///Awaitable running the blocking method in the default executor of the running asyncio loop
+fn in_executor<'py>(
+    object: &Bound<'py, PyAny>,
+    method: &str,
+    args: Vec<Bound<'py, PyAny>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let py = object.py();
+    let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+    let mut call = vec![py.None().into_bound(py), object.getattr(method)?];
+    call.extend(args);
+    event_loop.call_method1("run_in_executor", PyTuple::new(py, call)?)
+}
+
+///Output device found by enumerate_supported_devices
+#[pyclass(
+    frozen,
+    skip_from_py_object,
+    eq,
+    get_all,
+    name = "DeviceInfo",
+    module = "ndsdplayback"
+)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PyDeviceInfo {
+    ///Name to pass to create_player, card numbers in it may change after reboot or re-plugging
+    id: String,
+    ///Name which survives reboots and re-plugging, accepted by create_player the same way as id
+    stable_id: String,
+    card_description: String,
+    device_description: String,
+    ioid: Option<String>,
+    card_index: Option<i32>,
+    driver: Option<String>,
+    ///Device has no native dsd format and plays over DoP
+    dop: bool,
+}
+
+impl From<players::DeviceInfo> for PyDeviceInfo {
+    fn from(device: players::DeviceInfo) -> Self {
+        Self {
+            id: device.id.to_string_lossy().into_owned(),
+            stable_id: device.stable_id.to_string_lossy().into_owned(),
+            card_description: device.card_description,
+            device_description: device.device_description,
+            ioid: device.ioid,
+            card_index: device.card_index,
+            driver: device.driver,
+            dop: device.dop,
+        }
+    }
+}
+
+#[pymethods]
+impl PyDeviceInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceInfo(id={:?}, card_description={:?}, dop={})",
+            self.id, self.card_description, self.dop
+        )
+    }
+}
+
+#[pyclass(
+    frozen,
+    skip_from_py_object,
+    eq,
+    get_all,
+    name = "DSDFormat",
+    module = "ndsdplayback"
+)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PyDSDFormat {
+    ///Bits per second per channel
+    sampling_rate: u32,
+    num_channels: u32,
+    total_samples: u64,
+    is_lsb_first: bool,
+}
+
+impl From<DSDFormat> for PyDSDFormat {
+    fn from(format: DSDFormat) -> Self {
+        Self {
+            sampling_rate: format.sampling_rate,
+            num_channels: format.num_channels,
+            total_samples: format.total_samples,
+            is_lsb_first: format.is_lsb_first,
+        }
+    }
+}
+
+#[pymethods]
+impl PyDSDFormat {
+    fn __repr__(&self) -> String {
+        format!(
+            "DSDFormat(sampling_rate={}, num_channels={}, total_samples={}, is_lsb_first={})",
+            self.sampling_rate,
+            self.num_channels,
+            self.total_samples,
+            if self.is_lsb_first { "True" } else { "False" }
+        )
+    }
+}
+
+#[pyclass(frozen, skip_from_py_object, eq, name = "MetaPicture", module = "ndsdplayback")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PyMetaPicture {
+    #[pyo3(get)]
+    description: String,
+    #[pyo3(get)]
+    mime_type: String,
+    data: Vec<u8>,
+}
+
+#[pymethods]
+impl PyMetaPicture {
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.data)
+    }
+}
+
+impl From<MetaPicture> for PyMetaPicture {
+    fn from(picture: MetaPicture) -> Self {
+        Self {
+            description: picture.description,
+            mime_type: picture.mime_type,
+            data: picture.data,
+        }
+    }
+}
+
+///Tags of the track, None where the file has none
+#[pyclass(frozen, skip_from_py_object, eq, name = "DSDMeta", module = "ndsdplayback")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PyDSDMeta {
+    #[pyo3(get)]
+    artist: Option<String>,
+    #[pyo3(get)]
+    album: Option<String>,
+    #[pyo3(get)]
+    title: Option<String>,
+    #[pyo3(get)]
+    comment: Option<String>,
+    #[pyo3(get)]
+    genre: Option<String>,
+    #[pyo3(get)]
+    lyrics: Vec<String>,
+    #[pyo3(get)]
+    year: Option<u32>,
+    #[pyo3(get)]
+    cover_art: Vec<PyMetaPicture>,
+    id3_raw: Option<Vec<u8>>,
+}
+
+#[pymethods]
+impl PyDSDMeta {
+    ///Whole id3 tag of the file as bytes
+    #[getter]
+    fn id3_raw<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        self.id3_raw.as_ref().map(|raw| PyBytes::new(py, raw))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DSDMeta(artist={:?}, album={:?}, title={:?})",
+            self.artist, self.album, self.title
+        )
+    }
+}
+
+impl From<DSDMeta> for PyDSDMeta {
+    fn from(meta: DSDMeta) -> Self {
+        Self {
+            artist: meta.artist,
+            album: meta.album,
+            title: meta.title,
+            comment: meta.comment,
+            genre: meta.genre,
+            lyrics: meta.lyrics,
+            year: meta.year,
+            cover_art: meta.cover_art.into_iter().map(PyMetaPicture::from).collect(),
+            id3_raw: meta.id3_raw,
+        }
+    }
+}
+
+///Point in the track, one frame is a single bit per channel
+#[pyclass(
+    frozen,
+    skip_from_py_object,
+    eq,
+    get_all,
+    name = "PlaybackTime",
+    module = "ndsdplayback"
+)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PyPlaybackTime {
+    seconds: f64,
+    frames: u64,
+}
+
+impl From<players::PlaybackTime> for PyPlaybackTime {
+    fn from(time: players::PlaybackTime) -> Self {
+        Self {
+            seconds: time.time.as_secs_f64(),
+            frames: time.frames,
+        }
+    }
+}
+
+#[pymethods]
+impl PyPlaybackTime {
+    fn __repr__(&self) -> String {
+        format!("PlaybackTime(seconds={}, frames={})", self.seconds, self.frames)
+    }
+}
+
+///Dsd capable output devices
+#[pyfunction]
+fn enumerate_supported_devices(py: Python<'_>) -> Vec<PyDeviceInfo> {
+    py.detach(players::enumerate_supported_devices)
+        .into_iter()
+        .map(PyDeviceInfo::from)
+        .collect()
+}
+
+///Creates the player for a device id or stable id. Profile is "low_latency", "balanced" or "high_buffer",
+/// close_policy "drop" or "drain", times are seconds and None keeps the value of the profile
+#[pyfunction]
+#[pyo3(signature = (
+    device_id, *, profile = "balanced", buffer_time = None, period_time = None, start_threshold = None,
+    read_ahead = None, preroll = None, mute_time = None, reconnect_interval = None, reconnect_attempts = None,
+    close_policy = "drop"
+))]
+#[allow(clippy::too_many_arguments)]
+fn create_player(
+    py: Python<'_>,
+    device_id: &str,
+    profile: &str,
+    buffer_time: Option<f64>,
+    period_time: Option<f64>,
+    start_threshold: Option<f64>,
+    read_ahead: Option<f64>,
+    preroll: Option<f64>,
+    mute_time: Option<f64>,
+    reconnect_interval: Option<f64>,
+    reconnect_attempts: Option<u32>,
+    close_policy: &str,
+) -> PyResult<PyDSDPlayer> {
+    let profile = match profile {
+        "low_latency" => LatencyProfile::LowLatency,
+        "balanced" => LatencyProfile::Balanced,
+        "high_buffer" => LatencyProfile::HighBuffer,
+        _ => return Err(PyValueError::new_err(format!("unknown latency profile {profile:?}"))),
+    };
+    let close_policy = match close_policy {
+        "drop" => ClosePolicy::Drop,
+        "drain" => ClosePolicy::Drain,
+        _ => return Err(PyValueError::new_err(format!("unknown close policy {close_policy:?}"))),
+    };
+    let mut config = PlayerConfig::new().profile(profile).close_policy(close_policy);
+    let mut reconnect = ReconnectPolicy::default();
+    if let Some(interval) = reconnect_interval {
+        reconnect.interval = seconds(interval, "reconnect_interval")?;
+    }
+    if let Some(attempts) = reconnect_attempts {
+        reconnect.max_attempts = Some(attempts);
+    }
+    config = config.reconnect(reconnect);
+    type Setter = fn(PlayerConfig, Duration) -> PlayerConfig;
+    let setters: [(Option<f64>, &str, Setter); 6] = [
+        (buffer_time, "buffer_time", PlayerConfig::buffer_time),
+        (period_time, "period_time", PlayerConfig::period_time),
+        (start_threshold, "start_threshold", PlayerConfig::start_threshold),
+        (read_ahead, "read_ahead", PlayerConfig::read_ahead),
+        (preroll, "preroll", PlayerConfig::preroll),
+        (mute_time, "mute_time", PlayerConfig::mute_time),
+    ];
+    for (value, name, set) in setters {
+        if let Some(value) = value {
+            config = set(config, seconds(value, name)?);
+        }
+    }
+    let device_id = CString::new(device_id).map_err(|_| PyValueError::new_err("device id has a nul"))?;
+    let player = py.detach(|| SyncPlayer::create(device_id, config))?;
+    Ok(PyDSDPlayer {
+        player: Mutex::new(player),
+        closed: Arc::new(AtomicBool::new(false)),
+    })
+}
+
+///Player returned by create_player. Calls from several threads are answered one after another,
+/// the player is closed as its close policy says when it is garbage collected or leaves a with block
+#[pyclass(frozen, skip_from_py_object, name = "DSDPlayer", module = "ndsdplayback")]
+pub struct PyDSDPlayer {
+    player: Mutex<SyncPlayer>,
+    // Ends the iteration of the event receivers once the player is closed
+    closed: Arc<AtomicBool>,
+}
+
+impl PyDSDPlayer {
+    ///Runs the call on the player without holding the GIL
+    fn with<T: Send>(&self, py: Python<'_>, call: impl FnOnce(&mut SyncPlayer) -> T + Send) -> T {
+        py.detach(|| call(&mut self.player.lock().unwrap()))
+    }
+}
+
+impl Drop for PyDSDPlayer {
+    fn drop(&mut self) {
+        self.closed.store(true, Relaxed);
+    }
+}
+
+#[pymethods]
+impl PyDSDPlayer {
+    fn load_new_track(&self, py: Python<'_>, path: &str) -> PyResult<()> {
+        Ok(self.with(py, |player| player.load_new_track(path))?)
+    }
+
+    fn load_new_track_async<'py>(slf: &Bound<'py, Self>, path: &str) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "load_new_track", vec![path.into_bound_py_any(slf.py())?])
+    }
+
+    ///Track which plays right after the current one ends, gapless when the format is the same
+    fn enqueue_next(&self, py: Python<'_>, path: &str) -> PyResult<()> {
+        Ok(self.with(py, |player| player.enqueue_next(path))?)
+    }
+
+    fn enqueue_next_async<'py>(slf: &Bound<'py, Self>, path: &str) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "enqueue_next", vec![path.into_bound_py_any(slf.py())?])
+    }
+
+    fn start(&self, py: Python<'_>) -> PyResult<()> {
+        Ok(self.with(py, |player| player.start())?)
+    }
+
+    fn start_async<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "start", vec![])
+    }
+
+    fn pause(&self, py: Python<'_>) -> PyResult<()> {
+        Ok(self.with(py, |player| player.pause())?)
+    }
+
+    fn pause_async<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "pause", vec![])
+    }
+
+    fn play(&self, py: Python<'_>) -> PyResult<()> {
+        Ok(self.with(py, |player| player.play())?)
+    }
+
+    fn play_async<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "play", vec![])
+    }
+
+    fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        Ok(self.with(py, |player| player.stop())?)
+    }
+
+    fn stop_async<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "stop", vec![])
+    }
+
+    ///Position in percent (0..1)
+    fn seek(&self, py: Python<'_>, percent: f64) -> PyResult<()> {
+        Ok(self.with(py, |player| player.seek(percent))?)
+    }
+
+    fn seek_async<'py>(slf: &Bound<'py, Self>, percent: f64) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "seek", vec![percent.into_bound_py_any(slf.py())?])
+    }
+
+    ///Returns the position playback continues from
+    fn seek_to(&self, py: Python<'_>, position: f64) -> PyResult<PyPlaybackTime> {
+        let position = seconds(position, "position")?;
+        Ok(self.with(py, |player| player.seek_to(position))?.into())
+    }
+
+    fn seek_to_async<'py>(slf: &Bound<'py, Self>, position: f64) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "seek_to", vec![position.into_bound_py_any(slf.py())?])
+    }
+
+    ///Negative offset seeks backwards, returns the position playback continues from
+    fn seek_by(&self, py: Python<'_>, offset: f64) -> PyResult<PyPlaybackTime> {
+        let direction = if offset < 0. {
+            SeekDirection::Backward
+        } else {
+            SeekDirection::Forward
+        };
+        let offset = seconds(offset.abs(), "offset")?;
+        Ok(self.with(py, |player| player.seek_by(offset, direction))?.into())
+    }
+
+    fn seek_by_async<'py>(slf: &Bound<'py, Self>, offset: f64) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "seek_by", vec![offset.into_bound_py_any(slf.py())?])
+    }
+
+    fn start_at(&self, py: Python<'_>, position: f64) -> PyResult<PyPlaybackTime> {
+        let position = seconds(position, "position")?;
+        Ok(self.with(py, |player| player.start_at(position))?.into())
+    }
+
+    fn start_at_async<'py>(slf: &Bound<'py, Self>, position: f64) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "start_at", vec![position.into_bound_py_any(slf.py())?])
+    }
+
+    ///Stops the playback and releases the device, later calls raise PlayerError
+    fn close(&self, py: Python<'_>) -> PyResult<()> {
+        let result = self.with(py, |player| player.close());
+        self.closed.store(true, Relaxed);
+        Ok(result?)
+    }
+
+    fn close_async<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        in_executor(slf.as_any(), "close", vec![])
+    }
+
+    ///Position of what is heard right now in percent of the track
+    fn get_pos(&self, py: Python<'_>) -> f64 {
+        self.with(py, |player| player.get_pos())
+    }
+
+    fn position(&self, py: Python<'_>) -> PyPlaybackTime {
+        self.with(py, |player| player.position()).into()
+    }
+
+    fn duration(&self, py: Python<'_>) -> PyPlaybackTime {
+        self.with(py, |player| player.duration()).into()
+    }
+
+    fn is_playing(&self, py: Python<'_>) -> bool {
+        self.with(py, |player| player.is_playing())
+    }
+
+    fn is_device_lost(&self, py: Python<'_>) -> bool {
+        self.with(py, |player| player.is_device_lost())
+    }
+
+    fn get_format_info(&self, py: Python<'_>) -> PyDSDFormat {
+        self.with(py, |player| player.get_format_info()).into()
+    }
+
+    fn get_current_file_meta(&self, py: Python<'_>) -> Option<PyDSDMeta> {
+        self.with(py, |player| player.get_current_file_meta())
+            .map(PyDSDMeta::from)
+    }
+
+    ///Error which stopped the playback as PlayerError, None if there was none
+    fn take_error(&self, py: Python<'_>) -> Option<PyErr> {
+        self.with(py, |player| player.take_error()).map(PyErr::from)
+    }
+
+    ///New receiver of player events, it gets everything sent after the call
+    fn subscribe(&self, py: Python<'_>) -> PyPlayerEvents {
+        PyPlayerEvents::new(self.with(py, |player| player.subscribe()), self.closed.clone())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Py<PyAny>,
+        _exc_value: Py<PyAny>,
+        _traceback: Py<PyAny>,
+    ) -> PyResult<()> {
+        self.close(py)
+    }
+}
+
+#[pymodule]
+fn ndsdplayback(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(enumerate_supported_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(create_player, m)?)?;
+    m.add_class::<PyDSDPlayer>()?;
+    m.add_class::<PyPlayerEvents>()?;
+    m.add_class::<PyPlayerEvent>()?;
+    m.add_class::<PyDeviceInfo>()?;
+    m.add_class::<PyDSDFormat>()?;
+    m.add_class::<PyDSDMeta>()?;
+    m.add_class::<PyMetaPicture>()?;
+    m.add_class::<PyPlaybackTime>()?;
+    m.add("PlayerError", m.py().get_type::<PlayerError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_test_track;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn drives_the_player_from_asyncio() {
+        let path = write_test_track("ndsd_python_player.dsf", 2822400, 0.1);
+
+        Python::initialize();
+        Python::attach(|py| {
+            let module = pyo3::wrap_pymodule!(ndsdplayback)(py);
+            py.import("sys")?.getattr("modules")?.set_item("ndsdplayback", module)?;
+            let globals = PyDict::new(py);
+            globals.set_item("path", &path)?;
+            py.run(
+                cr#"
+import asyncio
+import ndsdplayback
+
+async def main():
+    player = ndsdplayback.create_player("null:4", preroll=0.0)
+    events = player.subscribe()
+    await player.load_new_track_async(path)
+    assert player.get_format_info().sampling_rate == 2822400
+    assert abs(player.duration().seconds - 0.1) < 1e-6
+    await player.start_async()
+    kinds = []
+    async for event in events:
+        kinds.append(event.kind)
+        if event.kind == "track_ended":
+            break
+    assert kinds[0] == "track_loaded" and "started" in kinds, kinds
+    await player.close_async()
+    assert [event async for event in events] == []
+    try:
+        player.start()
+        raise AssertionError("closed player started")
+    except ndsdplayback.PlayerError:
+        pass
+    try:
+        ndsdplayback.create_player("null", profile="fast")
+        raise AssertionError("unknown profile accepted")
+    except ValueError:
+        pass
+
+asyncio.run(main())
+"#,
+                Some(&globals),
+                None,
+            )
+        })
+        .unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+}
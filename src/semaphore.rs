@@ -19,6 +19,7 @@ impl Semaphore {
         }
     }
 
+    #[allow(clippy::collapsible_if)]
     pub fn acquire(&self) {
         loop {
             // Try fast path first
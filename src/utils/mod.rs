@@ -1 +1,3 @@
-pub mod bit_reverse_table;
\ No newline at end of file
+pub mod bit_reverse_table;
+pub mod silence;
+pub mod tracks;
@@ -0,0 +1,70 @@
+use crate::utils::bit_reverse_table::BIT_REVERSE_TABLE;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+
+///Idle pattern of msb first dsd, DACs output silence for it
+pub const DSD_SILENCE: u8 = 0x69;
+
+///Fills the buffer with the idle pattern in the bit order of the stream
+pub fn fill_silence(buffer: &mut [u8], lsb_first: bool) {
+    let pattern = if lsb_first { BIT_REVERSE_TABLE[DSD_SILENCE as usize] } else { DSD_SILENCE };
+    buffer.fill(pattern);
+}
+
+///Bytes per channel of silence lasting the time at the dsd rate
+pub fn silence_bytes(time: Duration, sampling_rate: u32) -> u64 {
+    (time.as_nanos() * sampling_rate as u128 / 8 / 1_000_000_000) as u64
+}
+
+///Silence the output has to play before the audio continues, in bytes per channel.
+/// Shared between the thread asking for it and the one filling the output
+#[derive(Debug, Default)]
+pub struct PendingSilence {
+    bytes: AtomicU64,
+}
+
+impl PendingSilence {
+    ///Extends the pending silence to at least bytes, requests do not add up
+    pub fn request(&self, bytes: u64) {
+        self.bytes.fetch_max(bytes, Relaxed);
+    }
+
+    ///Takes up to max bytes of the pending silence
+    pub fn take(&self, max: usize) -> usize {
+        let mut taken = 0;
+        let _ = self.bytes.fetch_update(Relaxed, Relaxed, |bytes| {
+            taken = bytes.min(max as u64);
+            Some(bytes - taken)
+        });
+        taken as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.load(Relaxed) == 0
+    }
+
+    pub fn clear(&self) {
+        self.bytes.store(0, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_silence_is_taken_in_chunks() {
+        let pending = PendingSilence::default();
+        pending.request(100);
+        pending.request(30);
+        assert_eq!(pending.take(64), 64);
+        assert_eq!(pending.take(64), 36);
+        assert!(pending.is_empty());
+
+        let mut buffer = [0u8; 4];
+        fill_silence(&mut buffer, true);
+        assert_eq!(buffer, [0x96; 4]);
+        assert_eq!(silence_bytes(Duration::from_millis(100), 2822400), 35280);
+    }
+}
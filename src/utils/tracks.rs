@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+
+pub fn is_dsd_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("dsf") || ext.eq_ignore_ascii_case("dff"))
+}
+
+///Files are taken as they are, directories are searched for dsf and dff files sorted by path
+pub fn collect_tracks(path: &Path, tracks: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        tracks.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || is_dsd_file(&entry) {
+            collect_tracks(&entry, tracks)?;
+        }
+    }
+    Ok(())
+}